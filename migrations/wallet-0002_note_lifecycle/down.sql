@@ -0,0 +1,4 @@
+ALTER TABLE notes DROP COLUMN lock_expiry_height;
+ALTER TABLE notes DROP COLUMN locked_by_tx_id;
+ALTER TABLE notes DROP COLUMN spend_height;
+ALTER TABLE notes DROP COLUMN mined_height;
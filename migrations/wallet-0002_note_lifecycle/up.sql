@@ -0,0 +1,4 @@
+ALTER TABLE notes ADD COLUMN mined_height INTEGER;
+ALTER TABLE notes ADD COLUMN spend_height INTEGER;
+ALTER TABLE notes ADD COLUMN locked_by_tx_id BINARY(32);
+ALTER TABLE notes ADD COLUMN lock_expiry_height INTEGER;
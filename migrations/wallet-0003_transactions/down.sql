@@ -0,0 +1,2 @@
+DROP TABLE transaction_values;
+DROP TABLE transactions;
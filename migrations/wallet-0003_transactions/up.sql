@@ -0,0 +1,18 @@
+CREATE TABLE transactions (
+    txid BINARY(32) PRIMARY KEY NOT NULL,
+    mined_height INTEGER,
+    block_hash TEXT,
+    expiry_height INTEGER NOT NULL,
+    fee BigInt,
+    memo_refs TEXT NOT NULL,
+    issuance TEXT NOT NULL,
+    burns TEXT NOT NULL
+);
+
+CREATE TABLE transaction_values (
+    txid BINARY(32) NOT NULL,
+    account INTEGER NOT NULL,
+    asset BINARY(32) NOT NULL,
+    value_change BigInt NOT NULL,
+    PRIMARY KEY (txid, account, asset)
+);
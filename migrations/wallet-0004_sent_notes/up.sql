@@ -0,0 +1,13 @@
+CREATE TABLE sent_notes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tx_id BINARY(32) NOT NULL,
+    action_index INTEGER NOT NULL,
+    sender_account INTEGER NOT NULL,
+    recipient_address BINARY(43) NOT NULL,
+    recipient_account INTEGER,
+    amount BigInt NOT NULL,
+    asset BINARY(32) NOT NULL,
+    memo BINARY(512) NOT NULL,
+    mined_height INTEGER NOT NULL,
+    UNIQUE (tx_id, action_index)
+);
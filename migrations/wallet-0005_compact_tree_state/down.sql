@@ -0,0 +1,19 @@
+-- The binary format cannot be converted back to JSON in SQL: only format 0
+-- rows survive, a wallet saved in format 1 rebuilds its state on the next sync.
+DROP TABLE IF EXISTS tree_marks;
+DROP TABLE IF EXISTS tree_checkpoints;
+DROP TABLE IF EXISTS tree_bridges;
+
+CREATE TABLE wallet_state_old (
+    id INTEGER PRIMARY KEY NOT NULL DEFAULT 1,
+    commitment_tree_json TEXT NOT NULL,
+    last_block_height INTEGER NOT NULL,
+    last_block_hash TEXT NOT NULL
+);
+
+INSERT INTO wallet_state_old (id, commitment_tree_json, last_block_height, last_block_hash)
+SELECT id, commitment_tree_json, last_block_height, last_block_hash FROM wallet_state
+WHERE format_version = 0;
+
+DROP TABLE wallet_state;
+ALTER TABLE wallet_state_old RENAME TO wallet_state;
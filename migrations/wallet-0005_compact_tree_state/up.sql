@@ -0,0 +1,35 @@
+-- Store the commitment tree in parts, in a binary encoding, instead of one
+-- JSON document. Existing rows keep their JSON tree as format 0 and are
+-- converted by the next save.
+CREATE TABLE wallet_state_new (
+    id INTEGER PRIMARY KEY NOT NULL DEFAULT 1,
+    format_version INTEGER NOT NULL,
+    commitment_tree_json TEXT,
+    current_bridge BLOB,
+    max_checkpoints INTEGER NOT NULL,
+    last_block_height INTEGER NOT NULL,
+    last_block_hash TEXT NOT NULL
+);
+
+INSERT INTO wallet_state_new (id, format_version, commitment_tree_json, max_checkpoints,
+                              last_block_height, last_block_hash)
+SELECT id, 0, commitment_tree_json, 0, last_block_height, last_block_hash FROM wallet_state;
+
+DROP TABLE wallet_state;
+ALTER TABLE wallet_state_new RENAME TO wallet_state;
+
+CREATE TABLE tree_bridges (
+    idx INTEGER PRIMARY KEY NOT NULL,
+    digest BINARY(32) NOT NULL,
+    data BLOB NOT NULL
+);
+
+CREATE TABLE tree_checkpoints (
+    id INTEGER PRIMARY KEY NOT NULL,
+    data BLOB NOT NULL
+);
+
+CREATE TABLE tree_marks (
+    position BigInt PRIMARY KEY NOT NULL,
+    bridge_idx INTEGER NOT NULL
+);
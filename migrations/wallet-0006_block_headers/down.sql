@@ -0,0 +1,5 @@
+ALTER TABLE block_data DROP COLUMN bits;
+ALTER TABLE block_data DROP COLUMN time;
+ALTER TABLE block_data DROP COLUMN block_commitments;
+ALTER TABLE block_data DROP COLUMN merkle_root;
+ALTER TABLE block_data DROP COLUMN prev_hash;
@@ -0,0 +1,8 @@
+-- Header fields of synced blocks, in the byte order `getblock` displays
+-- them. Null for blocks stored without a header (e.g. the block below the
+-- wallet birthday).
+ALTER TABLE block_data ADD COLUMN prev_hash TEXT;
+ALTER TABLE block_data ADD COLUMN merkle_root TEXT;
+ALTER TABLE block_data ADD COLUMN block_commitments TEXT;
+ALTER TABLE block_data ADD COLUMN time BigInt;
+ALTER TABLE block_data ADD COLUMN bits BigInt;
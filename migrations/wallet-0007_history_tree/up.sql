@@ -0,0 +1,7 @@
+-- Peaks of the local ZIP-221 chain history tree, left to right. Each row holds
+-- the serialized node data of one peak and the consensus branch it belongs to.
+CREATE TABLE history_tree_peaks (
+    idx INTEGER PRIMARY KEY NOT NULL,
+    branch_id BigInt NOT NULL,
+    data BLOB NOT NULL
+);
@@ -0,0 +1 @@
+DROP TABLE transparent_utxos;
@@ -0,0 +1,18 @@
+-- Transparent outputs paying an address of a wallet account or the miner key,
+-- discovered during sync. Spent and locked outputs are tracked like notes.
+-- The miner's outputs belong to no wallet account, so their `account` is NULL.
+CREATE TABLE transparent_utxos (
+    txid BINARY(32) NOT NULL,
+    output_index INTEGER NOT NULL,
+    account INTEGER NULL,
+    is_miner BOOLEAN NOT NULL,
+    amount BigInt NOT NULL,
+    is_coinbase BOOLEAN NOT NULL,
+    mined_height INTEGER NOT NULL,
+    spend_tx_id BINARY(32),
+    spend_height INTEGER,
+    locked_by_tx_id BINARY(32),
+    lock_expiry_height INTEGER,
+    PRIMARY KEY (txid, output_index),
+    CHECK ((account IS NULL) = is_miner)
+);
@@ -0,0 +1,2 @@
+DROP TABLE sapling_tree_state;
+DROP TABLE sapling_notes;
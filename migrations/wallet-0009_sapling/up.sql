@@ -0,0 +1,25 @@
+-- Sapling notes received by wallet accounts, with the incremental witness of
+-- their commitment, and the frontier of the Sapling note commitment tree at
+-- the sync position.
+CREATE TABLE sapling_notes (
+    txid BINARY(32) NOT NULL,
+    output_index INTEGER NOT NULL,
+    account INTEGER NOT NULL,
+    amount BigInt NOT NULL,
+    recipient BINARY(43) NOT NULL,
+    rseed BINARY(32) NOT NULL,
+    nullifier BINARY(32) NOT NULL,
+    memo BINARY(512) NOT NULL,
+    witness BLOB NOT NULL,
+    mined_height INTEGER NOT NULL,
+    spend_tx_id BINARY(32),
+    spend_height INTEGER,
+    locked_by_tx_id BINARY(32),
+    lock_expiry_height INTEGER,
+    PRIMARY KEY (txid, output_index)
+);
+
+CREATE TABLE sapling_tree_state (
+    id INTEGER PRIMARY KEY NOT NULL DEFAULT 1,
+    tree BLOB NOT NULL
+);
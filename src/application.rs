@@ -1,6 +1,6 @@
 //! ZcashTxTool Abscissa Application
 
-use crate::{commands::EntryPoint, config::AppConfig};
+use crate::{commands::EntryPoint, config::AppConfig, output};
 use abscissa_core::{
     application::{self, AppCell},
     config::{self, CfgCell},
@@ -58,7 +58,17 @@ impl Application for ZcashTxToolApp {
     /// If you would like to add additional components to your application
     /// beyond the default ones provided by the framework, this is the place
     /// to do so.
+    ///
+    /// This is the first callback with the parsed command, so the output mode
+    /// is set here, before anything is printed.
     fn register_components(&mut self, command: &Self::Cmd) -> Result<(), FrameworkError> {
+        output::set_mode(command.output_mode());
+        crate::print_info(&format!(
+            "Git tag: {}",
+            option_env!("GIT_TAG").unwrap_or("none")
+        ));
+        crate::print_info(&format!("Git commit: {}", env!("GIT_COMMIT")));
+
         let framework_components = self.framework_components(command)?;
         let mut app_components = self.state.components_mut();
         app_components.register(framework_components)
@@ -77,9 +87,13 @@ impl Application for ZcashTxToolApp {
         Ok(())
     }
 
-    /// Get tracing configuration from command-line options
+    /// Get tracing configuration from command-line options. Logs are
+    /// disabled in JSON output mode, as they would be mixed with the results
+    /// on stdout.
     fn tracing_config(&self, command: &EntryPoint) -> trace::Config {
-        if command.verbose {
+        if output::is_json() {
+            trace::Config::from("off".to_owned())
+        } else if command.verbose {
             trace::Config::verbose()
         } else {
             trace::Config::default()
@@ -12,24 +12,43 @@
 
 mod clean;
 mod get_block_data;
+mod history;
+mod load;
+mod prune_block_data;
+mod test_adversarial;
 mod test_balances;
 mod test_issue_one;
 mod test_orchard;
 mod test_orchard_zsa;
 mod test_persistence_part1;
 mod test_persistence_part2;
+mod test_random;
+mod test_sapling;
 mod test_three_party;
+mod test_transparent;
 
 use crate::commands::clean::CleanCmd;
 use crate::commands::get_block_data::GetBlockDataCmd;
+use crate::commands::history::HistoryCmd;
+use crate::commands::load::LoadCmd;
+use crate::commands::prune_block_data::PruneBlockDataCmd;
+use crate::commands::test_adversarial::TestAdversarialCmd;
 use crate::commands::test_issue_one::TestIssueOneCmd;
 use crate::commands::test_orchard::TestOrchardCmd;
 use crate::commands::test_orchard_zsa::TestOrchardZSACmd;
 use crate::commands::test_persistence_part1::TestPersistencePart1Cmd;
 use crate::commands::test_persistence_part2::TestPersistencePart2Cmd;
+use crate::commands::test_random::TestRandomCmd;
+use crate::commands::test_sapling::TestSaplingCmd;
 use crate::commands::test_three_party::TestThreePartyCmd;
+use crate::commands::test_transparent::TestTransparentCmd;
+use crate::components::test_vectors;
 use crate::config::AppConfig;
+use crate::output::{self, OutputMode};
+use crate::report;
 use abscissa_core::{Command, Configurable, FrameworkError, Runnable};
+use serde_json::json;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
 
 /// Application Configuration Filename
@@ -44,8 +63,15 @@ pub enum AppCmd {
     TestIssueOne(TestIssueOneCmd),
     TestPersistencePart1(TestPersistencePart1Cmd),
     TestPersistencePart2(TestPersistencePart2Cmd),
+    TestTransparent(TestTransparentCmd),
+    TestSapling(TestSaplingCmd),
+    TestAdversarial(TestAdversarialCmd),
+    TestRandom(TestRandomCmd),
     Clean(CleanCmd),
     GetBlockData(GetBlockDataCmd),
+    History(HistoryCmd),
+    Load(LoadCmd),
+    PruneBlockData(PruneBlockDataCmd),
 }
 
 /// Entry point for the application. It needs to be a struct to allow using subcommands!
@@ -62,11 +88,91 @@ pub struct EntryPoint {
     /// Use the specified config file
     #[arg(short, long)]
     pub config: Option<String>,
+
+    /// Report results as human-readable logs or as JSON lines on stdout
+    #[arg(long, value_enum, global = true, default_value_t)]
+    pub output: OutputMode,
+
+    /// Write a JSON and a JUnit XML report of the command's steps to this directory
+    #[arg(long, global = true)]
+    pub report_dir: Option<PathBuf>,
+
+    /// Export every submitted block and its transactions as test vectors to this directory
+    #[arg(long, global = true)]
+    pub export_dir: Option<PathBuf>,
+}
+
+impl AppCmd {
+    /// The name of the subcommand on the command line.
+    fn name(&self) -> &'static str {
+        match self {
+            AppCmd::TestOrchard(_) => "test-orchard",
+            AppCmd::TestOrchardZSA(_) => "test-orchard-zsa",
+            AppCmd::TestThreeParty(_) => "test-three-party",
+            AppCmd::TestIssueOne(_) => "test-issue-one",
+            AppCmd::TestPersistencePart1(_) => "test-persistence-part1",
+            AppCmd::TestPersistencePart2(_) => "test-persistence-part2",
+            AppCmd::TestTransparent(_) => "test-transparent",
+            AppCmd::TestSapling(_) => "test-sapling",
+            AppCmd::TestAdversarial(_) => "test-adversarial",
+            AppCmd::TestRandom(_) => "test-random",
+            AppCmd::Clean(_) => "clean",
+            AppCmd::GetBlockData(_) => "get-block-data",
+            AppCmd::History(_) => "history",
+            AppCmd::Load(_) => "load",
+            AppCmd::PruneBlockData(_) => "prune-block-data",
+        }
+    }
+
+    /// Whether the command prints nothing but a JSON document of its own.
+    fn prints_json(&self) -> bool {
+        matches!(self, AppCmd::GetBlockData(_) | AppCmd::History(_))
+    }
+}
+
+impl EntryPoint {
+    /// The output mode of the run: JSON for commands that only print JSON,
+    /// which keeps their stdout clean.
+    pub fn output_mode(&self) -> OutputMode {
+        if self.cmd.prints_json() {
+            OutputMode::Json
+        } else {
+            self.output
+        }
+    }
 }
 
 impl Runnable for EntryPoint {
+    /// Run the subcommand. In JSON mode its outcome is reported as a final
+    /// `result` event, including the panic message of a failed run, and with
+    /// `--report-dir` the report of its steps is written, failed or not.
+    /// With `--export-dir` the blocks it submits are exported as test vectors.
     fn run(&self) {
-        self.cmd.run()
+        if let Some(dir) = &self.export_dir {
+            test_vectors::set_dir(dir.clone());
+        }
+        if self.cmd.prints_json() {
+            self.cmd.run();
+            return;
+        }
+        if self.report_dir.is_some() {
+            report::start(self.cmd.name());
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.cmd.run()));
+        let error = result.as_ref().err().map(|e| output::panic_message(&**e));
+
+        match &error {
+            None => output::emit("result", &json!({ "status": "passed" })),
+            Some(error) => output::emit("result", &json!({ "status": "failed", "error": error })),
+        }
+        if let (Some(dir), Some(report)) = (&self.report_dir, report::finish(error)) {
+            report
+                .write(dir)
+                .unwrap_or_else(|e| panic!("failed to write report to {}: {e}", dir.display()));
+        }
+        if let Err(e) = result {
+            panic::resume_unwind(e);
+        }
     }
 }
 
@@ -15,7 +15,7 @@ impl Runnable for CleanCmd {
     fn run(&self) {
         let config = APP.config();
         let mut c = db::open();
-        let mut wallet = Wallet::new(&mut c, &config.wallet.seed_phrase);
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
 
         wallet.reset(&mut c);
     }
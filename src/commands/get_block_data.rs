@@ -1,70 +1,291 @@
 //! Subcommand to query block hashes from the local db.
 //!
-//! During sync the wallet records the hash of every block it processes.
-//! This command prints the hash (and the previous block's hash) for a
-//! given height, or for the most recently synced block when no height is
-//! specified.
+//! During sync the wallet records the hash and header of every block it
+//! processes. This command prints them for a given height, a given hash, or a
+//! range of heights, or for the most recently synced block when nothing is
+//! specified. Header fields are null for blocks stored without a header.
+//!
+//! With `--wallet` each block also lists the wallet transactions mined in it
+//! and the wallet notes it created and spent. Ranges can be printed as a JSON
+//! array, as JSON lines or as CSV, which keeps outputs of different runs easy
+//! to diff.
 //!
 //! Usage:
-//!   tx_tool get-block-data          # last synced block
-//!   tx_tool get-block-data <HEIGHT> # specific block
+//!   tx_tool get-block-data                          # last synced block
+//!   tx_tool get-block-data <HEIGHT>                 # specific block
+//!   tx_tool get-block-data --hash <HASH>            # block with this hash
+//!   tx_tool get-block-data --from 10 --to 20        # range of blocks
+//!   tx_tool get-block-data --from 10 --format csv   # range up to the last block, as CSV
+//!   tx_tool get-block-data <HEIGHT> --wallet        # include wallet txs and notes
 
 use abscissa_core::{Command, Runnable};
+use diesel::SqliteConnection;
 use serde::Serialize;
+use zcash_primitives::transaction::TxId;
 
-use crate::components::{block_data, db};
+use crate::components::block_data::{self, StoredBlock};
+use crate::components::persistence::model::NoteData;
+use crate::components::persistence::sqlite as notes_db;
+use crate::components::{db, history};
 
 #[derive(Serialize)]
 struct BlockDataResult {
     block_height: Option<u32>,
     hash: Option<String>,
     prev_hash: Option<String>,
+    merkle_root: Option<String>,
+    block_commitments: Option<String>,
+    time: Option<i64>,
+    bits: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wallet: Option<WalletBlockData>,
     error: Option<String>,
 }
 
+/// The wallet's view of a block.
+#[derive(Serialize)]
+struct WalletBlockData {
+    /// Recorded wallet transactions mined in the block.
+    transactions: Vec<String>,
+    /// Wallet notes created by the block's transactions.
+    received_notes: Vec<NoteRef>,
+    /// Wallet notes spent by the block's transactions.
+    spent_notes: Vec<NoteRef>,
+}
+
+#[derive(Serialize)]
+struct NoteRef {
+    txid: String,
+    action_index: i32,
+    asset: String,
+    amount: i64,
+}
+
+impl BlockDataResult {
+    fn error(block_height: Option<u32>, error: String) -> Self {
+        Self {
+            block_height,
+            hash: None,
+            prev_hash: None,
+            merkle_root: None,
+            block_commitments: None,
+            time: None,
+            bits: None,
+            wallet: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Output format of `get-block-data`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Pretty-printed JSON: an object for one block, an array for a range.
+    #[default]
+    Json,
+    /// One compact JSON object per line.
+    Jsonl,
+    /// CSV with a header line.
+    Csv,
+}
+
 /// Query block hash data from the local db.
 #[derive(clap::Parser, Command, Debug)]
 pub struct GetBlockDataCmd {
     /// Block height to retrieve (default: last block).
+    #[arg(conflicts_with_all = ["hash", "from", "to"])]
     pub block_height: Option<u32>,
+
+    /// Retrieve the block with this hash (hex, as reported by `getblock`).
+    #[arg(long, conflicts_with_all = ["from", "to"])]
+    pub hash: Option<String>,
+
+    /// First height of a range of blocks.
+    #[arg(long)]
+    pub from: Option<u32>,
+
+    /// Last height of a range of blocks (default: last block).
+    #[arg(long, requires = "from")]
+    pub to: Option<u32>,
+
+    /// Also list the wallet transactions and notes of each block.
+    #[arg(long)]
+    pub wallet: bool,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t)]
+    pub format: OutputFormat,
 }
 
 impl Runnable for GetBlockDataCmd {
     fn run(&self) {
         let mut c = db::open();
 
-        let height = self
-            .block_height
-            .or_else(|| block_data::last_height(&mut c));
-
-        let result = match height {
-            Some(height) => match block_data::get_hash(&mut c, height) {
-                Some(hash) => {
-                    let prev_hash = height
-                        .checked_sub(1)
-                        .and_then(|h| block_data::get_hash(&mut c, h));
-                    BlockDataResult {
-                        block_height: Some(height),
-                        hash: Some(hash),
-                        prev_hash,
-                        error: None,
-                    }
-                }
-                None => BlockDataResult {
-                    block_height: Some(height),
-                    hash: None,
-                    prev_hash: None,
-                    error: Some(format!("Block at height {} not found", height)),
-                },
+        if let Some(from) = self.from {
+            let to = self
+                .to
+                .or_else(|| block_data::last_height(&mut c))
+                .unwrap_or(from);
+            let results: Vec<BlockDataResult> = block_data::range(&mut c, from, to)
+                .into_iter()
+                .map(|block| self.block_result(&mut c, block))
+                .collect();
+            self.print(&results, true);
+            return;
+        }
+
+        let result = match &self.hash {
+            Some(hash) => match block_data::find_by_hash(&mut c, hash) {
+                Some(block) => self.block_result(&mut c, block),
+                None => BlockDataResult::error(None, format!("Block {} not found", hash)),
             },
-            None => BlockDataResult {
-                block_height: None,
-                hash: None,
-                prev_hash: None,
-                error: Some("No blocks found in local db".to_string()),
+            None => match self
+                .block_height
+                .or_else(|| block_data::last_height(&mut c))
+            {
+                Some(height) => match block_data::get(&mut c, height) {
+                    Some(block) => self.block_result(&mut c, block),
+                    None => BlockDataResult::error(
+                        Some(height),
+                        format!("Block at height {} not found", height),
+                    ),
+                },
+                None => BlockDataResult::error(None, "No blocks found in local db".to_string()),
             },
         };
+        self.print(&[result], false);
+    }
+}
+
+impl GetBlockDataCmd {
+    fn block_result(&self, c: &mut SqliteConnection, block: StoredBlock) -> BlockDataResult {
+        let height = block.height as u32;
+        // Blocks stored without a header fall back to the hash of the stored
+        // block below them.
+        let prev_hash = block.prev_hash.or_else(|| {
+            height
+                .checked_sub(1)
+                .and_then(|h| block_data::get_hash(c, h))
+        });
+        BlockDataResult {
+            block_height: Some(height),
+            hash: Some(block.hash),
+            prev_hash,
+            merkle_root: block.merkle_root,
+            block_commitments: block.block_commitments,
+            time: block.time,
+            bits: block.bits.map(|b| format!("{:08x}", b)),
+            wallet: self.wallet.then(|| wallet_block_data(c, height)),
+            error: None,
+        }
+    }
+
+    fn print(&self, results: &[BlockDataResult], range: bool) {
+        match self.format {
+            OutputFormat::Json if range => {
+                println!("{}", serde_json::to_string_pretty(results).unwrap())
+            }
+            OutputFormat::Json => {
+                for result in results {
+                    println!("{}", serde_json::to_string_pretty(result).unwrap());
+                }
+            }
+            OutputFormat::Jsonl => {
+                for result in results {
+                    println!("{}", serde_json::to_string(result).unwrap());
+                }
+            }
+            OutputFormat::Csv => {
+                let mut header = vec![
+                    "height",
+                    "hash",
+                    "prev_hash",
+                    "merkle_root",
+                    "block_commitments",
+                    "time",
+                    "bits",
+                ];
+                if self.wallet {
+                    header.extend(["transactions", "received_notes", "spent_notes"]);
+                }
+                header.push("error");
+                println!("{}", header.join(","));
+                for result in results {
+                    println!("{}", csv_row(result, self.wallet));
+                }
+            }
+        }
+    }
+}
+
+fn wallet_block_data(c: &mut SqliteConnection, height: u32) -> WalletBlockData {
+    WalletBlockData {
+        transactions: history::mined_at(c, height)
+            .into_iter()
+            .map(|r| txid_string(&r.txid))
+            .collect(),
+        received_notes: notes_db::find_notes_mined_at(c, height)
+            .iter()
+            .map(note_ref)
+            .collect(),
+        spent_notes: notes_db::find_notes_spent_at(c, height)
+            .iter()
+            .map(note_ref)
+            .collect(),
+    }
+}
+
+fn note_ref(note: &NoteData) -> NoteRef {
+    NoteRef {
+        txid: txid_string(&note.tx_id),
+        action_index: note.action_index,
+        asset: hex::encode(&note.asset),
+        amount: note.amount,
+    }
+}
+
+fn txid_string(txid: &[u8]) -> String {
+    TxId::from_bytes(txid.try_into().unwrap()).to_string()
+}
+
+/// One CSV line for `result`. Lists are `;`-separated, notes are written as
+/// `txid:action_index`. None of the values need quoting except the error.
+fn csv_row(result: &BlockDataResult, wallet_columns: bool) -> String {
+    fn opt<T: ToString>(value: &Option<T>) -> String {
+        value.as_ref().map(T::to_string).unwrap_or_default()
+    }
+    fn notes(notes: &[NoteRef]) -> String {
+        notes
+            .iter()
+            .map(|n| format!("{}:{}", n.txid, n.action_index))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
 
-        println!("{}", serde_json::to_string_pretty(&result).unwrap());
+    let mut fields = vec![
+        opt(&result.block_height),
+        opt(&result.hash),
+        opt(&result.prev_hash),
+        opt(&result.merkle_root),
+        opt(&result.block_commitments),
+        opt(&result.time),
+        opt(&result.bits),
+    ];
+    match &result.wallet {
+        Some(wallet) => {
+            fields.push(wallet.transactions.join(";"));
+            fields.push(notes(&wallet.received_notes));
+            fields.push(notes(&wallet.spent_notes));
+        }
+        None if wallet_columns => fields.extend([String::new(), String::new(), String::new()]),
+        None => {}
     }
+    fields.push(
+        result
+            .error
+            .as_ref()
+            .map(|e| format!("\"{}\"", e.replace('"', "\"\"")))
+            .unwrap_or_default(),
+    );
+    fields.join(",")
 }
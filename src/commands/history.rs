@@ -0,0 +1,118 @@
+//! Subcommand to list the wallet's transaction history from the local db.
+//!
+//! Transactions are recorded when the wallet builds them and when they are
+//! seen in a synced block. Each entry carries the net value change per
+//! account and asset, the fee, any issuance or burn contents, and the outputs
+//! recovered with the wallet's outgoing viewing keys.
+//!
+//! Usage:
+//!   tx_tool history                       # all transactions
+//!   tx_tool history --account 1           # transactions touching account 1
+//!   tx_tool history --asset <ASSET_HEX>   # value changes of a single asset
+
+use abscissa_core::{Command, Runnable};
+use serde::Serialize;
+use serde_json::Value;
+use zcash_primitives::transaction::TxId;
+
+use crate::components::persistence::sqlite as notes_db;
+use crate::components::{db, history};
+
+#[derive(Serialize)]
+struct ValueChange {
+    account: i32,
+    asset: String,
+    value_change: i64,
+}
+
+#[derive(Serialize)]
+struct SentOutput {
+    action_index: i32,
+    sender_account: i32,
+    recipient: String,
+    recipient_account: Option<i32>,
+    asset: String,
+    amount: i64,
+    /// Hex-encoded memo, absent for the empty memo.
+    memo: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HistoryEntry {
+    txid: String,
+    status: &'static str,
+    mined_height: Option<i32>,
+    block_hash: Option<String>,
+    expiry_height: i32,
+    fee: Option<i64>,
+    values: Vec<ValueChange>,
+    sent: Vec<SentOutput>,
+    memo_refs: Value,
+    issuance: Value,
+    burns: Value,
+}
+
+/// List the transaction history of the wallet.
+#[derive(clap::Parser, Command, Debug)]
+pub struct HistoryCmd {
+    /// Only list transactions changing the balance of this account.
+    #[arg(long)]
+    pub account: Option<u32>,
+
+    /// Only list value changes of this asset (hex-encoded asset base).
+    #[arg(long)]
+    pub asset: Option<String>,
+}
+
+impl Runnable for HistoryCmd {
+    fn run(&self) {
+        let asset = self.asset.as_ref().map(|a| {
+            hex::decode(a)
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .expect("asset must be 32 hex-encoded bytes")
+        });
+
+        let mut c = db::open();
+        let entries: Vec<HistoryEntry> = history::list(&mut c, self.account, asset)
+            .into_iter()
+            .map(|(record, values)| HistoryEntry {
+                sent: notes_db::find_sent_notes_for_tx(&mut c, &record.txid)
+                    .into_iter()
+                    .map(|n| SentOutput {
+                        action_index: n.action_index,
+                        sender_account: n.sender_account,
+                        recipient: hex::encode(n.recipient_address),
+                        recipient_account: n.recipient_account,
+                        asset: hex::encode(n.asset),
+                        amount: n.amount,
+                        memo: history::memo_hex(&n.memo),
+                    })
+                    .collect(),
+                txid: TxId::from_bytes(record.txid.as_slice().try_into().unwrap()).to_string(),
+                status: if record.mined_height.is_some() {
+                    "mined"
+                } else {
+                    "pending"
+                },
+                mined_height: record.mined_height,
+                block_hash: record.block_hash,
+                expiry_height: record.expiry_height,
+                fee: record.fee,
+                values: values
+                    .into_iter()
+                    .map(|v| ValueChange {
+                        account: v.account,
+                        asset: hex::encode(v.asset),
+                        value_change: v.value_change,
+                    })
+                    .collect(),
+                memo_refs: serde_json::from_str(&record.memo_refs).unwrap(),
+                issuance: serde_json::from_str(&record.issuance).unwrap(),
+                burns: serde_json::from_str(&record.burns).unwrap(),
+            })
+            .collect();
+
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+    }
+}
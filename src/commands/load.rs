@@ -0,0 +1,307 @@
+//! Subcommand to generate sustained OrchardZSA traffic.
+//!
+//! Issues a set of fresh assets and then keeps filling blocks with issues,
+//! transfers and burns of random amounts between the given number of
+//! accounts. After every block it logs the block size, the number of Orchard
+//! actions, the time spent building transactions and the running totals,
+//! including how many blocks the node rejected.
+//!
+//! Usage:
+//!   tx_tool load                                         # run until stopped
+//!   tx_tool load --accounts 8 --assets 3 --txs-per-block 20 --blocks 50
+
+use std::time::{Duration, Instant};
+
+use abscissa_core::{Command, Runnable};
+use diesel::SqliteConnection;
+use nonempty::NonEmpty;
+use orchard::issuance::compute_asset_desc_hash;
+use orchard::keys::Scope::External;
+use orchard::note::AssetBase;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde_json::json;
+use zcash_primitives::transaction::{OrchardBundle, Transaction};
+
+use crate::commands::test_balances::{BurnInfo, TransactionCreator, TransferInfo};
+use crate::components::db;
+use crate::components::rpc_client::reqwest::ReqwestRpcClient;
+use crate::components::transactions::{
+    assemble_block, create_issue_transaction, submit_block, sync, sync_from_height, BlockAssembly,
+};
+use crate::components::wallet::Wallet;
+use crate::output;
+use crate::prelude::*;
+
+/// The account holding the issuance key.
+const ISSUER_ACCOUNT: usize = 0;
+/// Amount of every asset issued to the issuer before the load starts.
+const INITIAL_SUPPLY: u64 = 1_000_000;
+/// Upper bound of randomly issued, transferred and burnt amounts.
+const MAX_AMOUNT: u64 = 1_000;
+
+/// Generate blocks full of OrchardZSA issues, transfers and burns.
+#[derive(clap::Parser, Command, Debug)]
+pub struct LoadCmd {
+    /// Number of accounts sending and receiving assets.
+    #[arg(long, default_value_t = 4)]
+    pub accounts: usize,
+
+    /// Number of assets issued for the run.
+    #[arg(long, default_value_t = 2)]
+    pub assets: usize,
+
+    /// Number of transactions put in every block.
+    #[arg(long, default_value_t = 10)]
+    pub txs_per_block: usize,
+
+    /// Stop after this many blocks instead of running until interrupted.
+    #[arg(long)]
+    pub blocks: Option<u32>,
+}
+
+/// Running totals of a load run.
+#[derive(Default)]
+struct LoadStats {
+    blocks: u32,
+    rejected_blocks: u32,
+    txs: usize,
+    rejected_txs: usize,
+    actions: usize,
+    bytes: usize,
+    build_time: Duration,
+}
+
+impl LoadStats {
+    fn report(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        info!(
+            "Totals: {} blocks ({} rejected), {} txs ({} rejected), {} actions, {} bytes, \
+             {:.2} txs/s, {:.1} ms average build time",
+            self.blocks,
+            self.rejected_blocks,
+            self.txs,
+            self.rejected_txs,
+            self.actions,
+            self.bytes,
+            (self.txs - self.rejected_txs) as f64 / secs,
+            self.build_time.as_secs_f64() * 1000.0 / self.txs.max(1) as f64,
+        );
+        output::emit(
+            "load_totals",
+            &json!({
+                "blocks": self.blocks,
+                "rejected_blocks": self.rejected_blocks,
+                "txs": self.txs,
+                "rejected_txs": self.rejected_txs,
+                "actions": self.actions,
+                "bytes": self.bytes,
+                "elapsed_ms": elapsed.as_millis() as u64,
+                "build_time_ms": self.build_time.as_millis() as u64,
+            }),
+        );
+    }
+}
+
+/// One randomly chosen operation.
+enum LoadTx {
+    Issue { recipient: usize, amount: u64 },
+    Transfer(TransferInfo),
+    Burn(BurnInfo),
+}
+
+impl Runnable for LoadCmd {
+    /// Run the `load` subcommand.
+    fn run(&self) {
+        assert!(self.accounts >= 2, "at least 2 accounts are needed");
+        assert!(self.assets >= 1, "at least 1 asset is needed");
+
+        let config = APP.config();
+        let mut c = db::open();
+        let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
+        let mut rng = rand::thread_rng();
+
+        for account in 0..self.accounts {
+            wallet.address_for_account(account, External);
+        }
+        sync_from_height(
+            &mut c,
+            config.chain.nu7_activation_height,
+            &mut wallet,
+            &mut rpc_client,
+        );
+
+        // --------------------- Issue the assets ---------------------
+
+        let issuer = wallet.address_for_account(ISSUER_ACCOUNT, External);
+        let mut assets = Vec::with_capacity(self.assets);
+        let mut first_issues = Vec::with_capacity(self.assets);
+        for _ in 0..self.assets {
+            let desc = format!("LOAD-{:016x}", rand::random::<u64>());
+            let desc_hash =
+                compute_asset_desc_hash(&NonEmpty::from_slice(desc.as_bytes()).unwrap());
+            let (tx, asset) = create_issue_transaction(
+                issuer,
+                INITIAL_SUPPLY,
+                desc_hash,
+                true,
+                &rpc_client,
+                &mut wallet,
+            );
+            assets.push((asset, desc_hash));
+            first_issues.push(tx);
+        }
+
+        let start = Instant::now();
+        let mut stats = LoadStats::default();
+        self.mine(
+            &mut c,
+            &mut wallet,
+            &mut rpc_client,
+            first_issues,
+            &mut stats,
+        );
+
+        // --------------------- Generate load ---------------------
+
+        let mut mined = 0;
+        while self.blocks.is_none_or(|blocks| mined < blocks) {
+            let build_start = Instant::now();
+            let mut txs = Vec::with_capacity(self.txs_per_block);
+            for _ in 0..self.txs_per_block {
+                let &(asset, desc_hash) = assets.choose(&mut rng).unwrap();
+                let tx = match self.choose(&mut c, &wallet, &mut rng, asset) {
+                    LoadTx::Issue { recipient, amount } => {
+                        let recipient = wallet.address_for_account(recipient, External);
+                        let (tx, _) = create_issue_transaction(
+                            recipient,
+                            amount,
+                            desc_hash,
+                            false,
+                            &rpc_client,
+                            &mut wallet,
+                        );
+                        tx
+                    }
+                    LoadTx::Transfer(info) => info.create_tx(&mut c, &rpc_client, &mut wallet),
+                    LoadTx::Burn(info) => info.create_tx(&mut c, &rpc_client, &mut wallet),
+                };
+                txs.push(tx);
+            }
+            let build_time = build_start.elapsed();
+            stats.build_time += build_time;
+            info!(
+                "Built {} transactions in {:.1} s",
+                txs.len(),
+                build_time.as_secs_f64()
+            );
+
+            self.mine(&mut c, &mut wallet, &mut rpc_client, txs, &mut stats);
+            stats.report(start.elapsed());
+            mined += 1;
+        }
+    }
+}
+
+impl LoadCmd {
+    /// Pick a random operation on `asset`: mostly transfers, and issues and
+    /// burns in equal parts. Transfers and burns only spend notes that are not
+    /// locked by a transaction of the current block.
+    fn choose(
+        &self,
+        c: &mut SqliteConnection,
+        wallet: &Wallet,
+        rng: &mut impl Rng,
+        asset: AssetBase,
+    ) -> LoadTx {
+        let funded: Vec<(usize, u64)> = (0..self.accounts)
+            .map(|account| (account, wallet.spendable_balance(c, account, asset)))
+            .filter(|(_, balance)| *balance > 0)
+            .collect();
+        let roll = rng.gen_range(0..5);
+        let Some(&(from, balance)) = funded.choose(rng).filter(|_| roll != 0) else {
+            return LoadTx::Issue {
+                recipient: rng.gen_range(0..self.accounts),
+                amount: rng.gen_range(1..=MAX_AMOUNT),
+            };
+        };
+        let amount = rng.gen_range(1..=balance.min(MAX_AMOUNT));
+        if roll == 1 {
+            LoadTx::Burn(BurnInfo::new(from, asset, amount))
+        } else {
+            let to = (from + rng.gen_range(1..self.accounts)) % self.accounts;
+            LoadTx::Transfer(TransferInfo::new(from, to, asset, amount))
+        }
+    }
+
+    /// Mine `txs` in one block, log its size and sync the wallet.
+    fn mine(
+        &self,
+        c: &mut SqliteConnection,
+        wallet: &mut Wallet,
+        rpc_client: &mut ReqwestRpcClient,
+        txs: Vec<Transaction>,
+        stats: &mut LoadStats,
+    ) {
+        let tx_count = txs.len();
+        let actions: usize = txs.iter().map(orchard_actions).sum();
+        let assembly = BlockAssembly::for_wallet(wallet);
+        let block = assemble_block(rpc_client, txs, assembly).expect("block assembled");
+        let mut bytes = vec![];
+        block.proposal.write(&mut bytes).unwrap();
+
+        let height = block.height;
+
+        stats.blocks += 1;
+        stats.txs += tx_count;
+        // The node answers blocks it did not connect, e.g. "duplicate" or
+        // "inconclusive", with a result string instead of an error.
+        let rejection = match submit_block(rpc_client, block, assembly) {
+            Ok(None) => None,
+            Ok(Some(result)) => Some(result),
+            Err(e) => Some(e.to_string()),
+        };
+        output::emit(
+            "load_block",
+            &json!({
+                "height": height,
+                "txs": tx_count,
+                "actions": actions,
+                "bytes": bytes.len(),
+                "accepted": rejection.is_none(),
+                "error": rejection,
+            }),
+        );
+        match rejection {
+            None => {
+                stats.actions += actions;
+                stats.bytes += bytes.len();
+                info!(
+                    "Block {}: {} txs, {} actions, {} bytes",
+                    height,
+                    tx_count,
+                    actions,
+                    bytes.len()
+                );
+            }
+            Some(reason) => {
+                stats.rejected_blocks += 1;
+                stats.rejected_txs += tx_count;
+                info!(
+                    "Block {} with {} txs rejected: {}",
+                    height, tx_count, reason
+                );
+            }
+        }
+        sync(c, wallet, rpc_client);
+    }
+}
+
+fn orchard_actions(tx: &Transaction) -> usize {
+    match tx.orchard_bundle() {
+        Some(OrchardBundle::OrchardVanilla(b)) => b.actions().len(),
+        Some(OrchardBundle::OrchardZSA(b)) => b.actions().len(),
+        None => 0,
+    }
+}
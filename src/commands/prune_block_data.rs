@@ -0,0 +1,60 @@
+//! `prune-block-data` - drops old block hashes from the local db
+//!
+//! Keeps the most recent blocks, which always include the stored head used
+//! for reorg detection, and older blocks at checkpoint heights. Without
+//! arguments the retention policy from the `[wallet]` config section is used.
+//!
+//! Usage:
+//!   tx_tool prune-block-data                       # policy from config
+//!   tx_tool prune-block-data --keep-last 1000      # keep the last 1000 blocks
+//!   tx_tool prune-block-data --keep-last 100 --checkpoint-interval 0
+
+use abscissa_core::{Command, Runnable};
+
+use crate::components::block_data::{self, RetentionPolicy};
+use crate::components::db;
+use crate::output;
+use crate::prelude::*;
+use serde_json::json;
+
+/// Prune stored block hashes
+#[derive(clap::Parser, Command, Debug)]
+pub struct PruneBlockDataCmd {
+    /// Number of most recent blocks to keep (default: `retain_blocks` from config).
+    #[arg(long)]
+    pub keep_last: Option<u32>,
+
+    /// Also keep older blocks at heights that are a multiple of this, 0 for
+    /// none (default: `retain_checkpoint_interval` from config).
+    #[arg(long)]
+    pub checkpoint_interval: Option<u32>,
+}
+
+impl Runnable for PruneBlockDataCmd {
+    /// Run the `prune-block-data` subcommand.
+    fn run(&self) {
+        let config = APP.config();
+        let keep_last = self
+            .keep_last
+            .or(config.wallet.retain_blocks)
+            .expect("--keep-last is required when `retain_blocks` is not configured");
+        let policy = RetentionPolicy {
+            keep_last,
+            checkpoint_interval: self
+                .checkpoint_interval
+                .unwrap_or(config.wallet.retain_checkpoint_interval),
+        };
+
+        let mut c = db::open();
+        let pruned = block_data::prune(&mut c, &policy);
+        let remaining = block_data::count(&mut c);
+        crate::print_info(&format!(
+            "Pruned {} block hashes, {} remain",
+            pruned, remaining
+        ));
+        output::emit(
+            "prune",
+            &json!({ "pruned": pruned, "remaining": remaining }),
+        );
+    }
+}
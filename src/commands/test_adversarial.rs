@@ -0,0 +1,126 @@
+//! Node conformance test with deliberately invalid blocks.
+//!
+//! Issues a fresh asset to the first account and submits every case of
+//! [`adversarial::CASES`] built from it, or only the ones named with
+//! `--case`. Each block must be rejected by the node, and for the rule the
+//! case breaks whenever the node gives a reason.
+//!
+//! Usage:
+//!   tx_tool test-adversarial                              # all cases
+//!   tx_tool test-adversarial --case duplicate-burn        # a single case
+
+use abscissa_core::{Command, Runnable};
+use nonempty::NonEmpty;
+use orchard::issuance::compute_asset_desc_hash;
+use orchard::keys::Scope::External;
+use serde_json::json;
+
+use crate::components::adversarial::{self, Context, RECIPIENT_ACCOUNT, SENDER_ACCOUNT};
+use crate::components::db;
+use crate::components::rpc_client::reqwest::ReqwestRpcClient;
+use crate::components::transactions::{create_issue_transaction, mine, sync_from_height};
+use crate::components::wallet::Wallet;
+use crate::output;
+use crate::prelude::*;
+
+/// Submit invalid blocks and check that the node rejects them.
+#[derive(clap::Parser, Command, Debug)]
+pub struct TestAdversarialCmd {
+    /// Only run the case with this name. Can be repeated.
+    #[arg(long)]
+    pub case: Vec<String>,
+}
+
+impl Runnable for TestAdversarialCmd {
+    /// Run the `test-adversarial` subcommand.
+    fn run(&self) {
+        let cases: Vec<_> = if self.case.is_empty() {
+            adversarial::CASES.iter().collect()
+        } else {
+            self.case
+                .iter()
+                .map(|name| {
+                    adversarial::find_case(name)
+                        .unwrap_or_else(|| panic!("unknown adversarial case: {name}"))
+                })
+                .collect()
+        };
+
+        let config = APP.config();
+        let mut c = db::open();
+        let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
+
+        let issuer_addr = wallet.address_for_account(SENDER_ACCOUNT, External);
+        wallet.address_for_account(RECIPIENT_ACCOUNT, External);
+
+        sync_from_height(
+            &mut c,
+            config.chain.nu7_activation_height,
+            &mut wallet,
+            &mut rpc_client,
+        );
+
+        let asset_desc = format!("ADV-{:016x}", rand::random::<u64>());
+        let asset_desc_hash =
+            compute_asset_desc_hash(&NonEmpty::from_slice(asset_desc.as_bytes()).unwrap());
+        let (issue_tx, asset) = create_issue_transaction(
+            issuer_addr,
+            1000,
+            asset_desc_hash,
+            true,
+            &rpc_client,
+            &mut wallet,
+        );
+        mine(&mut c, &mut wallet, &mut rpc_client, vec![issue_tx])
+            .expect("block mined successfully");
+
+        let mut ctx = Context {
+            conn: &mut c,
+            wallet: &mut wallet,
+            rpc: &mut rpc_client,
+            asset,
+        };
+        let mut accepted = vec![];
+        let mut misattributed = vec![];
+        for case in cases {
+            let result = adversarial::run_case(&mut ctx, case)
+                .unwrap_or_else(|e| panic!("failed to run case {}: {e}", case.name));
+            output::emit(
+                "adversarial_case",
+                &json!({
+                    "name": result.name,
+                    "rule": result.rule,
+                    "rejected": result.rejection.is_some(),
+                    "reason": result.rejection,
+                    "expected_reason": result.expected_reason,
+                    "reason_matches": result.reason_matches(),
+                }),
+            );
+            match (&result.rejection, result.reason_matches()) {
+                (None, _) => {
+                    info!("{}: ACCEPTED, expected: {}", result.name, result.rule);
+                    accepted.push(result.name);
+                }
+                (Some(reason), Some(false)) => {
+                    info!(
+                        "{}: rejected for another reason ({}), expected: {}",
+                        result.name, reason, result.rule
+                    );
+                    misattributed.push(result.name);
+                }
+                (Some(reason), _) => info!("{}: rejected ({})", result.name, reason),
+            }
+        }
+        assert!(
+            accepted.is_empty(),
+            "The node accepted invalid blocks: {}",
+            accepted.join(", ")
+        );
+        assert!(
+            misattributed.is_empty(),
+            "The node rejected invalid blocks for another reason: {}",
+            misattributed.join(", ")
+        );
+    }
+}
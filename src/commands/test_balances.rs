@@ -1,22 +1,44 @@
+use crate::components::miner::CoinbaseRecipient;
 use crate::components::rpc_client::RpcClient;
-use crate::components::transactions::{create_burn_transaction, create_transfer_transaction};
-use crate::components::wallet::Wallet;
+use crate::components::transactions::{
+    create_burn_transaction, create_shielding_transaction, create_transfer_transaction, mine,
+    mine_empty_blocks, sync, sync_from_height, BlockAssembly,
+};
+use crate::components::transparent::COINBASE_MATURITY;
+use crate::components::wallet::{AssetBalances, Wallet};
+use crate::output;
+use crate::report;
 use crate::prelude::info;
 use diesel::SqliteConnection;
 use orchard::keys::Scope::External;
 use orchard::note::AssetBase;
+use orchard::Address;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Instant;
 use zcash_primitives::transaction::Transaction;
+use zcash_transparent::address::TransparentAddress;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) struct TestBalances(Vec<u64>);
 
+/// Which notes count towards an account's balance.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum BalanceView {
+    /// Only notes sent to the account's default external address.
+    Address,
+    /// Notes sent to any address of the account: internal-scope change and
+    /// diversified addresses included.
+    Account,
+}
+
 impl TestBalances {
     pub(crate) fn get_native_balances(
         conn: &mut SqliteConnection,
         num_accounts: usize,
         user: &mut Wallet,
     ) -> TestBalances {
-        Self::get_asset_balances(conn, AssetBase::zatoshi(), num_accounts, user)
+        Self::get_account_balances(conn, AssetBase::zatoshi(), num_accounts, user)
     }
 
     pub(crate) fn get_asset_balances(
@@ -24,11 +46,33 @@ impl TestBalances {
         asset: AssetBase,
         num_accounts: usize,
         wallet: &mut Wallet,
+    ) -> TestBalances {
+        Self::get_balances(conn, asset, num_accounts, wallet, BalanceView::Address)
+    }
+
+    pub(crate) fn get_account_balances(
+        conn: &mut SqliteConnection,
+        asset: AssetBase,
+        num_accounts: usize,
+        wallet: &mut Wallet,
+    ) -> TestBalances {
+        Self::get_balances(conn, asset, num_accounts, wallet, BalanceView::Account)
+    }
+
+    pub(crate) fn get_balances(
+        conn: &mut SqliteConnection,
+        asset: AssetBase,
+        num_accounts: usize,
+        wallet: &mut Wallet,
+        view: BalanceView,
     ) -> TestBalances {
         let balances = (0..num_accounts)
-            .map(|i| {
-                let address = wallet.address_for_account(i, External);
-                wallet.balance(conn, address, asset)
+            .map(|i| match view {
+                BalanceView::Address => {
+                    let address = wallet.address_for_account(i, External);
+                    wallet.balance(conn, address, asset)
+                }
+                BalanceView::Account => wallet.account_balance(conn, i, asset),
             })
             .collect();
 
@@ -169,8 +213,8 @@ impl<T: Clone + TransactionCreator> TxiBatch<T> {
 pub(crate) fn expected_balances_after_mine(
     balances: &TestBalances,
     miner_idx: usize,
+    coinbase_value: u64,
 ) -> TestBalances {
-    let coinbase_value = 625_000_000;
     let mut new_balances = balances.clone();
     new_balances.0[miner_idx] += coinbase_value;
     new_balances
@@ -206,24 +250,100 @@ pub(crate) fn check_balances(
     expected_balances: &TestBalances,
     user: &mut Wallet,
     num_accounts: usize,
+    view: BalanceView,
 ) {
-    let actual_balances = TestBalances::get_asset_balances(conn, asset, num_accounts, user);
+    let started = Instant::now();
+    let actual_balances = TestBalances::get_balances(conn, asset, num_accounts, user, view);
+    report::check(
+        "balances",
+        started,
+        &expected_balances.0,
+        &actual_balances.0,
+    );
     assert_eq!(&actual_balances, expected_balances);
 }
 
+/// Sync from `target_height`, then mine enough blocks paying their coinbase
+/// to `miner` for the first of them to mature and discover their outputs.
+pub(crate) fn mine_mature_coinbase(
+    conn: &mut SqliteConnection,
+    target_height: u32,
+    wallet: &mut Wallet,
+    rpc_client: &mut dyn RpcClient,
+    miner: TransparentAddress,
+) {
+    sync_from_height(conn, target_height, wallet, rpc_client);
+    // Pay the coinbase ourselves, so that it does not need to match the
+    // node's miner address.
+    let recipient = CoinbaseRecipient::Transparent(miner);
+    mine_empty_blocks(
+        COINBASE_MATURITY,
+        rpc_client,
+        BlockAssembly {
+            coinbase_recipient: Some(&recipient),
+            ..BlockAssembly::for_wallet(wallet)
+        },
+    )
+    .expect("block mined successfully");
+    sync(conn, wallet, rpc_client);
+}
+
+/// Shield the spendable transparent outputs of `account` to `recipient` in
+/// a mined block. Returns the shielded value.
+pub(crate) fn shield_spendable_utxos(
+    conn: &mut SqliteConnection,
+    wallet: &mut Wallet,
+    rpc_client: &mut dyn RpcClient,
+    account: usize,
+    recipient: Address,
+) -> u64 {
+    let utxos = wallet.spendable_utxos(conn, account);
+    let shielded: u64 = utxos.iter().map(|utxo| utxo.amount as u64).sum();
+    assert!(shielded > 0, "no mature coinbase to shield");
+    let shielding_tx = create_shielding_transaction(conn, &utxos, recipient, rpc_client, wallet);
+    mine(conn, wallet, rpc_client, vec![shielding_tx]).expect("block mined successfully");
+    shielded
+}
+
 pub(crate) fn print_balances(header: &str, asset: AssetBase, balances: &TestBalances) {
     info!("{}", header);
+    info!("AssetBase: {}", asset_label(asset));
+    balances.0.iter().enumerate().for_each(|(i, balance)| {
+        info!("Account {} balance: {}", i, balance);
+    });
+    output::emit(
+        "balances",
+        &json!({
+            "label": header,
+            "asset": hex::encode(asset.to_bytes()),
+            "balances": balances.0,
+        }),
+    );
+}
+
+pub(crate) fn print_wallet_summary(header: &str, summary: &AssetBalances) {
+    info!("{}", header);
+    summary.iter().for_each(|(asset, balance)| {
+        info!("AssetBase {} balance: {}", asset_label(asset), balance);
+    });
+    let balances: BTreeMap<String, u64> = summary
+        .iter()
+        .map(|(asset, balance)| (hex::encode(asset.to_bytes()), balance))
+        .collect();
+    output::emit(
+        "wallet_summary",
+        &json!({ "label": header, "balances": balances }),
+    );
+}
+
+fn asset_label(asset: AssetBase) -> String {
     if asset.is_zatoshi().into() {
-        info!("AssetBase: Native ZEC");
+        "Native ZEC".to_string()
     } else {
-        let trimmed_asset_base = hex::encode(asset.to_bytes())
+        hex::encode(asset.to_bytes())
             .as_str()
             .chars()
             .take(8)
-            .collect::<String>();
-        info!("AssetBase: {}", trimmed_asset_base);
+            .collect::<String>()
     }
-    balances.0.iter().enumerate().for_each(|(i, balance)| {
-        info!("Account {} balance: {}", i, balance);
-    });
 }
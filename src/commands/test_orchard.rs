@@ -6,21 +6,17 @@
 use abscissa_core::{Command, Runnable};
 use orchard::keys::Scope::External;
 use orchard::note::AssetBase;
-use zcash_primitives::transaction::TxId;
 
 use crate::commands::test_balances::{
-    check_balances, print_balances, expected_balances_after_transfer, TestBalances, TransferInfo,
-    expected_balances_after_mine, TxiBatch,
+    BalanceView, check_balances, print_balances, expected_balances_after_transfer, TestBalances,
+    TransferInfo, expected_balances_after_mine, mine_mature_coinbase, TxiBatch,
 };
 use crate::components::db;
 use crate::components::miner::MinerKey;
 use crate::components::rpc_client::reqwest::ReqwestRpcClient;
-use crate::components::transactions::{
-    create_shield_coinbase_transaction, mine, mine_empty_blocks, sync_from_height,
-};
+use crate::components::transactions::{create_shield_mature_coinbase_transactions, mine};
 use crate::components::wallet::Wallet;
 use crate::prelude::*;
-use diesel::SqliteConnection;
 
 /// Run the E2E test
 #[derive(clap::Parser, Command, Debug)]
@@ -33,9 +29,9 @@ impl Runnable for TestOrchardCmd {
         let mut c = db::open();
         let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
         // Stable wallet identity so tree state and notes persist across runs;
-        // each run shields a fresh coinbase and balance assertions are computed
+        // each run shields every mature coinbase and balance assertions are computed
         // against the current (carried-forward) wallet balance.
-        let mut wallet = Wallet::new(&mut c, &config.wallet.seed_phrase);
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
         let miner_key = MinerKey::new(&config.wallet.miner_seed_phrase);
 
         let num_users = 2;
@@ -45,11 +41,12 @@ impl Runnable for TestOrchardCmd {
 
         let miner_addr = wallet.address_for_account(miner_idx, External);
 
-        let coinbase_txid = prepare_test(
+        mine_mature_coinbase(
             &mut c,
             config.chain.nu5_activation_height,
             &mut wallet,
             &mut rpc_client,
+            miner_key.address(),
         );
 
         let balances = TestBalances::get_native_balances(&mut c, num_users, &mut wallet);
@@ -57,28 +54,28 @@ impl Runnable for TestOrchardCmd {
 
         // --------------------- Shield miner's reward ---------------------
 
-        let shielding_tx = create_shield_coinbase_transaction(
+        let coinbase_value: u64 = wallet
+            .mature_coinbase_utxos(&mut c)
+            .iter()
+            .map(|utxo| utxo.amount as u64)
+            .sum();
+        let shielding_txs = create_shield_mature_coinbase_transactions(
+            &mut c,
             miner_addr,
-            coinbase_txid,
             &rpc_client,
             &mut wallet,
-            &miner_key,
         );
-        mine(
-            &mut c,
-            &mut wallet,
-            &mut rpc_client,
-            Vec::from([shielding_tx]),
-        )
-        .expect("block mined successfully");
+        mine(&mut c, &mut wallet, &mut rpc_client, shielding_txs)
+            .expect("block mined successfully");
 
-        let expected_balances = expected_balances_after_mine(&balances, 0);
+        let expected_balances = expected_balances_after_mine(&balances, 0, coinbase_value);
         check_balances(
             &mut c,
             AssetBase::zatoshi(),
             &expected_balances,
             &mut wallet,
             num_users,
+            BalanceView::Account,
         );
 
         print_balances(
@@ -111,6 +108,7 @@ impl Runnable for TestOrchardCmd {
             &expected_balances,
             &mut wallet,
             num_users,
+            BalanceView::Account,
         );
 
         print_balances(
@@ -120,14 +118,3 @@ impl Runnable for TestOrchardCmd {
         );
     }
 }
-
-fn prepare_test(
-    c: &mut SqliteConnection,
-    target_height: u32,
-    wallet: &mut Wallet,
-    rpc_client: &mut ReqwestRpcClient,
-) -> TxId {
-    sync_from_height(c, target_height, wallet, rpc_client);
-    let (_, coinbase_txid) = mine_empty_blocks(100, rpc_client).expect("block mined successfully"); // coinbase maturity = 100
-    coinbase_txid
-}
@@ -9,18 +9,21 @@
 
 use abscissa_core::{Command, Runnable};
 use nonempty::NonEmpty;
+use std::time::Instant;
 use orchard::issuance::compute_asset_desc_hash;
 use orchard::keys::Scope::External;
 use crate::commands::test_balances::{
-    check_balances, print_balances, expected_balances_after_burn, expected_balances_after_transfer,
-    BurnInfo, TestBalances, TransferInfo, TxiBatch,
+    BalanceView, check_balances, print_balances, expected_balances_after_burn,
+    expected_balances_after_transfer, BurnInfo, TestBalances, TransferInfo, TxiBatch,
 };
 use crate::components::db;
 use crate::components::rpc_client::reqwest::ReqwestRpcClient;
 use crate::components::transactions::{
-    create_finalization_transaction, create_issue_transaction, mine, sync_from_height,
+    create_finalization_transaction, create_issue_transaction, mine, mine_block, sync_from_height,
+    BlockAssembly,
 };
 use crate::components::wallet::Wallet;
+use crate::report;
 use crate::prelude::*;
 
 /// Run the E2E test
@@ -34,7 +37,7 @@ impl Runnable for TestOrchardZSACmd {
         let mut c = db::open();
         let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
         // Stable wallet identity so tree state and notes persist across runs.
-        let mut wallet = Wallet::new(&mut c, &config.wallet.seed_phrase);
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
 
         let num_users = 2;
 
@@ -68,13 +71,13 @@ impl Runnable for TestOrchardZSACmd {
             &mut wallet,
         );
 
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
         print_balances("=== Initial balances ===", asset, &balances);
 
         mine(&mut c, &mut wallet, &mut rpc_client, Vec::from([issue_tx]))
             .expect("block mined successfully");
 
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
         print_balances("=== Balances after issue ===", asset, &balances);
 
         // --------------------- ZSA transfer ---------------------
@@ -88,13 +91,20 @@ impl Runnable for TestOrchardZSACmd {
 
         mine(&mut c, &mut wallet, &mut rpc_client, txs).expect("block mined successfully");
 
-        check_balances(&mut c, asset, &expected_balances, &mut wallet, num_users);
+        check_balances(
+            &mut c,
+            asset,
+            &expected_balances,
+            &mut wallet,
+            num_users,
+            BalanceView::Account,
+        );
 
         print_balances("=== Balances after transfer ===", asset, &expected_balances);
 
         // --------------------- Burn asset ---------------------
 
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
 
         let amount_to_burn_issuer = 7;
         let amount_to_burn_alice = amount_to_transfer_1 - 1;
@@ -111,7 +121,14 @@ impl Runnable for TestOrchardZSACmd {
         mine(&mut c, &mut wallet, &mut rpc_client, txs).expect("block mined successfully");
 
         // burn from issuer(account0) and alice(account1)
-        check_balances(&mut c, asset, &expected_balances, &mut wallet, num_users);
+        check_balances(
+            &mut c,
+            asset,
+            &expected_balances,
+            &mut wallet,
+            num_users,
+            BalanceView::Account,
+        );
 
         print_balances("=== Balances after burning ===", asset, &expected_balances);
 
@@ -134,11 +151,22 @@ impl Runnable for TestOrchardZSACmd {
             &rpc_client,
             &mut wallet,
         );
-        let result = mine(
-            &mut c,
-            &mut wallet,
+        let invalid_txids = [invalid_issue_tx.0.txid()];
+        let started = Instant::now();
+        let result = mine_block(
             &mut rpc_client,
             Vec::from([invalid_issue_tx.0]),
+            BlockAssembly {
+                violates: Some("no issuance of a finalized asset"),
+                violating_txids: &invalid_txids,
+                ..BlockAssembly::for_wallet(&wallet)
+            },
+        );
+        report::check(
+            "issue_after_finalization_rejected",
+            started,
+            &true,
+            &result.is_err(),
         );
         assert!(
             result.is_err(),
@@ -27,10 +27,10 @@ impl Runnable for TestPersistencePart1Cmd {
         let mut c = db::open();
         let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
 
-        // Fixed seed so part 2 can re-derive the same keys. Wallet::new
+        // Fixed seed so part 2 can re-derive the same keys. Wallet::open
         // auto-loads any persisted wallet_state — for part 1's first run on
         // a fresh volume there's nothing to load.
-        let mut wallet = Wallet::new(&mut c, &config.wallet.seed_phrase);
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
 
         sync_from_height(
             &mut c,
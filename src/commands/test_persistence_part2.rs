@@ -11,8 +11,8 @@ use orchard::issuance::{auth::IssueValidatingKey, compute_asset_desc_hash};
 use orchard::note::{AssetBase, AssetId};
 
 use crate::commands::test_balances::{
-    check_balances, expected_balances_after_transfer, print_balances, TestBalances, TransferInfo,
-    TxiBatch,
+    BalanceView, check_balances, expected_balances_after_transfer, print_balances, TestBalances,
+    TransferInfo, TxiBatch,
 };
 use crate::components::db;
 use crate::components::rpc_client::reqwest::ReqwestRpcClient;
@@ -29,10 +29,10 @@ impl Runnable for TestPersistencePart2Cmd {
         let mut c = db::open();
         let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
 
-        // Same fixed seed as part 1. Wallet::new auto-loads the persisted
+        // Same fixed seed as part 1. Wallet::open auto-loads the persisted
         // wallet_state row (the issued PERSIST asset's note position lives
         // there) so that the transfer below can witness it.
-        let mut wallet = Wallet::new(&mut c, &config.wallet.seed_phrase);
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
 
         // Resume from the persisted head; usually a no-op here.
         sync_from_height(
@@ -52,7 +52,7 @@ impl Runnable for TestPersistencePart2Cmd {
         ));
 
         let num_users = 2;
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
         print_balances(
             "=== Persistence part 2: balances after reload ===",
             asset,
@@ -67,7 +67,14 @@ impl Runnable for TestPersistencePart2Cmd {
         let txs = txi.to_transactions(&mut c, &rpc_client, &mut wallet);
         mine(&mut c, &mut wallet, &mut rpc_client, txs).expect("transfer block mined successfully");
 
-        check_balances(&mut c, asset, &expected_balances, &mut wallet, num_users);
+        check_balances(
+            &mut c,
+            asset,
+            &expected_balances,
+            &mut wallet,
+            num_users,
+            BalanceView::Account,
+        );
         print_balances(
             "=== Persistence part 2: balances after transfer ===",
             asset,
@@ -0,0 +1,207 @@
+//! Randomized OrchardZSA scenario checked against an independent model.
+//!
+//! Draws seeded blocks of issues, transfers, burns and finalizations with
+//! [`random_scenario::Generator`] over fresh assets, mines them and checks
+//! after every block that the balance of every account and the wallet-wide
+//! supply of every asset match the generator's model. The seed is logged at
+//! the start and again on failure, so a failing run can be replayed.
+//!
+//! Usage:
+//!   tx_tool test-random                                   # random seed
+//!   tx_tool test-random --seed 42 --blocks 20 --accounts 4 --assets 3
+
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+use abscissa_core::{Command, Runnable};
+use diesel::SqliteConnection;
+use nonempty::NonEmpty;
+use orchard::issuance::auth::IssueValidatingKey;
+use orchard::issuance::compute_asset_desc_hash;
+use orchard::keys::Scope::External;
+use orchard::note::{AssetBase, AssetId};
+
+use crate::commands::test_balances::{BurnInfo, TransactionCreator, TransferInfo};
+use crate::components::db;
+use crate::components::random_scenario::{Generator, Model, Operation};
+use crate::components::rpc_client::reqwest::ReqwestRpcClient;
+use crate::components::transactions::{
+    create_finalization_transaction, create_issue_transaction, mine, sync_from_height,
+};
+use crate::components::wallet::Wallet;
+use crate::output;
+use crate::report;
+use crate::prelude::*;
+
+/// Run a randomized scenario and check balance and supply invariants.
+#[derive(clap::Parser, Command, Debug)]
+pub struct TestRandomCmd {
+    /// Seed of the scenario. A random one is used if not given.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Number of blocks to mine.
+    #[arg(long, default_value_t = 10)]
+    pub blocks: u32,
+
+    /// Number of accounts taking part.
+    #[arg(long, default_value_t = 3)]
+    pub accounts: usize,
+
+    /// Number of assets issued.
+    #[arg(long, default_value_t = 2)]
+    pub assets: usize,
+
+    /// Maximum number of operations per block.
+    #[arg(long, default_value_t = 5)]
+    pub ops_per_block: usize,
+}
+
+impl Runnable for TestRandomCmd {
+    /// Run the `test-random` subcommand.
+    fn run(&self) {
+        let seed = self.seed.unwrap_or_else(rand::random);
+        info!("Randomized scenario seed: {}", seed);
+        output::emit("seed", &serde_json::json!({ "seed": seed }));
+
+        if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| self.run_scenario(seed))) {
+            error!(
+                "Randomized scenario failed, reproduce with: test-random --seed {} --blocks {} \
+                 --accounts {} --assets {} --ops-per-block {}",
+                seed, self.blocks, self.accounts, self.assets, self.ops_per_block
+            );
+            panic::resume_unwind(e);
+        }
+    }
+}
+
+impl TestRandomCmd {
+    fn run_scenario(&self, seed: u64) {
+        let config = APP.config();
+        let mut c = db::open();
+        let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
+
+        for account in 0..self.accounts {
+            wallet.address_for_account(account, External);
+        }
+        sync_from_height(
+            &mut c,
+            config.chain.nu7_activation_height,
+            &mut wallet,
+            &mut rpc_client,
+        );
+
+        // Fresh assets on every run, so that a seed can be replayed on the
+        // same chain: the operations only depend on the seed.
+        let issuer = IssueValidatingKey::from(&wallet.issuance_key());
+        let assets: Vec<([u8; 32], AssetBase)> = (0..self.assets)
+            .map(|_| {
+                let desc = format!("RND-{:016x}", rand::random::<u64>());
+                let desc_hash =
+                    compute_asset_desc_hash(&NonEmpty::from_slice(desc.as_bytes()).unwrap());
+                let asset = AssetBase::custom(&AssetId::new_v0(&issuer, &desc_hash));
+                (desc_hash, asset)
+            })
+            .collect();
+
+        let mut generator = Generator::new(seed, self.accounts, self.assets);
+        for block in 0..self.blocks {
+            let ops = generator.next_block(self.ops_per_block);
+            info!("Block {}: {:?}", block, ops);
+
+            let txs = ops
+                .iter()
+                .map(|op| match *op {
+                    Operation::Issue {
+                        asset,
+                        recipient,
+                        amount,
+                        first,
+                    } => {
+                        let recipient = wallet.address_for_account(recipient, External);
+                        let (tx, _) = create_issue_transaction(
+                            recipient,
+                            amount,
+                            assets[asset].0,
+                            first,
+                            &rpc_client,
+                            &mut wallet,
+                        );
+                        tx
+                    }
+                    Operation::Transfer {
+                        asset,
+                        from,
+                        to,
+                        amount,
+                    } => TransferInfo::new(from, to, assets[asset].1, amount).create_tx(
+                        &mut c,
+                        &rpc_client,
+                        &mut wallet,
+                    ),
+                    Operation::Burn {
+                        asset,
+                        account,
+                        amount,
+                    } => BurnInfo::new(account, assets[asset].1, amount).create_tx(
+                        &mut c,
+                        &rpc_client,
+                        &mut wallet,
+                    ),
+                    Operation::Finalize { asset } => {
+                        create_finalization_transaction(assets[asset].0, &rpc_client, &mut wallet)
+                    }
+                })
+                .collect();
+            mine(&mut c, &mut wallet, &mut rpc_client, txs).expect("block mined successfully");
+
+            check_model(&mut c, &wallet, &assets, generator.model(), seed);
+        }
+        info!("Randomized scenario with seed {} passed", seed);
+    }
+}
+
+/// Check the balance of every account and the wallet-wide supply of every
+/// asset against `model`.
+fn check_model(
+    c: &mut SqliteConnection,
+    wallet: &Wallet,
+    assets: &[([u8; 32], AssetBase)],
+    model: &Model,
+    seed: u64,
+) {
+    for (index, &(_, asset)) in assets.iter().enumerate() {
+        let started = Instant::now();
+        let expected: Vec<u64> = (0..model.accounts())
+            .map(|account| model.balance(account, index))
+            .collect();
+        let actual: Vec<u64> = (0..model.accounts())
+            .map(|account| wallet.account_balance(c, account, asset))
+            .collect();
+        report::check(
+            &format!("balances of asset {index}"),
+            started,
+            &expected,
+            &actual,
+        );
+        assert_eq!(
+            actual, expected,
+            "seed {seed}: balances of asset {index} by account"
+        );
+
+        let started = Instant::now();
+        let supply = wallet.summary(c).get(asset);
+        report::check(
+            &format!("supply of asset {index}"),
+            started,
+            &model.supply(index),
+            &supply,
+        );
+        assert_eq!(
+            supply,
+            model.supply(index),
+            "seed {seed}: supply of asset {index}"
+        );
+    }
+}
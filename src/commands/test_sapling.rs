@@ -0,0 +1,149 @@
+//! End-to-end test for the Sapling wallet.
+//!
+//! Mature coinbase outputs are shielded into the Orchard address of the first
+//! account, which funds the Sapling address of a second account. That account
+//! pays a third one in Sapling, which migrates part of the received funds to
+//! its Orchard address. Balances are checked after every mined block.
+
+use abscissa_core::{Command, Runnable};
+use orchard::keys::Scope::External;
+use orchard::note::AssetBase;
+
+use crate::commands::test_balances::{mine_mature_coinbase, shield_spendable_utxos};
+use crate::components::db;
+use crate::components::rpc_client::reqwest::ReqwestRpcClient;
+use crate::components::transactions::{
+    create_orchard_to_sapling_transaction, create_sapling_to_orchard_transaction,
+    create_sapling_transfer_transaction, mine,
+};
+use crate::components::wallet::Wallet;
+use crate::report;
+use crate::prelude::*;
+use diesel::SqliteConnection;
+use serde::Serialize;
+use std::time::Instant;
+
+/// Run the E2E test
+#[derive(clap::Parser, Command, Debug)]
+pub struct TestSaplingCmd {}
+
+impl Runnable for TestSaplingCmd {
+    /// Run the `test-sapling` subcommand.
+    fn run(&self) {
+        let config = APP.config();
+        let mut c = db::open();
+        let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
+
+        let miner_idx = 0;
+        let alice_idx = 1;
+        let bob_idx = 2;
+
+        let miner_orchard = wallet.address_for_account(miner_idx, External);
+        let miner_t = wallet.transparent_address(miner_idx);
+        let alice_sapling = wallet.sapling_address(alice_idx);
+        let bob_sapling = wallet.sapling_address(bob_idx);
+        let bob_orchard = wallet.address_for_account(bob_idx, External);
+
+        mine_mature_coinbase(
+            &mut c,
+            config.chain.nu5_activation_height,
+            &mut wallet,
+            &mut rpc_client,
+            miner_t,
+        );
+
+        shield_spendable_utxos(
+            &mut c,
+            &mut wallet,
+            &mut rpc_client,
+            miner_idx,
+            miner_orchard,
+        );
+
+        let balances = Balances::get(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Initial balances === {:?}", balances);
+
+        // --------------------- Fund Alice's Sapling address ---------------------
+
+        let funded = 1_000_000;
+        let funding_tx = create_orchard_to_sapling_transaction(
+            &mut c,
+            miner_orchard,
+            alice_sapling,
+            funded,
+            &rpc_client,
+            &mut wallet,
+        );
+        mine(&mut c, &mut wallet, &mut rpc_client, vec![funding_tx])
+            .expect("block mined successfully");
+
+        let mut expected = balances;
+        expected.orchard[0] -= funded;
+        expected.sapling[1] += funded;
+        expected.check(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Balances after funding === {:?}", expected);
+
+        // --------------------- Sapling transfer to Bob ---------------------
+
+        let sent = 400_000;
+        let transfer_tx = create_sapling_transfer_transaction(
+            &mut c,
+            alice_sapling,
+            bob_sapling,
+            sent,
+            &rpc_client,
+            &mut wallet,
+        );
+        mine(&mut c, &mut wallet, &mut rpc_client, vec![transfer_tx])
+            .expect("block mined successfully");
+
+        expected.sapling[1] -= sent;
+        expected.sapling[2] += sent;
+        expected.check(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Balances after Sapling transfer === {:?}", expected);
+
+        // --------------------- Bob migrates to Orchard ---------------------
+
+        let migrated = 150_000;
+        let migration_tx = create_sapling_to_orchard_transaction(
+            &mut c,
+            bob_sapling,
+            bob_orchard,
+            migrated,
+            &rpc_client,
+            &mut wallet,
+        );
+        mine(&mut c, &mut wallet, &mut rpc_client, vec![migration_tx])
+            .expect("block mined successfully");
+
+        expected.sapling[2] -= migrated;
+        expected.orchard[2] += migrated;
+        expected.check(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Balances after migration === {:?}", expected);
+    }
+}
+
+/// Sapling and Orchard ZEC balances of the scenario's accounts.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct Balances {
+    sapling: [u64; 3],
+    orchard: [u64; 3],
+}
+
+impl Balances {
+    fn get(c: &mut SqliteConnection, wallet: &Wallet, accounts: [usize; 3]) -> Self {
+        Balances {
+            sapling: accounts.map(|account| wallet.sapling_balance(c, account)),
+            orchard: accounts
+                .map(|account| wallet.account_balance(c, account, AssetBase::zatoshi())),
+        }
+    }
+
+    fn check(&self, c: &mut SqliteConnection, wallet: &Wallet, accounts: [usize; 3]) {
+        let started = Instant::now();
+        let actual = Balances::get(c, wallet, accounts);
+        report::check("balances", started, self, &actual);
+        assert_eq!(&actual, self);
+    }
+}
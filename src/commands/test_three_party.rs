@@ -13,8 +13,9 @@ use orchard::issuance::compute_asset_desc_hash;
 use orchard::keys::Scope::External;
 
 use crate::commands::test_balances::{
-    check_balances, print_balances, expected_balances_after_burn, expected_balances_after_transfer,
-    BurnInfo, TestBalances, TransferInfo, TxiBatch,
+    BalanceView, check_balances, print_balances, print_wallet_summary,
+    expected_balances_after_burn, expected_balances_after_transfer, BurnInfo, TestBalances,
+    TransferInfo, TxiBatch,
 };
 use crate::components::db;
 use crate::components::rpc_client::reqwest::ReqwestRpcClient;
@@ -33,7 +34,7 @@ impl Runnable for TestThreePartyCmd {
         let mut c = db::open();
         let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
         // Stable wallet identity so tree state and notes persist across runs.
-        let mut wallet = Wallet::new(&mut c, &config.wallet.seed_phrase);
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
 
         sync_from_height(
             &mut c,
@@ -67,13 +68,13 @@ impl Runnable for TestThreePartyCmd {
             &mut wallet,
         );
 
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
         print_balances("=== Initial balances ===", asset, &balances);
 
         mine(&mut c, &mut wallet, &mut rpc_client, Vec::from([issue_tx]))
             .expect("block mined successfully");
 
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
         print_balances("=== Balances after issue ===", asset, &balances);
 
         // --------------------- ZSA transfer from manufacturer to purchaser ---------------------
@@ -88,7 +89,14 @@ impl Runnable for TestThreePartyCmd {
 
         mine(&mut c, &mut wallet, &mut rpc_client, txs).expect("block mined successfully");
 
-        check_balances(&mut c, asset, &expected_balances, &mut wallet, num_users);
+        check_balances(
+            &mut c,
+            asset,
+            &expected_balances,
+            &mut wallet,
+            num_users,
+            BalanceView::Account,
+        );
 
         print_balances(
             "=== Balances after transfer to purchaser ===",
@@ -98,7 +106,7 @@ impl Runnable for TestThreePartyCmd {
 
         // --------------------- ZSA transfer from purchaser to supplier ---------------------
 
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
         let amount_to_transfer_2 = 1;
 
         let transfer_info =
@@ -112,7 +120,14 @@ impl Runnable for TestThreePartyCmd {
 
         mine(&mut c, &mut wallet, &mut rpc_client, txs).expect("block mined successfully");
 
-        check_balances(&mut c, asset, &expected_balances, &mut wallet, num_users);
+        check_balances(
+            &mut c,
+            asset,
+            &expected_balances,
+            &mut wallet,
+            num_users,
+            BalanceView::Account,
+        );
 
         print_balances(
             "=== Balances after transfer to supplier ===",
@@ -122,7 +137,7 @@ impl Runnable for TestThreePartyCmd {
 
         // --------------------- Supplier burning asset ---------------------
 
-        let balances = TestBalances::get_asset_balances(&mut c, asset, num_users, &mut wallet);
+        let balances = TestBalances::get_account_balances(&mut c, asset, num_users, &mut wallet);
         let amount_to_burn_supplier = 1;
 
         let txi = TxiBatch::from_item(BurnInfo::new(supplier_idx, asset, amount_to_burn_supplier));
@@ -134,12 +149,21 @@ impl Runnable for TestThreePartyCmd {
 
         mine(&mut c, &mut wallet, &mut rpc_client, txs).expect("block mined successfully");
 
-        check_balances(&mut c, asset, &expected_balances, &mut wallet, num_users);
+        check_balances(
+            &mut c,
+            asset,
+            &expected_balances,
+            &mut wallet,
+            num_users,
+            BalanceView::Account,
+        );
 
         print_balances(
             "=== Balances after burning by supplier ===",
             asset,
             &expected_balances,
         );
+
+        print_wallet_summary("=== Wallet summary ===", &wallet.summary(&mut c));
     }
 }
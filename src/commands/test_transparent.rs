@@ -0,0 +1,135 @@
+//! End-to-end test for the transparent wallet.
+//!
+//! Coinbase rewards are paid to the transparent address of the first account,
+//! shielded into Orchard, partly de-shielded to the transparent address of a
+//! second account and sent on from there to a third one. Balances are
+//! checked after every mined block.
+
+use abscissa_core::{Command, Runnable};
+use orchard::keys::Scope::External;
+use orchard::note::AssetBase;
+
+use crate::commands::test_balances::{mine_mature_coinbase, shield_spendable_utxos};
+use crate::components::db;
+use crate::components::rpc_client::reqwest::ReqwestRpcClient;
+use crate::components::transactions::{
+    create_deshielding_transaction, create_transparent_transfer_transaction, mine,
+};
+use crate::components::wallet::Wallet;
+use crate::report;
+use crate::prelude::*;
+use diesel::SqliteConnection;
+use serde::Serialize;
+use std::time::Instant;
+
+/// Run the E2E test
+#[derive(clap::Parser, Command, Debug)]
+pub struct TestTransparentCmd {}
+
+impl Runnable for TestTransparentCmd {
+    /// Run the `test-transparent` subcommand.
+    fn run(&self) {
+        let config = APP.config();
+        let mut c = db::open();
+        let mut rpc_client = ReqwestRpcClient::new(config.network.node_url());
+        let mut wallet = Wallet::open(&mut c, &config.wallet);
+
+        let miner_idx = 0;
+        let alice_idx = 1;
+        let bob_idx = 2;
+
+        let miner_orchard = wallet.address_for_account(miner_idx, External);
+        let miner_t = wallet.transparent_address(miner_idx);
+        let alice_t = wallet.transparent_address(alice_idx);
+        let bob_t = wallet.transparent_address(bob_idx);
+
+        mine_mature_coinbase(
+            &mut c,
+            config.chain.nu5_activation_height,
+            &mut wallet,
+            &mut rpc_client,
+            miner_t,
+        );
+
+        let balances = Balances::get(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Initial balances === {:?}", balances);
+
+        // --------------------- Shield mature coinbase ---------------------
+
+        let shielded = shield_spendable_utxos(
+            &mut c,
+            &mut wallet,
+            &mut rpc_client,
+            miner_idx,
+            miner_orchard,
+        );
+
+        let mut expected = balances;
+        expected.transparent[0] -= shielded;
+        expected.orchard[0] += shielded;
+        expected.check(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Balances after shielding === {:?}", expected);
+
+        // --------------------- De-shield to Alice ---------------------
+
+        let deshielded = 1_000_000;
+        let deshielding_tx = create_deshielding_transaction(
+            &mut c,
+            miner_orchard,
+            alice_t,
+            deshielded,
+            &rpc_client,
+            &mut wallet,
+        );
+        mine(&mut c, &mut wallet, &mut rpc_client, vec![deshielding_tx])
+            .expect("block mined successfully");
+
+        expected.orchard[0] -= deshielded;
+        expected.transparent[1] += deshielded;
+        expected.check(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Balances after de-shielding === {:?}", expected);
+
+        // --------------------- Transparent transfer to Bob ---------------------
+
+        let sent = 400_000;
+        let transfer_tx = create_transparent_transfer_transaction(
+            &mut c,
+            alice_t,
+            bob_t,
+            sent,
+            &rpc_client,
+            &mut wallet,
+        );
+        mine(&mut c, &mut wallet, &mut rpc_client, vec![transfer_tx])
+            .expect("block mined successfully");
+
+        expected.transparent[1] -= sent;
+        expected.transparent[2] += sent;
+        expected.check(&mut c, &wallet, [miner_idx, alice_idx, bob_idx]);
+        info!("=== Balances after transparent transfer === {:?}", expected);
+    }
+}
+
+/// Transparent and Orchard ZEC balances of the scenario's accounts.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct Balances {
+    transparent: [u64; 3],
+    orchard: [u64; 3],
+}
+
+impl Balances {
+    fn get(c: &mut SqliteConnection, wallet: &Wallet, accounts: [usize; 3]) -> Self {
+        Balances {
+            transparent: accounts.map(|account| wallet.transparent_balance(c, account)),
+            orchard: accounts
+                .map(|account| wallet.account_balance(c, account, AssetBase::zatoshi())),
+        }
+    }
+
+    fn check(&self, c: &mut SqliteConnection, wallet: &Wallet, accounts: [usize; 3]) {
+        let started = Instant::now();
+        let actual = Balances::get(c, wallet, accounts);
+        report::check("balances", started, self, &actual);
+        assert_eq!(&actual, self);
+    }
+}
@@ -1,9 +1,19 @@
+pub mod adversarial;
 pub mod block_commitment;
 pub mod block_data;
 pub mod db;
+pub mod equihash;
+pub mod history;
+pub mod history_tree;
+pub mod locks;
 pub mod miner;
 pub mod persistence;
+pub mod random_scenario;
 pub mod rpc_client;
+pub mod sapling;
+pub mod scanner;
+pub mod test_vectors;
+pub mod transparent;
 pub mod tree_state;
 pub mod wallet;
 
@@ -0,0 +1,658 @@
+//! Deliberately invalid V6 transactions and blocks for node conformance
+//! testing.
+//!
+//! Every [`AdversarialCase`] builds a block that breaks one consensus rule,
+//! optionally after mining valid setup transactions, and expects the node to
+//! reject it, naming the broken rule if it gives a reason at all.
+//!
+//! The regular builders refuse to produce invalid burns or anchors, so those
+//! cases build the Orchard bundle themselves and change it before it is
+//! proven and signed: the proof and the signatures commit to the changed
+//! bundle, and only the rule the change breaks is violated. Cases breaking a
+//! signature rule tamper with the serialized signature instead.
+//!
+//! The invalid transactions are built without locking the notes they spend or
+//! recording them in the history, so the wallet is left as it was whenever
+//! the node rejects them.
+
+use std::error::Error;
+
+use diesel::SqliteConnection;
+use incrementalmerkletree::{Hashable, Level};
+use orchard::builder::{Builder as OrchardBuilder, BundleType};
+use orchard::circuit::ProvingKey;
+use orchard::keys::{FullViewingKey, Scope, SpendAuthorizingKey};
+use orchard::note::AssetBase;
+use orchard::orchard_flavor::OrchardZSA;
+use orchard::tree::MerkleHashOrchard;
+use orchard::value::NoteValue;
+use orchard::{Anchor, Bundle};
+use rand::rngs::OsRng;
+use zcash_primitives::block::{BlockHeader, BlockHeaderData};
+use zcash_primitives::transaction::builder::{Unauthorized, DEFAULT_TX_EXPIRY_DELTA};
+use zcash_primitives::transaction::fees::zip317::FeeError;
+use zcash_primitives::transaction::sighash::{signature_hash, SignableInput};
+use zcash_primitives::transaction::txid::TxIdDigester;
+use zcash_primitives::transaction::{
+    Authorized, OrchardBundle, Transaction, TransactionData, TxId, TxVersion,
+};
+use zcash_protocol::consensus::BranchId;
+use zcash_protocol::memo::MemoBytes;
+use zcash_protocol::value::Zatoshis;
+use zcash_transparent::builder::TransparentSigningSet;
+
+use crate::components::block_commitment::block_commitment_from_parts;
+use crate::components::rpc_client::RpcClient;
+use crate::components::transactions::{
+    assemble_block, build_tx, create_issue_transaction, create_tx, mine, submit_block, sync,
+    BlockAssembly,
+};
+use crate::components::wallet::{NoteSpendMetadata, Wallet};
+use crate::prelude::info;
+
+/// The account holding the asset the cases spend and burn.
+pub const SENDER_ACCOUNT: usize = 0;
+/// The account receiving the transfers of the cases.
+pub const RECIPIENT_ACCOUNT: usize = 1;
+
+/// What a case submits to the node.
+pub struct Attack {
+    /// Valid transactions mined in a block of their own first.
+    pub setup: Vec<Transaction>,
+    /// The transactions of the block the node must reject.
+    pub block: Vec<Transaction>,
+    /// The transactions of that block breaking the rule of the case.
+    pub violating: Vec<TxId>,
+    /// Whether the header of that block commits to a wrong auth data root.
+    pub wrong_auth_data_root: bool,
+}
+
+impl Attack {
+    /// A block whose transactions all break the rule of the case.
+    fn block(block: Vec<Transaction>) -> Self {
+        Attack {
+            setup: vec![],
+            violating: block.iter().map(Transaction::txid).collect(),
+            block,
+            wrong_auth_data_root: false,
+        }
+    }
+}
+
+/// The wallet state the cases are built from.
+pub struct Context<'a> {
+    pub conn: &'a mut SqliteConnection,
+    pub wallet: &'a mut Wallet,
+    pub rpc: &'a mut dyn RpcClient,
+    /// A custom asset held by [`SENDER_ACCOUNT`], issued by the wallet.
+    pub asset: AssetBase,
+}
+
+/// An invalid block and the rule it breaks.
+pub struct AdversarialCase {
+    pub name: &'static str,
+    /// The consensus rule a conforming node rejects the block under.
+    pub rule: &'static str,
+    /// A word the node's rejection contains when it names the rule.
+    pub reason: &'static str,
+    build: fn(&mut Context) -> Result<Attack, Box<dyn Error>>,
+}
+
+/// The outcome of submitting a case.
+pub struct CaseResult {
+    pub name: &'static str,
+    pub rule: &'static str,
+    /// The [`AdversarialCase::reason`] of the case.
+    pub expected_reason: &'static str,
+    /// The node's rejection, `None` if it accepted the block.
+    pub rejection: Option<String>,
+}
+
+/// Rejections that only say that the block was rejected, as Zebra's
+/// `submitblock` does, or that it did not extend the chain.
+const REJECTIONS_WITHOUT_REASON: &[&str] = &["block rejected", "rejected", "not connected"];
+
+impl CaseResult {
+    /// Whether the node rejected the block for the expected reason. `None` if
+    /// it accepted the block or gave no reason.
+    pub fn reason_matches(&self) -> Option<bool> {
+        let rejection = self.rejection.as_deref()?.to_lowercase();
+        if REJECTIONS_WITHOUT_REASON.contains(&rejection.as_str()) {
+            return None;
+        }
+        Some(rejection.contains(self.expected_reason))
+    }
+}
+
+pub const CASES: &[AdversarialCase] = &[
+    AdversarialCase {
+        name: "double-spend-in-block",
+        rule: "a nullifier is revealed at most once in a block",
+        reason: "nullifier",
+        build: double_spend_in_block,
+    },
+    AdversarialCase {
+        name: "double-spend-across-blocks",
+        rule: "a nullifier is not revealed again after it was mined",
+        reason: "nullifier",
+        build: double_spend_across_blocks,
+    },
+    AdversarialCase {
+        name: "reused-nullifier",
+        rule: "a nullifier is revealed at most once in a transaction",
+        reason: "nullifier",
+        build: reused_nullifier,
+    },
+    AdversarialCase {
+        name: "burn-more-than-held",
+        rule: "the asset value balance including burns is zero",
+        reason: "binding",
+        build: burn_more_than_held,
+    },
+    AdversarialCase {
+        name: "burn-native-zec",
+        rule: "the native asset cannot be burnt",
+        reason: "burn",
+        build: burn_native,
+    },
+    AdversarialCase {
+        name: "zero-value-burn",
+        rule: "burn amounts are non-zero",
+        reason: "burn",
+        build: zero_value_burn,
+    },
+    AdversarialCase {
+        name: "duplicate-burn",
+        rule: "an asset appears at most once in the burn list",
+        reason: "burn",
+        build: duplicate_burn,
+    },
+    AdversarialCase {
+        name: "wrong-issuer-signature",
+        rule: "the issuance authorization signature is valid for the issuer key",
+        reason: "signature",
+        build: wrong_issuer_signature,
+    },
+    AdversarialCase {
+        name: "wrong-anchor",
+        rule: "the Orchard anchor is a previous note commitment tree root",
+        reason: "anchor",
+        build: wrong_anchor,
+    },
+    AdversarialCase {
+        name: "bad-binding-signature",
+        rule: "the Orchard binding signature is valid",
+        reason: "binding",
+        build: bad_binding_signature,
+    },
+    AdversarialCase {
+        name: "wrong-auth-data-root",
+        rule: "hashBlockCommitments commits to the block's auth data root",
+        reason: "commitment",
+        build: wrong_auth_data_root,
+    },
+];
+
+/// The case called `name`, if any.
+pub fn find_case(name: &str) -> Option<&'static AdversarialCase> {
+    CASES.iter().find(|case| case.name == name)
+}
+
+/// Build `case`, mine its setup transactions and submit its block. The wallet
+/// is synced afterwards, so it follows the chain even if the node accepted
+/// the block.
+pub fn run_case(ctx: &mut Context, case: &AdversarialCase) -> Result<CaseResult, Box<dyn Error>> {
+    let attack = (case.build)(ctx)?;
+    if !attack.setup.is_empty() {
+        mine(ctx.conn, ctx.wallet, ctx.rpc, attack.setup)?;
+    }
+
+    let tip = ctx.rpc.get_target_height()?;
+    let assembly = BlockAssembly {
+        violates: Some(case.rule),
+        violating_txids: &attack.violating,
+        ..BlockAssembly::for_wallet(ctx.wallet)
+    };
+    let mut block = assemble_block(ctx.rpc, attack.block, assembly)?;
+    if attack.wrong_auth_data_root {
+        let commitments = block_commitment_from_parts(block.chain_history_root, [0; 32]);
+        block.proposal.header = with_block_commitments(&block.proposal.header, commitments);
+    }
+    let submitted = submit_block(ctx.rpc, block, assembly);
+    let accepted = ctx.rpc.get_target_height()? > tip;
+    sync(ctx.conn, ctx.wallet, ctx.rpc);
+
+    let rejection = match submitted {
+        _ if accepted => None,
+        Err(e) => Some(e.to_string()),
+        Ok(result) => Some(result.unwrap_or_else(|| "not connected".to_string())),
+    };
+    Ok(CaseResult {
+        name: case.name,
+        rule: case.rule,
+        expected_reason: case.reason,
+        rejection,
+    })
+}
+
+fn double_spend_in_block(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    let first = transfer(ctx, &inputs, 1, 0);
+    let second = transfer(ctx, &inputs, 2, 0);
+    // Either transaction is valid on its own; the second reveals the
+    // nullifiers again.
+    Ok(Attack {
+        violating: vec![second.txid()],
+        ..Attack::block(vec![first, second])
+    })
+}
+
+fn double_spend_across_blocks(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    let first = transfer(ctx, &inputs, 1, 0);
+    let second = transfer(ctx, &inputs, 2, 0);
+    Ok(Attack {
+        setup: vec![first],
+        ..Attack::block(vec![second])
+    })
+}
+
+fn reused_nullifier(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let mut inputs = select_asset_notes(ctx);
+    inputs.push(inputs[0].clone());
+    Ok(Attack::block(vec![transfer(ctx, &inputs, 1, 0)]))
+}
+
+fn burn_more_than_held(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let held = ctx
+        .wallet
+        .account_balance(ctx.conn, SENDER_ACCOUNT, ctx.asset);
+    let inputs = select_asset_notes(ctx);
+    let asset = ctx.asset;
+    // The burn is not covered by the spent notes, so the binding signature,
+    // which enforces the value balance, does not verify.
+    let tx = mutated_transfer(ctx, &inputs, 0, 1, |parts| {
+        parts.burn = vec![(asset, NoteValue::from_raw(held + 1))];
+    })?;
+    Ok(Attack::block(vec![tx]))
+}
+
+fn burn_native(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    // No ZEC is spent to balance the burn, so the binding signature does not
+    // verify either; a node checks the burn list before it.
+    let tx = mutated_transfer(ctx, &inputs, 0, 1, |parts| {
+        parts.burn = vec![(AssetBase::zatoshi(), NoteValue::from_raw(1))];
+    })?;
+    Ok(Attack::block(vec![tx]))
+}
+
+fn zero_value_burn(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    let asset = ctx.asset;
+    let tx = mutated_transfer(ctx, &inputs, 1, 0, |parts| {
+        parts.burn = vec![(asset, NoteValue::from_raw(0))];
+    })?;
+    Ok(Attack::block(vec![tx]))
+}
+
+fn duplicate_burn(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    let asset = ctx.asset;
+    // Two entries burning one unit each balance the two units built in.
+    let tx = mutated_transfer(ctx, &inputs, 0, 2, |parts| {
+        parts.burn = vec![(asset, NoteValue::from_raw(1)); 2];
+    })?;
+    Ok(Attack::block(vec![tx]))
+}
+
+fn wrong_issuer_signature(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let recipient = ctx
+        .wallet
+        .address_for_account(SENDER_ACCOUNT, Scope::External);
+    let asset_desc_hash: [u8; 32] = rand::random();
+    let (tx, _) =
+        create_issue_transaction(recipient, 1, asset_desc_hash, true, &*ctx.rpc, ctx.wallet);
+    let signature = issue_signature(&tx);
+    // The last byte belongs to the low end of `s`, so the signature still parses.
+    let mut flipped = signature.clone();
+    *flipped.last_mut().unwrap() ^= 1;
+    let tx = tamper(&tx, |bytes| replace_unique(bytes, &signature, &flipped))?;
+    Ok(Attack::block(vec![tx]))
+}
+
+fn wrong_anchor(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    // A valid base field element that is not the root of any tree of depth 32.
+    let wrong = Anchor::from(MerkleHashOrchard::empty_root(Level::from(1)));
+    let tx = mutated_transfer(ctx, &inputs, 1, 0, |parts| parts.anchor = wrong)?;
+    Ok(Attack::block(vec![tx]))
+}
+
+fn bad_binding_signature(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    let tx = transfer(ctx, &inputs, 1, 0);
+    let signature = binding_signature(&tx);
+    let mut flipped = signature;
+    flipped[0] ^= 1;
+    let tx = tamper(&tx, |bytes| replace_unique(bytes, &signature, &flipped))?;
+    Ok(Attack::block(vec![tx]))
+}
+
+fn wrong_auth_data_root(ctx: &mut Context) -> Result<Attack, Box<dyn Error>> {
+    let inputs = select_asset_notes(ctx);
+    let tx = transfer(ctx, &inputs, 1, 0);
+    Ok(Attack {
+        violating: vec![],
+        wrong_auth_data_root: true,
+        ..Attack::block(vec![tx])
+    })
+}
+
+/// Notes of the case asset held by [`SENDER_ACCOUNT`] worth at least 2 units.
+fn select_asset_notes(ctx: &mut Context) -> Vec<NoteSpendMetadata> {
+    ctx.wallet
+        .select_spendable_notes(ctx.conn, SENDER_ACCOUNT, 2, ctx.asset)
+}
+
+/// Spend `inputs`, pay `amount` to [`RECIPIENT_ACCOUNT`], burn `burnt` and
+/// send the rest back to [`SENDER_ACCOUNT`].
+fn transfer(
+    ctx: &mut Context,
+    inputs: &[NoteSpendMetadata],
+    amount: u64,
+    burnt: u64,
+) -> Transaction {
+    let total: u64 = inputs.iter().map(|input| input.note.value().inner()).sum();
+    let recipient = ctx
+        .wallet
+        .address_for_account(RECIPIENT_ACCOUNT, Scope::External);
+    let change_address = ctx.wallet.change_address(SENDER_ACCOUNT);
+
+    let target_height = ctx
+        .rpc
+        .get_target_height()
+        .expect("failed to get target height");
+    let mut tx = create_tx(target_height, ctx.wallet);
+    let orchard_keys: Vec<SpendAuthorizingKey> = inputs
+        .iter()
+        .map(|input| {
+            tx.add_orchard_spend::<FeeError>(
+                (&input.sk).into(),
+                input.note,
+                input.merkle_path.clone(),
+            )
+            .unwrap();
+            SpendAuthorizingKey::from(&input.sk)
+        })
+        .collect();
+
+    let outputs = [
+        (recipient, amount, ctx.wallet.account_ovk(SENDER_ACCOUNT)),
+        (
+            change_address,
+            total - amount - burnt,
+            ctx.wallet.internal_ovk(SENDER_ACCOUNT),
+        ),
+    ];
+    for (address, value, ovk) in outputs {
+        if value != 0 {
+            tx.add_orchard_output::<FeeError>(
+                Some(ovk),
+                address,
+                Zatoshis::from_u64(value).unwrap(),
+                ctx.asset,
+                MemoBytes::empty(),
+            )
+            .unwrap();
+        }
+    }
+    if burnt != 0 {
+        tx.add_burn::<FeeError>(burnt, ctx.asset).unwrap();
+    }
+
+    build_tx(
+        tx,
+        &TransparentSigningSet::new(),
+        &[],
+        orchard_keys.as_slice(),
+        None,
+    )
+}
+
+/// The fields of an Orchard bundle a case changes before the bundle is
+/// proven and signed.
+struct BundleParts {
+    anchor: Anchor,
+    burn: Vec<(AssetBase, NoteValue)>,
+}
+
+/// Like [`transfer`], but `mutate` changes the Orchard bundle before it is
+/// proven and signed. The transaction builder refuses such bundles, so the
+/// bundle is built with the Orchard builder and the transaction assembled
+/// here, the way the transaction builder does.
+fn mutated_transfer(
+    ctx: &mut Context,
+    inputs: &[NoteSpendMetadata],
+    amount: u64,
+    burnt: u64,
+    mutate: impl FnOnce(&mut BundleParts),
+) -> Result<Transaction, Box<dyn Error>> {
+    let total: u64 = inputs.iter().map(|input| input.note.value().inner()).sum();
+    let recipient = ctx
+        .wallet
+        .address_for_account(RECIPIENT_ACCOUNT, Scope::External);
+    let change_address = ctx.wallet.change_address(SENDER_ACCOUNT);
+    let target_height = ctx.rpc.get_target_height()?;
+    let anchor = ctx
+        .wallet
+        .orchard_anchor()
+        .ok_or("the wallet has no Orchard anchor")?;
+
+    let mut builder = OrchardBuilder::new(BundleType::DEFAULT_ZSA, anchor);
+    for input in inputs {
+        builder
+            .add_spend(
+                FullViewingKey::from(&input.sk),
+                input.note,
+                input.merkle_path.clone(),
+            )
+            .map_err(|e| format!("failed to add Orchard spend: {e:?}"))?;
+    }
+    let outputs = [
+        (recipient, amount, ctx.wallet.account_ovk(SENDER_ACCOUNT)),
+        (
+            change_address,
+            total - amount - burnt,
+            ctx.wallet.internal_ovk(SENDER_ACCOUNT),
+        ),
+    ];
+    for (address, value, ovk) in outputs {
+        if value != 0 {
+            builder
+                .add_output(
+                    Some(ovk),
+                    address,
+                    NoteValue::from_raw(value),
+                    ctx.asset,
+                    MemoBytes::empty().into_bytes(),
+                )
+                .map_err(|e| format!("failed to add Orchard output: {e:?}"))?;
+        }
+    }
+    if burnt != 0 {
+        builder
+            .add_burn(ctx.asset, NoteValue::from_raw(burnt))
+            .map_err(|e| format!("failed to add burn: {e:?}"))?;
+    }
+    let (bundle, _) = builder
+        .build(&mut OsRng)
+        .map_err(|e| format!("failed to build Orchard bundle: {e:?}"))?
+        .ok_or("empty Orchard bundle")?;
+
+    let mut parts = BundleParts {
+        anchor: *bundle.anchor(),
+        burn: bundle.burn().to_vec(),
+    };
+    mutate(&mut parts);
+    let bundle = Bundle::from_parts(
+        bundle.actions().clone(),
+        *bundle.flags(),
+        *bundle.value_balance(),
+        parts.burn,
+        parts.anchor,
+        bundle.authorization().clone(),
+    );
+
+    let expiry_height = target_height + DEFAULT_TX_EXPIRY_DELTA;
+    let unauthorized = TransactionData::<Unauthorized>::from_parts(
+        TxVersion::V6,
+        BranchId::Nu7,
+        0,
+        expiry_height,
+        Zatoshis::ZERO,
+        None,
+        None,
+        None,
+        Some(OrchardBundle::OrchardZSA(bundle.clone())),
+        None,
+    );
+    let txid_parts = unauthorized.digest(TxIdDigester);
+    let sighash = signature_hash(&unauthorized, &SignableInput::Shielded, &txid_parts);
+    let saks: Vec<SpendAuthorizingKey> = inputs
+        .iter()
+        .map(|input| SpendAuthorizingKey::from(&input.sk))
+        .collect();
+    let bundle = bundle
+        .create_proof(&ProvingKey::build::<OrchardZSA>(), &mut OsRng)
+        .and_then(|bundle| bundle.apply_signatures(&mut OsRng, *sighash.as_ref(), &saks))
+        .map_err(|e| format!("failed to authorize Orchard bundle: {e:?}"))?;
+
+    let tx = TransactionData::<Authorized>::from_parts(
+        TxVersion::V6,
+        BranchId::Nu7,
+        0,
+        expiry_height,
+        Zatoshis::ZERO,
+        None,
+        None,
+        None,
+        Some(OrchardBundle::OrchardZSA(bundle)),
+        None,
+    )
+    .freeze()?;
+    info!("Build mutated tx: {}", tx.txid());
+    Ok(tx)
+}
+
+fn binding_signature(tx: &Transaction) -> [u8; 64] {
+    match tx.orchard_bundle() {
+        Some(OrchardBundle::OrchardVanilla(b)) => b.authorization().binding_signature().into(),
+        Some(OrchardBundle::OrchardZSA(b)) => b.authorization().binding_signature().into(),
+        None => panic!("transaction has no Orchard bundle"),
+    }
+}
+
+/// The encoded issuance authorization signature of `tx`.
+fn issue_signature(tx: &Transaction) -> Vec<u8> {
+    tx.issue_bundle()
+        .expect("transaction has no issue bundle")
+        .authorization()
+        .signature()
+        .encode()
+}
+
+/// Re-parse `tx` after applying `f` to its serialized form.
+fn tamper(tx: &Transaction, f: impl FnOnce(&mut Vec<u8>)) -> Result<Transaction, Box<dyn Error>> {
+    let mut bytes = vec![];
+    tx.write(&mut bytes)?;
+    f(&mut bytes);
+    Transaction::read(bytes.as_slice(), tx.consensus_branch_id())
+        .map_err(|e| format!("tampered transaction does not parse: {e}").into())
+}
+
+/// Replace the only occurrence of `from` in `bytes` with `to`.
+fn replace_unique(bytes: &mut Vec<u8>, from: &[u8], to: &[u8]) {
+    let position = find_unique(bytes, from);
+    bytes.splice(position..position + from.len(), to.iter().copied());
+}
+
+fn find_unique(bytes: &[u8], needle: &[u8]) -> usize {
+    let mut positions = bytes
+        .windows(needle.len())
+        .enumerate()
+        .filter(|(_, window)| *window == needle)
+        .map(|(position, _)| position);
+    let position = positions.next().expect("field not found in transaction");
+    assert!(
+        positions.next().is_none(),
+        "field is not unique in transaction"
+    );
+    position
+}
+
+/// `header` with its `hashBlockCommitments` replaced by `commitments`.
+fn with_block_commitments(header: &BlockHeader, commitments: [u8; 32]) -> BlockHeader {
+    BlockHeader::from_data(BlockHeaderData {
+        version: header.version,
+        prev_block: header.prev_block,
+        merkle_root: header.merkle_root,
+        final_sapling_root: commitments,
+        time: header.time,
+        bits: header.bits,
+        nonce: header.nonce,
+        solution: header.solution.clone(),
+    })
+    .expect("valid header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_names_are_unique() {
+        for (i, case) in CASES.iter().enumerate() {
+            assert!(CASES[i + 1..].iter().all(|other| other.name != case.name));
+            assert!(std::ptr::eq(find_case(case.name).unwrap(), case));
+        }
+        assert!(find_case("no-such-case").is_none());
+    }
+
+    #[test]
+    fn test_replace_unique() {
+        let mut bytes = vec![0, 1, 2, 3, 4];
+        replace_unique(&mut bytes, &[2, 3], &[9, 9, 9]);
+        assert_eq!(bytes, vec![0, 1, 9, 9, 9, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not unique")]
+    fn test_replace_ambiguous() {
+        replace_unique(&mut vec![1, 2, 1, 2], &[1, 2], &[0, 0]);
+    }
+
+    fn result(rejection: Option<&str>) -> CaseResult {
+        CaseResult {
+            name: "wrong-anchor",
+            rule: "anchor",
+            expected_reason: "anchor",
+            rejection: rejection.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_reason_matches() {
+        assert_eq!(result(None).reason_matches(), None);
+        assert_eq!(result(Some("Block rejected")).reason_matches(), None);
+        assert_eq!(result(Some("not connected")).reason_matches(), None);
+        assert_eq!(
+            result(Some("unknown Orchard anchor")).reason_matches(),
+            Some(true)
+        );
+        assert_eq!(
+            result(Some("invalid binding signature")).reason_matches(),
+            Some(false)
+        );
+    }
+}
@@ -1,11 +1,87 @@
 //! SQLite-backed local db for block hashes, used for resumable sync.
 //!
+//! Only the stored head is needed to resume sync and detect reorgs, so older
+//! blocks can be dropped with [`prune`].
+//!
 //! Free functions take `&mut SqliteConnection` so callers can participate in
 //! a transaction.
 
 use diesel::dsl::max;
 use diesel::prelude::*;
+use diesel::sql_types::Integer;
 use diesel::upsert::excluded;
+use zcash_primitives::block::BlockHeader;
+
+/// Which stored blocks [`prune`] keeps.
+///
+/// The most recent blocks always include the stored head, which is all that
+/// reorg detection during sync compares against the chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Number of most recent blocks to keep, at least 1.
+    pub keep_last: u32,
+    /// Older blocks at heights that are a multiple of this are kept as
+    /// checkpoints; 0 keeps none of them.
+    pub checkpoint_interval: u32,
+}
+
+/// A stored block: its hash and, for blocks synced with their header, the
+/// header fields. Hashes and roots are hex in `getblock` display order.
+#[derive(Queryable, Selectable, Debug, Clone, PartialEq, Eq)]
+#[diesel(table_name = crate::schema::block_data)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct StoredBlock {
+    pub height: i32,
+    pub hash: String,
+    pub prev_hash: Option<String>,
+    pub merkle_root: Option<String>,
+    pub block_commitments: Option<String>,
+    pub time: Option<i64>,
+    pub bits: Option<i64>,
+}
+
+/// Hex encoding of a header hash or root in `getblock` display order.
+pub fn display_hex(bytes: &[u8; 32]) -> String {
+    let mut bytes = *bytes;
+    bytes.reverse();
+    hex::encode(bytes)
+}
+
+/// Get the stored block at the given height.
+pub fn get(conn: &mut SqliteConnection, height: u32) -> Option<StoredBlock> {
+    use crate::schema::block_data::dsl as bd;
+    let height_i32 = i32::try_from(height).ok()?;
+    bd::block_data
+        .filter(bd::height.eq(height_i32))
+        .select(StoredBlock::as_select())
+        .first(conn)
+        .optional()
+        .expect("Error querying block data")
+}
+
+/// Get the stored blocks with heights in `from..=to`, in height order.
+pub fn range(conn: &mut SqliteConnection, from: u32, to: u32) -> Vec<StoredBlock> {
+    use crate::schema::block_data::dsl as bd;
+    let from = i32::try_from(from).unwrap_or(i32::MAX);
+    let to = i32::try_from(to).unwrap_or(i32::MAX);
+    bd::block_data
+        .filter(bd::height.between(from, to))
+        .order(bd::height.asc())
+        .select(StoredBlock::as_select())
+        .load(conn)
+        .expect("Error querying block data")
+}
+
+/// Get the stored block with the given hash (hex, display order).
+pub fn find_by_hash(conn: &mut SqliteConnection, hash: &str) -> Option<StoredBlock> {
+    use crate::schema::block_data::dsl as bd;
+    bd::block_data
+        .filter(bd::hash.eq(hash.to_lowercase()))
+        .select(StoredBlock::as_select())
+        .first(conn)
+        .optional()
+        .expect("Error querying block data")
+}
 
 /// Get the hash of the block at the given height.
 pub fn get_hash(conn: &mut SqliteConnection, height: u32) -> Option<String> {
@@ -19,7 +95,7 @@ pub fn get_hash(conn: &mut SqliteConnection, height: u32) -> Option<String> {
         .expect("Error querying block data")
 }
 
-/// Insert or update a block hash in the local db.
+/// Insert or update a block hash in the local db, without a header.
 pub fn insert(conn: &mut SqliteConnection, height: u32, hash: String) {
     use crate::schema::block_data::dsl as bd;
     let height_i32 = i32::try_from(height).expect("height too large");
@@ -27,7 +103,40 @@ pub fn insert(conn: &mut SqliteConnection, height: u32, hash: String) {
         .values((bd::height.eq(height_i32), bd::hash.eq(hash)))
         .on_conflict(bd::height)
         .do_update()
-        .set(bd::hash.eq(excluded(bd::hash)))
+        .set((
+            bd::hash.eq(excluded(bd::hash)),
+            bd::prev_hash.eq(None::<String>),
+            bd::merkle_root.eq(None::<String>),
+            bd::block_commitments.eq(None::<String>),
+            bd::time.eq(None::<i64>),
+            bd::bits.eq(None::<i64>),
+        ))
+        .execute(conn)
+        .expect("Error inserting block data");
+}
+
+/// Insert or update a block hash together with its header.
+pub fn insert_with_header(
+    conn: &mut SqliteConnection,
+    height: u32,
+    hash: String,
+    header: &BlockHeader,
+) {
+    use crate::schema::block_data::dsl as bd;
+    let height_i32 = i32::try_from(height).expect("height too large");
+    let row = (
+        bd::hash.eq(hash),
+        bd::prev_hash.eq(Some(display_hex(&header.prev_block.0))),
+        bd::merkle_root.eq(Some(display_hex(&header.merkle_root))),
+        bd::block_commitments.eq(Some(display_hex(&header.final_sapling_root))),
+        bd::time.eq(Some(i64::from(header.time))),
+        bd::bits.eq(Some(i64::from(header.bits))),
+    );
+    diesel::insert_into(bd::block_data)
+        .values((bd::height.eq(height_i32), row.clone()))
+        .on_conflict(bd::height)
+        .do_update()
+        .set(row)
         .execute(conn)
         .expect("Error inserting block data");
 }
@@ -42,6 +151,32 @@ pub fn last_height(conn: &mut SqliteConnection) -> Option<u32> {
         .and_then(|h| u32::try_from(h).ok())
 }
 
+/// Number of stored blocks.
+pub fn count(conn: &mut SqliteConnection) -> i64 {
+    use crate::schema::block_data::dsl as bd;
+    bd::block_data
+        .count()
+        .get_result(conn)
+        .expect("Error counting block data")
+}
+
+/// Delete the stored blocks that `policy` does not keep and return how many
+/// were deleted.
+pub fn prune(conn: &mut SqliteConnection, policy: &RetentionPolicy) -> usize {
+    let Some(head) = last_height(conn) else {
+        return 0;
+    };
+    let cutoff = head.saturating_sub(policy.keep_last.max(1) - 1);
+    let cutoff = i32::try_from(cutoff).expect("height too large");
+    let interval = i32::try_from(policy.checkpoint_interval).unwrap_or(i32::MAX);
+    diesel::sql_query("DELETE FROM block_data WHERE height < ? AND (? = 0 OR height % ? != 0)")
+        .bind::<Integer, _>(cutoff)
+        .bind::<Integer, _>(interval)
+        .bind::<Integer, _>(interval)
+        .execute(conn)
+        .expect("Error pruning block data")
+}
+
 /// Clear all stored blocks.
 pub fn clear(conn: &mut SqliteConnection) {
     use crate::schema::block_data::dsl as bd;
@@ -55,6 +190,7 @@ mod tests {
     use super::*;
     use crate::components::db;
     use tempfile::NamedTempFile;
+    use zcash_primitives::block::{BlockHash, BlockHeaderData};
 
     #[test]
     fn test_block_data_operations() {
@@ -83,4 +219,86 @@ mod tests {
         assert!(last_height(&mut c).is_none());
         assert!(get_hash(&mut c, 100).is_none());
     }
+
+    #[test]
+    fn test_block_headers() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        let mut prev_block = [0u8; 32];
+        prev_block[0] = 0xaa;
+        let header = BlockHeader::from_data(BlockHeaderData {
+            version: 4,
+            prev_block: BlockHash(prev_block),
+            merkle_root: [1; 32],
+            final_sapling_root: [2; 32],
+            time: 1_700_000_000,
+            bits: 0x200f0f0f,
+            nonce: [0; 32],
+            solution: vec![],
+        })
+        .unwrap();
+        insert_with_header(&mut c, 5, "hash5".to_string(), &header);
+
+        let block = get(&mut c, 5).unwrap();
+        assert_eq!(block.hash, "hash5");
+        // Display order: the first internal byte comes last.
+        assert!(block.prev_hash.as_deref().unwrap().ends_with("aa"));
+        assert_eq!(block.merkle_root, Some(hex::encode([1; 32])));
+        assert_eq!(block.block_commitments, Some(hex::encode([2; 32])));
+        assert_eq!(block.time, Some(1_700_000_000));
+        assert_eq!(block.bits, Some(0x200f0f0f));
+
+        // Re-inserting without a header drops the stale header fields.
+        insert(&mut c, 5, "hash5b".to_string());
+        let block = get(&mut c, 5).unwrap();
+        assert_eq!(block.hash, "hash5b");
+        assert!(block.prev_hash.is_none() && block.time.is_none());
+        assert!(get(&mut c, 6).is_none());
+
+        insert(&mut c, 4, "hash4".to_string());
+        insert(&mut c, 7, "hash7".to_string());
+        let heights: Vec<i32> = range(&mut c, 3, 6).iter().map(|b| b.height).collect();
+        assert_eq!(heights, vec![4, 5]);
+        assert_eq!(find_by_hash(&mut c, "HASH7").map(|b| b.height), Some(7));
+        assert!(find_by_hash(&mut c, "hash6").is_none());
+    }
+
+    #[test]
+    fn test_prune() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        let policy = RetentionPolicy {
+            keep_last: 5,
+            checkpoint_interval: 10,
+        };
+        assert_eq!(prune(&mut c, &policy), 0);
+
+        for height in 0..=30 {
+            insert(&mut c, height, format!("hash{height}"));
+        }
+        // Keeps 26..=30 and the checkpoints 0, 10 and 20.
+        assert_eq!(prune(&mut c, &policy), 23);
+        assert_eq!(count(&mut c), 8);
+        assert_eq!(last_height(&mut c), Some(30));
+        for height in [0, 10, 20, 26, 30] {
+            assert!(get_hash(&mut c, height).is_some());
+        }
+        for height in [1, 19, 21, 25] {
+            assert!(get_hash(&mut c, height).is_none());
+        }
+        assert_eq!(prune(&mut c, &policy), 0);
+
+        // Without checkpoints only the head is kept.
+        let policy = RetentionPolicy {
+            keep_last: 0,
+            checkpoint_interval: 0,
+        };
+        assert_eq!(prune(&mut c, &policy), 7);
+        assert_eq!(count(&mut c), 1);
+        assert_eq!(get_hash(&mut c, 30).as_deref(), Some("hash30"));
+    }
 }
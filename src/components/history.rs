@@ -0,0 +1,238 @@
+//! SQLite-backed transaction history of the wallet.
+//!
+//! A row is written when the wallet builds a transaction and updated once the
+//! transaction is seen in a mined block. Net value changes are stored per
+//! account and asset in `transaction_values`.
+//!
+//! Free functions take `&mut SqliteConnection` so callers can participate in
+//! a transaction.
+
+use std::collections::BTreeMap;
+
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+
+use crate::schema::{transaction_values, transactions};
+
+#[derive(Clone, Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = transactions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TxRecord {
+    pub txid: Vec<u8>,
+    pub mined_height: Option<i32>,
+    pub block_hash: Option<String>,
+    pub expiry_height: i32,
+    /// Fee in zatoshis, when it can be derived from the transaction alone
+    /// (i.e. it has no transparent inputs).
+    pub fee: Option<i64>,
+    /// JSON array of the action indices of wallet outputs carrying a memo.
+    pub memo_refs: String,
+    /// JSON array describing the issue actions of the transaction.
+    pub issuance: String,
+    /// JSON array of the assets and amounts burnt by the transaction.
+    pub burns: String,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = transaction_values)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TxValue {
+    pub txid: Vec<u8>,
+    pub account: i32,
+    pub asset: Vec<u8>,
+    pub value_change: i64,
+}
+
+/// Insert or update a transaction together with its value changes.
+///
+/// On conflict only the mined height and block hash are updated; the value
+/// changes are replaced as a whole.
+pub fn record(conn: &mut SqliteConnection, record: &TxRecord, values: &[TxValue]) {
+    use crate::schema::transaction_values::dsl as tv;
+    use crate::schema::transactions::dsl as t;
+    diesel::insert_into(t::transactions)
+        .values(record)
+        .on_conflict(t::txid)
+        .do_update()
+        .set((
+            t::mined_height.eq(excluded(t::mined_height)),
+            t::block_hash.eq(excluded(t::block_hash)),
+        ))
+        .execute(conn)
+        .expect("Error recording transaction");
+    diesel::delete(tv::transaction_values.filter(tv::txid.eq(&record.txid)))
+        .execute(conn)
+        .expect("Error replacing transaction values");
+    diesel::insert_into(tv::transaction_values)
+        .values(values)
+        .execute(conn)
+        .expect("Error recording transaction values");
+}
+
+/// Whether the transaction with the given id is in the history.
+pub fn contains(conn: &mut SqliteConnection, txid: &[u8]) -> bool {
+    use crate::schema::transactions::dsl as t;
+    t::transactions
+        .filter(t::txid.eq(txid))
+        .count()
+        .get_result::<i64>(conn)
+        .expect("Error querying transactions")
+        > 0
+}
+
+/// All recorded transactions with their value changes, mined ones first in
+/// chain order followed by pending ones.
+///
+/// When `account` or `asset` is given, only the value changes matching both
+/// filters are kept and transactions left without any are dropped.
+pub fn list(
+    conn: &mut SqliteConnection,
+    account: Option<u32>,
+    asset: Option<[u8; 32]>,
+) -> Vec<(TxRecord, Vec<TxValue>)> {
+    use crate::schema::transaction_values::dsl as tv;
+    use crate::schema::transactions::dsl as t;
+
+    let mut values: BTreeMap<Vec<u8>, Vec<TxValue>> = BTreeMap::new();
+    for v in tv::transaction_values
+        .select(TxValue::as_select())
+        .order((tv::account, tv::asset))
+        .load(conn)
+        .expect("Error loading transaction values")
+    {
+        values.entry(v.txid.clone()).or_default().push(v);
+    }
+
+    let mut records = t::transactions
+        .select(TxRecord::as_select())
+        .load(conn)
+        .expect("Error loading transactions");
+    records.sort_by_key(|r| (r.mined_height.is_none(), r.mined_height));
+
+    let filtered = account.is_some() || asset.is_some();
+    records
+        .into_iter()
+        .filter_map(|r| {
+            let vs: Vec<TxValue> = values
+                .remove(&r.txid)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|v| account.is_none_or(|a| v.account as u32 == a))
+                .filter(|v| asset.is_none_or(|a| v.asset == a))
+                .collect();
+            if filtered && vs.is_empty() {
+                None
+            } else {
+                Some((r, vs))
+            }
+        })
+        .collect()
+}
+
+/// The recorded transactions mined at `height`, ordered by txid.
+pub fn mined_at(conn: &mut SqliteConnection, height: u32) -> Vec<TxRecord> {
+    use crate::schema::transactions::dsl as t;
+    t::transactions
+        .filter(t::mined_height.eq(height as i32))
+        .order(t::txid)
+        .select(TxRecord::as_select())
+        .load(conn)
+        .expect("Error loading transactions")
+}
+
+/// Clear the whole transaction history.
+pub fn clear(conn: &mut SqliteConnection) {
+    use crate::schema::transaction_values::dsl as tv;
+    use crate::schema::transactions::dsl as t;
+    diesel::delete(tv::transaction_values)
+        .execute(conn)
+        .expect("Error clearing transaction values");
+    diesel::delete(t::transactions)
+        .execute(conn)
+        .expect("Error clearing transactions");
+}
+
+/// Whether `memo` is the empty memo, which starts with 0xF6 (ZIP 302).
+pub fn is_empty_memo(memo: &[u8]) -> bool {
+    memo.first() == Some(&0xF6)
+}
+
+/// `memo` as hex, `None` for an empty memo.
+pub fn memo_hex(memo: &[u8]) -> Option<String> {
+    (!is_empty_memo(memo)).then(|| hex::encode(memo))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::db;
+    use tempfile::NamedTempFile;
+
+    fn tx_record(id: u8, mined_height: Option<i32>) -> TxRecord {
+        TxRecord {
+            txid: vec![id; 32],
+            mined_height,
+            block_hash: mined_height.map(|h| format!("hash{}", h)),
+            expiry_height: 40,
+            fee: Some(0),
+            memo_refs: "[]".to_string(),
+            issuance: "[]".to_string(),
+            burns: "[]".to_string(),
+        }
+    }
+
+    fn tx_value(id: u8, account: i32, asset: u8, value_change: i64) -> TxValue {
+        TxValue {
+            txid: vec![id; 32],
+            account,
+            asset: vec![asset; 32],
+            value_change,
+        }
+    }
+
+    #[test]
+    fn test_history_operations() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        record(
+            &mut c,
+            &tx_record(2, None),
+            &[tx_value(2, 0, 0, -30), tx_value(2, 1, 0, 30)],
+        );
+        record(&mut c, &tx_record(1, Some(10)), &[tx_value(1, 0, 9, 100)]);
+        assert!(contains(&mut c, &[2; 32]));
+        assert!(!contains(&mut c, &[3; 32]));
+
+        let all = list(&mut c, None, None);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].0.mined_height, Some(10));
+        assert_eq!(all[1].1.len(), 2);
+
+        // Seeing the pending transaction mined updates it in place.
+        record(
+            &mut c,
+            &tx_record(2, Some(12)),
+            &[tx_value(2, 0, 0, -30), tx_value(2, 1, 0, 30)],
+        );
+        let all = list(&mut c, None, None);
+        assert_eq!(all[1].0.mined_height, Some(12));
+        assert_eq!(all[1].0.block_hash.as_deref(), Some("hash12"));
+
+        let account1 = list(&mut c, Some(1), None);
+        assert_eq!(account1.len(), 1);
+        assert_eq!(account1[0].1[0].value_change, 30);
+
+        let asset9 = list(&mut c, None, Some([9; 32]));
+        assert_eq!(asset9.len(), 1);
+        assert_eq!(asset9[0].0.txid, vec![1; 32]);
+
+        let mined: Vec<Vec<u8>> = mined_at(&mut c, 12).into_iter().map(|r| r.txid).collect();
+        assert_eq!(mined, vec![vec![2; 32]]);
+        assert!(mined_at(&mut c, 11).is_empty());
+
+        clear(&mut c);
+        assert!(list(&mut c, None, None).is_empty());
+    }
+}
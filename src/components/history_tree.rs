@@ -0,0 +1,622 @@
+//! Local ZIP-221 chain history tree.
+//!
+//! Since Heartwood every block header commits to the root of a Merkle
+//! mountain range (FlyClient MMR) over the blocks of the current network
+//! upgrade before it; from NU5 on the root enters the header through
+//! `hashBlockCommitments`. The wallet maintains the tree from synced headers
+//! so that mined blocks can commit to a locally computed root instead of the
+//! one served by `getblocktemplate`, or cross-check the node's.
+//!
+//! Only the peaks of the mountain range are kept: that is all that is needed
+//! to append a block and to compute the root. They are persisted in the
+//! `history_tree_peaks` table.
+//!
+//! Leaves commit to the Sapling and Orchard roots after their block, both
+//! taken from the trees the wallet maintains itself. The history tree is only
+//! available when sync has seen every block since the activation of the
+//! current network upgrade.
+//!
+//! See <https://zips.z.cash/zip-0221>.
+
+use std::cmp::Ordering;
+use std::io::{self, Read, Write};
+
+use diesel::prelude::*;
+use zcash_encoding::CompactSize;
+use zcash_primitives::block::BlockHeader;
+use zcash_primitives::transaction::Transaction;
+use zcash_protocol::consensus::{BlockHeight, BranchId, REGTEST_NETWORK};
+
+use crate::schema::history_tree_peaks;
+
+/// Root of the empty Sapling note commitment tree, in internal byte order.
+pub const EMPTY_SAPLING_ROOT: [u8; 32] = [
+    0xfb, 0xc2, 0xf4, 0x30, 0x0c, 0x01, 0xf0, 0xb7, 0x82, 0x0d, 0x00, 0xe3, 0x34, 0x7c, 0x8d, 0xa4,
+    0xee, 0x61, 0x46, 0x74, 0x37, 0x6c, 0xbc, 0x45, 0x35, 0x9d, 0xaa, 0x54, 0xf9, 0xb5, 0x49, 0x3e,
+];
+
+const HISTORY_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZcashHistory";
+
+/// A 256-bit unsigned integer, as little-endian 64-bit limbs. Only what the
+/// block work computation needs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct U256([u64; 4]);
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+}
+
+impl U256 {
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (chunk, limb) in bytes.chunks_mut(8).zip(self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    /// The work represented by a block with the given compact difficulty
+    /// target: `2^256 / (target + 1)`, computed as `!target / (target + 1) + 1`
+    /// like zcashd's `GetBlockProof`.
+    pub fn work(bits: u32) -> Self {
+        let target = Self::from_compact(bits);
+        if target == U256::default() {
+            return U256::default();
+        }
+        let divisor = target.wrapping_add(U256::from(1));
+        target.not().div(divisor).wrapping_add(U256::from(1))
+    }
+
+    /// The target encoded by compact `bits`.
+    pub fn from_compact(bits: u32) -> Self {
+        let exponent = bits >> 24;
+        let mantissa = u64::from(bits & 0x007f_ffff);
+        if exponent <= 3 {
+            U256::from(mantissa >> (8 * (3 - exponent)))
+        } else {
+            (0..8 * (exponent - 3)).fold(U256::from(mantissa), |t, _| t.shl1())
+        }
+    }
+
+    pub fn wrapping_add(self, other: Self) -> Self {
+        let mut result = [0; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        U256(result)
+    }
+
+    fn wrapping_sub(self, other: Self) -> Self {
+        let mut result = [0; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        U256(result)
+    }
+
+    fn not(self) -> Self {
+        U256(self.0.map(|limb| !limb))
+    }
+
+    fn shl1(self) -> Self {
+        let mut result = [0; 4];
+        for (i, limb) in result.iter_mut().enumerate() {
+            *limb = self.0[i] << 1 | if i > 0 { self.0[i - 1] >> 63 } else { 0 };
+        }
+        U256(result)
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.0[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    /// Binary long division. Panics on division by zero.
+    fn div(self, divisor: Self) -> Self {
+        assert!(divisor != U256::default(), "division by zero");
+        let mut quotient = U256::default();
+        let mut remainder = U256::default();
+        for i in (0..256).rev() {
+            // A remainder that overflows the shift is larger than any divisor.
+            let overflow = remainder.bit(255);
+            remainder = remainder.shl1();
+            remainder.0[0] |= self.bit(i) as u64;
+            if overflow || remainder >= divisor {
+                remainder = remainder.wrapping_sub(divisor);
+                quotient.0[i / 64] |= 1 << (i % 64);
+            }
+        }
+        quotient
+    }
+}
+
+/// The node data of a ZIP-221 tree node: a block for a leaf, the range of
+/// blocks below it for an inner node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryNode {
+    branch_id: BranchId,
+    subtree_commitment: [u8; 32],
+    start_time: u32,
+    end_time: u32,
+    start_target: u32,
+    end_target: u32,
+    start_sapling_root: [u8; 32],
+    end_sapling_root: [u8; 32],
+    subtree_total_work: U256,
+    start_height: u64,
+    end_height: u64,
+    sapling_tx: u64,
+    start_orchard_root: [u8; 32],
+    end_orchard_root: [u8; 32],
+    orchard_tx: u64,
+}
+
+/// Whether blocks of `branch_id` commit to a chain history root.
+fn has_history(branch_id: BranchId) -> bool {
+    !matches!(
+        branch_id,
+        BranchId::Sprout | BranchId::Overwinter | BranchId::Sapling | BranchId::Blossom
+    )
+}
+
+/// Whether node data of `branch_id` includes the Orchard fields (V2 node
+/// data, NU5 onwards).
+fn has_orchard(branch_id: BranchId) -> bool {
+    has_history(branch_id) && !matches!(branch_id, BranchId::Heartwood | BranchId::Canopy)
+}
+
+fn branch_at(height: u32) -> BranchId {
+    BranchId::for_height(&REGTEST_NETWORK, BlockHeight::from_u32(height))
+}
+
+/// Whether the block at `height` is the first of its network upgrade, so that
+/// it commits to an empty history tree.
+fn is_activation(height: u32) -> bool {
+    height == 0 || branch_at(height) != branch_at(height - 1)
+}
+
+impl HistoryNode {
+    /// The leaf for the block at `height` with the given header, Sapling and
+    /// Orchard roots after the block and transactions.
+    pub fn leaf<'a>(
+        height: u32,
+        header: &BlockHeader,
+        sapling_root: [u8; 32],
+        orchard_root: [u8; 32],
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Self {
+        let (mut sapling_tx, mut orchard_tx) = (0, 0);
+        for tx in transactions {
+            sapling_tx += tx.sapling_bundle().is_some() as u64;
+            orchard_tx += tx.orchard_bundle().is_some() as u64;
+        }
+        HistoryNode {
+            branch_id: branch_at(height),
+            subtree_commitment: header.hash().0,
+            start_time: header.time,
+            end_time: header.time,
+            start_target: header.bits,
+            end_target: header.bits,
+            start_sapling_root: sapling_root,
+            end_sapling_root: sapling_root,
+            subtree_total_work: U256::work(header.bits),
+            start_height: u64::from(height),
+            end_height: u64::from(height),
+            sapling_tx,
+            start_orchard_root: orchard_root,
+            end_orchard_root: orchard_root,
+            orchard_tx,
+        }
+    }
+
+    /// The parent of `left` and `right`, which must be adjacent subtrees of
+    /// the same network upgrade.
+    fn combine(left: &Self, right: &Self) -> Self {
+        let mut children = left.to_bytes();
+        children.extend(right.to_bytes());
+        HistoryNode {
+            branch_id: left.branch_id,
+            subtree_commitment: hash(left.branch_id, &children),
+            start_time: left.start_time,
+            end_time: right.end_time,
+            start_target: left.start_target,
+            end_target: right.end_target,
+            start_sapling_root: left.start_sapling_root,
+            end_sapling_root: right.end_sapling_root,
+            subtree_total_work: left
+                .subtree_total_work
+                .wrapping_add(right.subtree_total_work),
+            start_height: left.start_height,
+            end_height: right.end_height,
+            sapling_tx: left.sapling_tx + right.sapling_tx,
+            start_orchard_root: left.start_orchard_root,
+            end_orchard_root: right.end_orchard_root,
+            orchard_tx: left.orchard_tx + right.orchard_tx,
+        }
+    }
+
+    /// Number of blocks covered by this node.
+    fn leaf_count(&self) -> u64 {
+        self.end_height - self.start_height + 1
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.subtree_commitment)?;
+        w.write_all(&self.start_time.to_le_bytes())?;
+        w.write_all(&self.end_time.to_le_bytes())?;
+        w.write_all(&self.start_target.to_le_bytes())?;
+        w.write_all(&self.end_target.to_le_bytes())?;
+        w.write_all(&self.start_sapling_root)?;
+        w.write_all(&self.end_sapling_root)?;
+        w.write_all(&self.subtree_total_work.to_le_bytes())?;
+        CompactSize::write(&mut *w, self.start_height as usize)?;
+        CompactSize::write(&mut *w, self.end_height as usize)?;
+        CompactSize::write(&mut *w, self.sapling_tx as usize)?;
+        if has_orchard(self.branch_id) {
+            w.write_all(&self.start_orchard_root)?;
+            w.write_all(&self.end_orchard_root)?;
+            CompactSize::write(&mut *w, self.orchard_tx as usize)?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(branch_id: BranchId, r: &mut R) -> io::Result<Self> {
+        fn hash<R: Read>(r: &mut R) -> io::Result<[u8; 32]> {
+            let mut bytes = [0; 32];
+            r.read_exact(&mut bytes)?;
+            Ok(bytes)
+        }
+        fn u32_le<R: Read>(r: &mut R) -> io::Result<u32> {
+            let mut bytes = [0; 4];
+            r.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+
+        let mut node = HistoryNode {
+            branch_id,
+            subtree_commitment: hash(r)?,
+            start_time: u32_le(r)?,
+            end_time: u32_le(r)?,
+            start_target: u32_le(r)?,
+            end_target: u32_le(r)?,
+            start_sapling_root: hash(r)?,
+            end_sapling_root: hash(r)?,
+            subtree_total_work: U256::from_le_bytes(hash(r)?),
+            start_height: CompactSize::read(&mut *r)?,
+            end_height: CompactSize::read(&mut *r)?,
+            sapling_tx: CompactSize::read(&mut *r)?,
+            start_orchard_root: [0; 32],
+            end_orchard_root: [0; 32],
+            orchard_tx: 0,
+        };
+        if has_orchard(branch_id) {
+            node.start_orchard_root = hash(r)?;
+            node.end_orchard_root = hash(r)?;
+            node.orchard_tx = CompactSize::read(&mut *r)?;
+        }
+        Ok(node)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.write(&mut bytes)
+            .expect("writing to a Vec never fails");
+        bytes
+    }
+}
+
+/// BLAKE2b-256 personalized with `ZcashHistory` and the consensus branch id.
+fn hash(branch_id: BranchId, data: &[u8]) -> [u8; 32] {
+    let mut personalization = [0; 16];
+    personalization[..12].copy_from_slice(HISTORY_PERSONALIZATION_PREFIX);
+    personalization[12..].copy_from_slice(&u32::from(branch_id).to_le_bytes());
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .personal(&personalization)
+        .hash(data)
+        .as_bytes()
+        .try_into()
+        .expect("32 byte array")
+}
+
+/// The chain history tree of one network upgrade, from its activation block
+/// up to the last appended block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryTree {
+    /// Peaks of the mountain range from left to right, each covering fewer
+    /// blocks than the one before it.
+    peaks: Vec<HistoryNode>,
+}
+
+impl HistoryTree {
+    /// A tree holding the activation block of a network upgrade.
+    fn new(leaf: HistoryNode) -> Self {
+        HistoryTree { peaks: vec![leaf] }
+    }
+
+    pub fn branch_id(&self) -> BranchId {
+        self.peaks[0].branch_id
+    }
+
+    /// Height of the last block in the tree.
+    pub fn end_height(&self) -> u32 {
+        self.peaks.last().unwrap().end_height as u32
+    }
+
+    fn append(&mut self, leaf: HistoryNode) {
+        self.peaks.push(leaf);
+        while let [.., left, right] = self.peaks.as_slice() {
+            if left.leaf_count() != right.leaf_count() {
+                break;
+            }
+            let parent = HistoryNode::combine(left, right);
+            self.peaks.truncate(self.peaks.len() - 2);
+            self.peaks.push(parent);
+        }
+    }
+
+    /// The root hash of the tree. The peaks are bagged from left to right,
+    /// like `zcash_history` does: the leftmost two are combined first.
+    pub fn root(&self) -> [u8; 32] {
+        let mut peaks = self.peaks.iter();
+        let first = peaks.next().unwrap().clone();
+        let root = peaks.fold(first, |root, peak| HistoryNode::combine(&root, peak));
+        hash(root.branch_id, &root.to_bytes())
+    }
+
+    /// Extend `tree` with the block at `height`.
+    ///
+    /// Returns the tree up to and including the block, or `None` when it
+    /// cannot be computed: the block predates Heartwood, or `tree` does not
+    /// end right below it in the same network upgrade.
+    pub fn append_block<'a>(
+        tree: Option<Self>,
+        height: u32,
+        header: &BlockHeader,
+        sapling_root: [u8; 32],
+        orchard_root: [u8; 32],
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Option<Self> {
+        let branch_id = branch_at(height);
+        if !has_history(branch_id) {
+            return None;
+        }
+        let leaf = HistoryNode::leaf(height, header, sapling_root, orchard_root, transactions);
+        if is_activation(height) {
+            return Some(HistoryTree::new(leaf));
+        }
+        let mut tree =
+            tree.filter(|t| t.branch_id() == branch_id && t.end_height() + 1 == height)?;
+        tree.append(leaf);
+        Some(tree)
+    }
+}
+
+/// The chain history root the header of the block at `height` commits to:
+/// all zeros for the first block of a network upgrade, otherwise the root of
+/// `tree` if it ends right below `height`. `None` if it cannot be computed.
+pub fn root_at(tree: Option<&HistoryTree>, height: u32) -> Option<[u8; 32]> {
+    let branch_id = branch_at(height);
+    if !has_history(branch_id) {
+        return None;
+    }
+    if is_activation(height) {
+        return Some([0; 32]);
+    }
+    tree.filter(|t| t.branch_id() == branch_id && t.end_height() + 1 == height)
+        .map(HistoryTree::root)
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = history_tree_peaks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct HistoryPeakRow {
+    idx: i32,
+    branch_id: i64,
+    data: Vec<u8>,
+}
+
+/// Replace the stored history tree with `tree`, or remove it for `None`.
+pub fn save(conn: &mut SqliteConnection, tree: Option<&HistoryTree>) -> Result<(), String> {
+    clear(conn)?;
+    let Some(tree) = tree else {
+        return Ok(());
+    };
+    let rows: Vec<HistoryPeakRow> = tree
+        .peaks
+        .iter()
+        .enumerate()
+        .map(|(idx, peak)| HistoryPeakRow {
+            idx: idx as i32,
+            branch_id: i64::from(u32::from(peak.branch_id)),
+            data: peak.to_bytes(),
+        })
+        .collect();
+    diesel::insert_into(history_tree_peaks::table)
+        .values(&rows)
+        .execute(conn)
+        .map_err(|e| format!("Failed to save history tree: {e}"))?;
+    Ok(())
+}
+
+/// Load the stored history tree, if any.
+pub fn load(conn: &mut SqliteConnection) -> Result<Option<HistoryTree>, String> {
+    let rows = history_tree_peaks::table
+        .order(history_tree_peaks::idx.asc())
+        .select(HistoryPeakRow::as_select())
+        .load(conn)
+        .map_err(|e| format!("Failed to query history_tree_peaks: {e}"))?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    let mut peaks = Vec::with_capacity(rows.len());
+    for row in rows {
+        let branch_id = BranchId::try_from(row.branch_id as u32)
+            .map_err(|e| format!("Invalid branch id in history tree: {e}"))?;
+        let mut data = row.data.as_slice();
+        let peak = HistoryNode::read(branch_id, &mut data)
+            .map_err(|e| format!("Failed to decode history tree peak: {e}"))?;
+        if !data.is_empty() {
+            return Err("Trailing bytes in history tree peak".to_string());
+        }
+        peaks.push(peak);
+    }
+    Ok(Some(HistoryTree { peaks }))
+}
+
+/// Remove the stored history tree.
+pub fn clear(conn: &mut SqliteConnection) -> Result<(), String> {
+    diesel::delete(history_tree_peaks::table)
+        .execute(conn)
+        .map_err(|e| format!("Failed to delete history tree: {e}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::db;
+    use tempfile::NamedTempFile;
+
+    fn node(height: u64, time: u32) -> HistoryNode {
+        HistoryNode {
+            branch_id: BranchId::Nu5,
+            subtree_commitment: [height as u8; 32],
+            start_time: time,
+            end_time: time,
+            start_target: 0x200f0f0f,
+            end_target: 0x200f0f0f,
+            start_sapling_root: EMPTY_SAPLING_ROOT,
+            end_sapling_root: EMPTY_SAPLING_ROOT,
+            subtree_total_work: U256::work(0x200f0f0f),
+            start_height: height,
+            end_height: height,
+            sapling_tx: 0,
+            start_orchard_root: [7; 32],
+            end_orchard_root: [7; 32],
+            orchard_tx: 1,
+        }
+    }
+
+    #[test]
+    fn test_work() {
+        // The work of Bitcoin's genesis block, 0x0100010001.
+        assert_eq!(U256::work(0x1d00ffff), U256::from(0x0001_0001_0001));
+        // Zebra's regtest difficulty.
+        assert_eq!(U256::work(0x200f0f0f), U256::from(17));
+        let work = U256::work(0x1d00ffff).wrapping_add(U256::from(u64::MAX));
+        assert_eq!(U256::from_le_bytes(work.to_le_bytes()), work);
+    }
+
+    #[test]
+    fn test_node_roundtrip() {
+        let node = node(300, 1000);
+        let bytes = node.to_bytes();
+        // V2 node data: 6 hashes, 4 u32s and 3 + 1 compact sizes.
+        assert_eq!(bytes.len(), 6 * 32 + 4 * 4 + 3 + 3 + 1 + 1);
+        assert_eq!(
+            HistoryNode::read(BranchId::Nu5, &mut bytes.as_slice()).unwrap(),
+            node
+        );
+    }
+
+    #[test]
+    fn test_mountain_range() {
+        let leaves: Vec<HistoryNode> = (1..=3).map(|h| node(h, h as u32 * 10)).collect();
+        let mut tree = HistoryTree::new(leaves[0].clone());
+        assert_eq!(tree.root(), hash(BranchId::Nu5, &leaves[0].to_bytes()));
+
+        tree.append(leaves[1].clone());
+        assert_eq!(tree.peaks.len(), 1);
+        let parent = HistoryNode::combine(&leaves[0], &leaves[1]);
+        assert_eq!(parent.leaf_count(), 2);
+        assert_eq!(parent.start_time, 10);
+        assert_eq!(parent.end_time, 20);
+        assert_eq!(parent.orchard_tx, 2);
+        assert_eq!(parent.subtree_total_work, U256::from(34));
+
+        tree.append(leaves[2].clone());
+        assert_eq!(tree.peaks.len(), 2);
+        assert_eq!(tree.end_height(), 3);
+        let root = HistoryNode::combine(&parent, &leaves[2]);
+        assert_eq!(tree.root(), hash(BranchId::Nu5, &root.to_bytes()));
+
+        tree.append(node(4, 40));
+        assert_eq!(tree.peaks.len(), 1);
+        assert_eq!(tree.peaks[0].leaf_count(), 4);
+    }
+
+    #[test]
+    fn test_root_matches_zcash_history() {
+        use zcash_history::{Entry, Tree, Version, V2};
+
+        let branch_id = u32::from(BranchId::Nu5);
+        let data = |node: &HistoryNode| V2::from_bytes(branch_id, node.to_bytes()).unwrap();
+        let leaves: Vec<HistoryNode> = (1..=32).map(|h| node(h, h as u32 * 10)).collect();
+
+        let mut tree = HistoryTree::new(leaves[0].clone());
+        let mut reference =
+            Tree::<V2>::new(1, vec![(0, Entry::new_leaf(data(&leaves[0])))], vec![]);
+        for leaf in &leaves[1..] {
+            tree.append(leaf.clone());
+            reference.append_leaf(data(leaf)).unwrap();
+            let expected = V2::hash(reference.root_node().unwrap().data());
+            assert_eq!(tree.root(), expected, "{} leaves", tree.end_height());
+        }
+    }
+
+    #[test]
+    fn test_db_save_load_clear() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        assert_eq!(load(&mut c).unwrap(), None);
+
+        let mut tree = HistoryTree::new(node(1, 10));
+        for h in 2..=7 {
+            tree.append(node(h, h as u32 * 10));
+        }
+        assert_eq!(tree.peaks.len(), 3);
+        save(&mut c, Some(&tree)).unwrap();
+        assert_eq!(load(&mut c).unwrap(), Some(tree.clone()));
+
+        tree.append(node(8, 80));
+        save(&mut c, Some(&tree)).unwrap();
+        let loaded = load(&mut c).unwrap().unwrap();
+        assert_eq!(loaded.peaks.len(), 1);
+        assert_eq!(loaded.root(), tree.root());
+
+        save(&mut c, None).unwrap();
+        assert_eq!(load(&mut c).unwrap(), None);
+    }
+}
@@ -0,0 +1,24 @@
+//! Locks on the rows spent by transactions the wallet built.
+//!
+//! A spent row is locked by the spending transaction until the spend is
+//! observed on chain or the transaction expires, so that transactions built
+//! before the next sync do not spend it again. Every table of spendable rows
+//! has the `spend_tx_id`, `locked_by_tx_id` and `lock_expiry_height` columns
+//! this works on.
+
+use diesel::prelude::*;
+use diesel::sql_types::Integer;
+
+/// Release the locks of `table` whose transaction can no longer be mined at
+/// `height`. Locks with an expiry height of zero never expire. Returns the
+/// number of rows that became spendable again.
+pub fn unlock_expired(conn: &mut SqliteConnection, table: &str, height: u32) -> usize {
+    diesel::sql_query(format!(
+        "UPDATE {table} SET locked_by_tx_id = NULL, lock_expiry_height = NULL \
+         WHERE spend_tx_id IS NULL AND locked_by_tx_id IS NOT NULL \
+         AND lock_expiry_height > 0 AND lock_expiry_height < ?"
+    ))
+    .bind::<Integer, _>(height as i32)
+    .execute(conn)
+    .unwrap_or_else(|e| panic!("Error unlocking {table}: {e}"))
+}
@@ -1,21 +1,30 @@
-//! The transparent keypair whose address Zebra is configured to pay coinbase
-//! rewards to.
+//! The transparent keypair coinbase rewards are paid to, and the recipients a
+//! locally built coinbase can pay.
 //!
-//! In regtest, Zebra mines blocks and pays the coinbase to a single hard-coded
-//! address; the tx-tool holds the matching secret key here so it can *spend*
-//! those coinbase outputs (i.e., shield them into the Orchard wallet). The
-//! tool itself never produces blocks — that's Zebra's job — and `MinerKey` is
-//! not used outside the t→z shielding path.
+//! By default blocks are mined with the coinbase from Zebra's block template,
+//! which pays the address Zebra is configured with; `MinerKey` then has to
+//! hold the matching secret key so the tool can *spend* those coinbase outputs
+//! (i.e., shield them into the Orchard wallet). With a [`CoinbaseRecipient`]
+//! the tool builds the coinbase itself and can pay any miner key, or an
+//! Orchard address directly.
 
 use bip0039::Mnemonic;
-use ripemd::{Digest, Ripemd160};
-use secp256k1::{Secp256k1, SecretKey};
-use sha2::Sha256;
-use zip32::AccountId;
-use zcash_protocol::consensus::REGTEST_NETWORK;
+use orchard::Address;
+use secp256k1::SecretKey;
 use zcash_transparent::address::TransparentAddress;
 use zcash_transparent::builder::TransparentSigningSet;
-use zcash_transparent::keys::NonHardenedChildIndex;
+
+use crate::components::transparent;
+
+/// Who the coinbase of a locally built block pays the miner reward to.
+#[derive(Clone, Debug)]
+pub enum CoinbaseRecipient {
+    /// A transparent P2PKH address, usually [`MinerKey::address`].
+    Transparent(TransparentAddress),
+    /// An Orchard address. The output is encrypted with the all-zero OVK, as
+    /// ZIP-213 requires for shielded coinbase outputs.
+    Orchard(Address),
+}
 
 pub struct MinerKey {
     seed: [u8; 64],
@@ -29,17 +38,11 @@ impl MinerKey {
     }
 
     pub(crate) fn address(&self) -> TransparentAddress {
-        let pubkey = self.secret_key().public_key(&Secp256k1::new()).serialize();
-        let hash = &Ripemd160::digest(Sha256::digest(pubkey))[..];
-        TransparentAddress::PublicKeyHash(hash.try_into().unwrap())
+        transparent::p2pkh_address(&self.secret_key())
     }
 
     pub(crate) fn secret_key(&self) -> SecretKey {
-        let account = AccountId::try_from(0).unwrap();
-        zcash_transparent::keys::AccountPrivKey::from_seed(&REGTEST_NETWORK, &self.seed, account)
-            .unwrap()
-            .derive_external_secret_key(NonHardenedChildIndex::ZERO)
-            .unwrap()
+        transparent::account_secret_key(&self.seed, 0)
     }
 
     pub(crate) fn signing_set(&self) -> TransparentSigningSet {
@@ -1,5 +1,17 @@
 use diesel::prelude::*;
 
+/// Where a note is in its spend lifecycle.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NoteState {
+    /// Not referenced by any transaction known to the wallet.
+    Unspent,
+    /// Selected as an input of a transaction built by this wallet that has
+    /// not been mined yet. Released again once the transaction expires.
+    PendingSpend,
+    /// Its nullifier was revealed in a mined block.
+    Spent,
+}
+
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = crate::schema::notes)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -17,6 +29,10 @@ pub struct NoteData {
     pub recipient_address: Vec<u8>,
     pub spend_tx_id: Option<Vec<u8>>,
     pub spend_action_index: i32,
+    pub mined_height: Option<i32>,
+    pub spend_height: Option<i32>,
+    pub locked_by_tx_id: Option<Vec<u8>>,
+    pub lock_expiry_height: Option<i32>,
 }
 
 #[derive(Insertable)]
@@ -35,6 +51,10 @@ pub struct InsertableNoteData {
     pub recipient_address: Vec<u8>,
     pub spend_tx_id: Option<Vec<u8>>,
     pub spend_action_index: i32,
+    pub mined_height: Option<i32>,
+    pub spend_height: Option<i32>,
+    pub locked_by_tx_id: Option<Vec<u8>>,
+    pub lock_expiry_height: Option<i32>,
 }
 
 impl InsertableNoteData {
@@ -52,6 +72,81 @@ impl InsertableNoteData {
             recipient_address: note.recipient_address,
             spend_tx_id: note.spend_tx_id,
             spend_action_index: note.spend_action_index,
+            mined_height: note.mined_height,
+            spend_height: note.spend_height,
+            locked_by_tx_id: note.locked_by_tx_id,
+            lock_expiry_height: note.lock_expiry_height,
+        }
+    }
+}
+
+impl NoteData {
+    pub fn state(&self) -> NoteState {
+        if self.spend_tx_id.is_some() {
+            NoteState::Spent
+        } else if self.locked_by_tx_id.is_some() {
+            NoteState::PendingSpend
+        } else {
+            NoteState::Unspent
+        }
+    }
+
+    /// Number of blocks, counting the one that mined the note, up to and
+    /// including `tip_height`. Zero if the mined height is unknown.
+    pub fn confirmations(&self, tip_height: u32) -> u32 {
+        match self.mined_height {
+            Some(h) if h >= 0 && (h as u32) <= tip_height => tip_height - h as u32 + 1,
+            _ => 0,
+        }
+    }
+}
+
+/// An output of a wallet transaction recovered with the sender's outgoing
+/// viewing key. `recipient_account` is set when the recipient is one of the
+/// wallet's own accounts (e.g. change).
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::sent_notes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct SentNoteData {
+    pub id: i32,
+    pub tx_id: Vec<u8>,
+    pub action_index: i32,
+    pub sender_account: i32,
+    pub recipient_address: Vec<u8>,
+    pub recipient_account: Option<i32>,
+    pub amount: i64,
+    pub asset: Vec<u8>,
+    pub memo: Vec<u8>,
+    pub mined_height: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::sent_notes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct InsertableSentNoteData {
+    pub tx_id: Vec<u8>,
+    pub action_index: i32,
+    pub sender_account: i32,
+    pub recipient_address: Vec<u8>,
+    pub recipient_account: Option<i32>,
+    pub amount: i64,
+    pub asset: Vec<u8>,
+    pub memo: Vec<u8>,
+    pub mined_height: i32,
+}
+
+impl InsertableSentNoteData {
+    pub fn from_sent_note_data(note: SentNoteData) -> Self {
+        Self {
+            tx_id: note.tx_id,
+            action_index: note.action_index,
+            sender_account: note.sender_account,
+            recipient_address: note.recipient_address,
+            recipient_account: note.recipient_account,
+            amount: note.amount,
+            asset: note.asset,
+            memo: note.memo,
+            mined_height: note.mined_height,
         }
     }
 }
@@ -1,4 +1,7 @@
-use crate::components::persistence::model::{InsertableNoteData, NoteData};
+use crate::components::locks;
+use crate::components::persistence::model::{
+    InsertableNoteData, InsertableSentNoteData, NoteData, SentNoteData,
+};
 use crate::schema::notes::dsl::notes;
 use crate::schema::notes::*;
 use diesel::associations::HasTable;
@@ -26,6 +29,48 @@ pub fn find_non_spent_notes(
         .expect("Error loading notes")
 }
 
+/// All unspent notes of `asset_base`, regardless of which wallet address received them.
+pub fn find_non_spent_notes_for_asset(
+    conn: &mut SqliteConnection,
+    asset_base: AssetBase,
+) -> Vec<NoteData> {
+    notes
+        .filter(
+            spend_tx_id
+                .is_null()
+                .and(asset.eq(asset_base.to_bytes().to_vec())),
+        )
+        .select(NoteData::as_select())
+        .load(conn)
+        .expect("Error loading notes")
+}
+
+/// All unspent notes of every asset held by the wallet.
+pub fn find_all_non_spent_notes(conn: &mut SqliteConnection) -> Vec<NoteData> {
+    notes
+        .filter(spend_tx_id.is_null())
+        .select(NoteData::as_select())
+        .load(conn)
+        .expect("Error loading notes")
+}
+
+/// Unspent notes of `asset_base` that are not locked by a pending transaction.
+pub fn find_spendable_notes_for_asset(
+    conn: &mut SqliteConnection,
+    asset_base: AssetBase,
+) -> Vec<NoteData> {
+    notes
+        .filter(
+            spend_tx_id
+                .is_null()
+                .and(locked_by_tx_id.is_null())
+                .and(asset.eq(asset_base.to_bytes().to_vec())),
+        )
+        .select(NoteData::as_select())
+        .load(conn)
+        .expect("Error loading notes")
+}
+
 pub fn find_notes_for_tx(conn: &mut SqliteConnection, txid: &TxId) -> Vec<NoteData> {
     notes
         .filter(tx_id.eq(txid.as_ref().to_vec()))
@@ -34,6 +79,26 @@ pub fn find_notes_for_tx(conn: &mut SqliteConnection, txid: &TxId) -> Vec<NoteDa
         .expect("Error loading notes")
 }
 
+/// Notes received in a transaction mined at `height`.
+pub fn find_notes_mined_at(conn: &mut SqliteConnection, height: u32) -> Vec<NoteData> {
+    notes
+        .filter(mined_height.eq(height as i32))
+        .order((tx_id, action_index))
+        .select(NoteData::as_select())
+        .load(conn)
+        .expect("Error loading notes")
+}
+
+/// Notes spent by a transaction mined at `height`.
+pub fn find_notes_spent_at(conn: &mut SqliteConnection, height: u32) -> Vec<NoteData> {
+    notes
+        .filter(spend_height.eq(height as i32))
+        .order((tx_id, action_index))
+        .select(NoteData::as_select())
+        .load(conn)
+        .expect("Error loading notes")
+}
+
 pub fn find_by_nullifier(conn: &mut SqliteConnection, nf: &Nullifier) -> Option<NoteData> {
     notes
         .filter(nullifier.eq(nf.to_bytes().to_vec()))
@@ -49,17 +114,41 @@ pub fn mark_as_potentially_spent(
     note_id: i32,
     spend_tx_id_value: &TxId,
     spend_action_index_value: i32,
+    spend_height_value: u32,
 ) {
     diesel::update(notes)
         .filter(id.eq(note_id))
         .set((
             spend_tx_id.eq(spend_tx_id_value.as_ref().to_vec()),
             spend_action_index.eq(spend_action_index_value),
+            spend_height.eq(spend_height_value as i32),
+            locked_by_tx_id.eq(None::<Vec<u8>>),
+            lock_expiry_height.eq(None::<i32>),
         ))
         .execute(conn)
         .unwrap();
 }
 
+/// Reserve `note_ids` for the not-yet-mined transaction `txid` so they are
+/// not selected again. An `expiry_height` of zero means the transaction never
+/// expires and the lock is only released when the spend is observed.
+pub fn lock_notes(conn: &mut SqliteConnection, note_ids: &[i32], txid: &TxId, expiry_height: u32) {
+    diesel::update(notes)
+        .filter(id.eq_any(note_ids).and(spend_tx_id.is_null()))
+        .set((
+            locked_by_tx_id.eq(txid.as_ref().to_vec()),
+            lock_expiry_height.eq(expiry_height as i32),
+        ))
+        .execute(conn)
+        .expect("Error locking notes");
+}
+
+/// Release locks whose transaction can no longer be mined at `height`.
+/// Returns the number of notes that became spendable again.
+pub fn unlock_expired_notes(conn: &mut SqliteConnection, height: u32) -> usize {
+    locks::unlock_expired(conn, "notes", height)
+}
+
 pub fn update_note_position(conn: &mut SqliteConnection, note_id: i32, position_value: i64) {
     diesel::update(notes)
         .filter(id.eq(note_id))
@@ -81,3 +170,139 @@ pub fn delete_all_notes(conn: &mut SqliteConnection) {
         .execute(conn)
         .expect("Error deleting notes");
 }
+
+/// Store an output recovered with an outgoing viewing key. Outputs already
+/// recovered (same transaction and action index) are left untouched.
+pub fn insert_sent_note(conn: &mut SqliteConnection, note: SentNoteData) {
+    use crate::schema::sent_notes::dsl as sn;
+    diesel::insert_or_ignore_into(sn::sent_notes)
+        .values(&InsertableSentNoteData::from_sent_note_data(note))
+        .execute(conn)
+        .expect("Error saving sent note");
+}
+
+pub fn find_sent_notes_for_tx(conn: &mut SqliteConnection, txid: &[u8]) -> Vec<SentNoteData> {
+    use crate::schema::sent_notes::dsl as sn;
+    sn::sent_notes
+        .filter(sn::tx_id.eq(txid))
+        .order(sn::action_index)
+        .select(SentNoteData::as_select())
+        .load(conn)
+        .expect("Error loading sent notes")
+}
+
+pub fn delete_all_sent_notes(conn: &mut SqliteConnection) {
+    use crate::schema::sent_notes::dsl as sn;
+    diesel::delete(sn::sent_notes)
+        .execute(conn)
+        .expect("Error deleting sent notes");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::db;
+    use crate::components::persistence::model::NoteState;
+    use tempfile::NamedTempFile;
+
+    fn test_note(nf: u8) -> NoteData {
+        NoteData {
+            id: 0,
+            amount: 10,
+            asset: AssetBase::zatoshi().to_bytes().to_vec(),
+            tx_id: vec![1; 32],
+            action_index: nf as i32,
+            position: nf as i64,
+            memo: vec![0; 512],
+            rho: vec![0; 32],
+            nullifier: vec![nf; 32],
+            rseed: vec![0; 32],
+            recipient_address: vec![0; 43],
+            spend_tx_id: None,
+            spend_action_index: -1,
+            mined_height: Some(5),
+            spend_height: None,
+            locked_by_tx_id: None,
+            lock_expiry_height: None,
+        }
+    }
+
+    fn test_sent_note(action_index: i32, amount: i64) -> SentNoteData {
+        SentNoteData {
+            id: 0,
+            tx_id: vec![3; 32],
+            action_index,
+            sender_account: 0,
+            recipient_address: vec![0; 43],
+            recipient_account: None,
+            amount,
+            asset: AssetBase::zatoshi().to_bytes().to_vec(),
+            memo: vec![0xF6; 512],
+            mined_height: 8,
+        }
+    }
+
+    #[test]
+    fn test_note_locking_lifecycle() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        let first = insert_note(&mut c, test_note(1));
+        let second = insert_note(&mut c, test_note(2));
+        assert_eq!(first.state(), NoteState::Unspent);
+        assert_eq!(first.confirmations(5), 1);
+        assert_eq!(first.confirmations(14), 10);
+
+        let pending_tx = TxId::from_bytes([7; 32]);
+        lock_notes(&mut c, &[first.id], &pending_tx, 20);
+
+        let spendable = find_spendable_notes_for_asset(&mut c, AssetBase::zatoshi());
+        assert_eq!(spendable.len(), 1);
+        assert_eq!(spendable[0].id, second.id);
+        // Locked notes still count towards the balance.
+        assert_eq!(
+            find_non_spent_notes_for_asset(&mut c, AssetBase::zatoshi()).len(),
+            2
+        );
+
+        // The transaction can still be mined at its expiry height.
+        assert_eq!(unlock_expired_notes(&mut c, 20), 0);
+        assert_eq!(unlock_expired_notes(&mut c, 21), 1);
+        assert_eq!(
+            find_spendable_notes_for_asset(&mut c, AssetBase::zatoshi()).len(),
+            2
+        );
+
+        // Observing the spend on chain clears the lock and records the height.
+        lock_notes(&mut c, &[second.id], &pending_tx, 30);
+        mark_as_potentially_spent(&mut c, second.id, &pending_tx, 0, 25);
+        let spent = find_by_nullifier(&mut c, &Nullifier::from_bytes(&[2; 32]).unwrap());
+        let spent = spent.unwrap();
+        assert_eq!(spent.state(), NoteState::Spent);
+        assert_eq!(spent.spend_height, Some(25));
+        assert!(spent.locked_by_tx_id.is_none());
+        assert_eq!(unlock_expired_notes(&mut c, 31), 0);
+    }
+
+    #[test]
+    fn test_sent_notes() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        insert_sent_note(&mut c, test_sent_note(1, 40));
+        insert_sent_note(&mut c, test_sent_note(0, 60));
+        // Re-syncing the same block does not duplicate recovered outputs.
+        insert_sent_note(&mut c, test_sent_note(1, 40));
+
+        let sent = find_sent_notes_for_tx(&mut c, &[3; 32]);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].action_index, 0);
+        assert_eq!(sent[1].amount, 40);
+        assert!(find_sent_notes_for_tx(&mut c, &[4; 32]).is_empty());
+
+        delete_all_sent_notes(&mut c);
+        assert!(find_sent_notes_for_tx(&mut c, &[3; 32]).is_empty());
+    }
+}
@@ -0,0 +1,392 @@
+//! Randomized OrchardZSA scenarios.
+//!
+//! A [`Generator`] draws, from a seed, blocks of issue, transfer, burn and
+//! finalize operations between a number of accounts and assets. Every
+//! operation it draws is valid against the [`Model`] it keeps alongside: an
+//! independent record of the balance of every account and of the issued,
+//! burnt and finalized state of every asset, which the wallet must agree with
+//! once the block is mined.
+//!
+//! Operations of a block are drawn so that they can all be built before the
+//! block is mined: each (account, asset) pair is spent from at most once per
+//! block, and never for more than its balance at the start of the block,
+//! since the wallet cannot spend notes received or locked in the same block.
+//! An asset is only issued again, or finalized, in a later block than its
+//! first issuance, and is not finalized in a block that also issues it.
+
+use std::collections::BTreeSet;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Upper bound of randomly issued amounts.
+pub const MAX_ISSUE_AMOUNT: u64 = 1_000;
+
+/// One operation of a randomized scenario. Accounts and assets are indices
+/// into the scenario's accounts and assets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Operation {
+    /// Issue `amount` of `asset` to `recipient`; `first` for the asset's
+    /// first issuance.
+    Issue {
+        asset: usize,
+        recipient: usize,
+        amount: u64,
+        first: bool,
+    },
+    Transfer {
+        asset: usize,
+        from: usize,
+        to: usize,
+        amount: u64,
+    },
+    Burn {
+        asset: usize,
+        account: usize,
+        amount: u64,
+    },
+    /// Finalize `asset`, after which it cannot be issued anymore.
+    Finalize { asset: usize },
+}
+
+/// Expected state of a randomized scenario.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Model {
+    /// Balance of every asset, by account then asset.
+    balances: Vec<Vec<u64>>,
+    issued: Vec<u64>,
+    burnt: Vec<u64>,
+    finalized: Vec<bool>,
+}
+
+impl Model {
+    pub fn new(accounts: usize, assets: usize) -> Self {
+        Model {
+            balances: vec![vec![0; assets]; accounts],
+            issued: vec![0; assets],
+            burnt: vec![0; assets],
+            finalized: vec![false; assets],
+        }
+    }
+
+    pub fn accounts(&self) -> usize {
+        self.balances.len()
+    }
+
+    pub fn assets(&self) -> usize {
+        self.issued.len()
+    }
+
+    pub fn balance(&self, account: usize, asset: usize) -> u64 {
+        self.balances[account][asset]
+    }
+
+    /// Amount of `asset` issued and not burnt.
+    pub fn supply(&self, asset: usize) -> u64 {
+        self.issued[asset] - self.burnt[asset]
+    }
+
+    pub fn is_issued(&self, asset: usize) -> bool {
+        self.issued[asset] > 0
+    }
+
+    pub fn is_finalized(&self, asset: usize) -> bool {
+        self.finalized[asset]
+    }
+
+    /// Apply `op`.
+    ///
+    /// Panics if `op` is not valid in the current state: spending more than
+    /// the balance, or issuing or finalizing a finalized asset.
+    pub fn apply(&mut self, op: &Operation) {
+        match *op {
+            Operation::Issue {
+                asset,
+                recipient,
+                amount,
+                first,
+            } => {
+                assert!(!self.finalized[asset], "issue of finalized asset {asset}");
+                assert_eq!(first, !self.is_issued(asset), "first issuance of {asset}");
+                self.issued[asset] += amount;
+                self.balances[recipient][asset] += amount;
+            }
+            Operation::Transfer {
+                asset,
+                from,
+                to,
+                amount,
+            } => {
+                self.debit(from, asset, amount);
+                self.balances[to][asset] += amount;
+            }
+            Operation::Burn {
+                asset,
+                account,
+                amount,
+            } => {
+                self.debit(account, asset, amount);
+                self.burnt[asset] += amount;
+            }
+            Operation::Finalize { asset } => {
+                assert!(!self.finalized[asset], "asset {asset} finalized twice");
+                self.finalized[asset] = true;
+            }
+        }
+    }
+
+    fn debit(&mut self, account: usize, asset: usize, amount: u64) {
+        let balance = &mut self.balances[account][asset];
+        assert!(
+            *balance >= amount,
+            "account {account} spends {amount} of asset {asset} but holds {balance}"
+        );
+        *balance -= amount;
+    }
+}
+
+/// Seeded generator of valid operations.
+pub struct Generator {
+    rng: StdRng,
+    model: Model,
+}
+
+impl Generator {
+    pub fn new(seed: u64, accounts: usize, assets: usize) -> Self {
+        assert!(accounts >= 2, "at least 2 accounts are needed");
+        Generator {
+            rng: StdRng::seed_from_u64(seed),
+            model: Model::new(accounts, assets),
+        }
+    }
+
+    /// The expected state after all blocks drawn so far.
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// Draw a block of at most `max_ops` operations and apply it to the model.
+    /// The first block of a scenario issues every asset.
+    pub fn next_block(&mut self, max_ops: usize) -> Vec<Operation> {
+        let start = self.model.clone();
+        let mut ops: Vec<Operation> = (0..start.assets())
+            .filter(|&asset| !start.is_issued(asset))
+            .map(|asset| self.issue(asset))
+            .collect();
+        for op in &ops {
+            self.model.apply(op);
+        }
+
+        let mut spent = BTreeSet::new();
+        let mut issued: BTreeSet<usize> = ops.iter().filter_map(issued_asset).collect();
+        while ops.len() < max_ops {
+            let Some(op) = self.draw(&start, &spent, &issued) else {
+                break;
+            };
+            match op {
+                Operation::Issue { asset, .. } | Operation::Finalize { asset } => {
+                    issued.insert(asset);
+                }
+                Operation::Transfer {
+                    asset,
+                    from: account,
+                    ..
+                }
+                | Operation::Burn { asset, account, .. } => {
+                    spent.insert((account, asset));
+                }
+            }
+            self.model.apply(&op);
+            ops.push(op);
+        }
+        ops
+    }
+
+    /// Draw one operation: mostly transfers, then burns and issues, rarely a
+    /// finalization. Returns `None` once no operation is possible.
+    fn draw(
+        &mut self,
+        start: &Model,
+        spent: &BTreeSet<(usize, usize)>,
+        issued: &BTreeSet<usize>,
+    ) -> Option<Operation> {
+        let spendable: Vec<(usize, usize)> = (0..start.accounts())
+            .flat_map(|account| (0..start.assets()).map(move |asset| (account, asset)))
+            .filter(|&(account, asset)| start.balance(account, asset) > 0)
+            .filter(|pair| !spent.contains(pair))
+            .collect();
+        let issuable: Vec<usize> = (0..self.model.assets())
+            .filter(|&asset| start.is_issued(asset) && !self.model.is_finalized(asset))
+            .collect();
+        let finalizable: Vec<usize> = issuable
+            .iter()
+            .copied()
+            .filter(|asset| !issued.contains(asset))
+            .collect();
+
+        // Fall back to the other kinds when the drawn one is not possible.
+        let first = self.rng.gen_range(0..10);
+        for roll in (first..10).chain(0..first) {
+            match roll {
+                0..=4 if !spendable.is_empty() => {
+                    let (from, asset) = spendable[self.rng.gen_range(0..spendable.len())];
+                    let to = (from + self.rng.gen_range(1..start.accounts())) % start.accounts();
+                    let amount = self.rng.gen_range(1..=start.balance(from, asset));
+                    return Some(Operation::Transfer {
+                        asset,
+                        from,
+                        to,
+                        amount,
+                    });
+                }
+                5..=6 if !spendable.is_empty() => {
+                    let (account, asset) = spendable[self.rng.gen_range(0..spendable.len())];
+                    let amount = self.rng.gen_range(1..=start.balance(account, asset));
+                    return Some(Operation::Burn {
+                        asset,
+                        account,
+                        amount,
+                    });
+                }
+                7..=8 if !issuable.is_empty() => {
+                    let asset = issuable[self.rng.gen_range(0..issuable.len())];
+                    return Some(self.issue(asset));
+                }
+                9 if !finalizable.is_empty() => {
+                    let asset = finalizable[self.rng.gen_range(0..finalizable.len())];
+                    return Some(Operation::Finalize { asset });
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn issue(&mut self, asset: usize) -> Operation {
+        Operation::Issue {
+            asset,
+            recipient: self.rng.gen_range(0..self.model.accounts()),
+            amount: self.rng.gen_range(1..=MAX_ISSUE_AMOUNT),
+            first: !self.model.is_issued(asset),
+        }
+    }
+}
+
+fn issued_asset(op: &Operation) -> Option<usize> {
+    match op {
+        Operation::Issue { asset, .. } => Some(*asset),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(seed: u64, count: usize) -> Vec<Vec<Operation>> {
+        let mut generator = Generator::new(seed, 3, 2);
+        (0..count).map(|_| generator.next_block(6)).collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_scenario() {
+        assert_eq!(blocks(7, 20), blocks(7, 20));
+        assert_ne!(blocks(7, 20), blocks(8, 20));
+    }
+
+    #[test]
+    fn test_first_block_issues_every_asset() {
+        let block = Generator::new(1, 3, 2).next_block(6);
+        for (asset, op) in block.iter().take(2).enumerate() {
+            assert!(matches!(op, Operation::Issue { asset: a, first: true, .. } if *a == asset));
+        }
+        assert!(!block[2..].iter().any(|op| issued_asset(op).is_some()));
+    }
+
+    #[test]
+    fn test_blocks_are_buildable_before_mining() {
+        for seed in 0..50 {
+            let mut generator = Generator::new(seed, 3, 2);
+            for _ in 0..30 {
+                let start = generator.model().clone();
+                let block = generator.next_block(6);
+                assert!(block.len() <= 6);
+
+                let mut spent = BTreeSet::new();
+                for op in &block {
+                    match *op {
+                        Operation::Transfer {
+                            asset,
+                            from: account,
+                            amount,
+                            ..
+                        }
+                        | Operation::Burn {
+                            asset,
+                            account,
+                            amount,
+                        } => {
+                            assert!(spent.insert((account, asset)), "seed {seed}: {block:?}");
+                            assert!(amount > 0 && amount <= start.balance(account, asset));
+                        }
+                        Operation::Finalize { asset } => {
+                            assert!(!block.iter().any(|op| issued_asset(op) == Some(asset)));
+                        }
+                        Operation::Issue { .. } => {}
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_balances_add_up_to_supply() {
+        for seed in 0..50 {
+            let mut generator = Generator::new(seed, 4, 3);
+            for _ in 0..30 {
+                generator.next_block(8);
+                let model = generator.model();
+                for asset in 0..model.assets() {
+                    let held: u64 = (0..model.accounts())
+                        .map(|account| model.balance(account, asset))
+                        .sum();
+                    assert_eq!(held, model.supply(asset), "seed {seed}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_issue_after_finalization() {
+        for seed in 0..50 {
+            let mut generator = Generator::new(seed, 3, 2);
+            let mut finalized = BTreeSet::new();
+            for _ in 0..50 {
+                for op in generator.next_block(6) {
+                    match op {
+                        Operation::Issue { asset, .. } => assert!(!finalized.contains(&asset)),
+                        Operation::Finalize { asset } => assert!(finalized.insert(asset)),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "spends 2 of asset 0 but holds 1")]
+    fn test_model_rejects_overdraw() {
+        let mut model = Model::new(2, 1);
+        model.apply(&Operation::Issue {
+            asset: 0,
+            recipient: 0,
+            amount: 1,
+            first: true,
+        });
+        model.apply(&Operation::Burn {
+            asset: 0,
+            account: 0,
+            amount: 2,
+        });
+    }
+}
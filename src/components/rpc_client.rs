@@ -4,18 +4,24 @@ pub mod reqwest;
 use std::convert::TryInto;
 use std::error::Error;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
 use zcash_encoding::{CompactSize, Vector};
 use zcash_primitives::block::{BlockHash, BlockHeader};
 use zcash_primitives::transaction::{Transaction, TxId};
 
-use zcash_protocol::consensus::BlockHeight;
+use zcash_protocol::consensus::{BlockHeight, BranchId, REGTEST_NETWORK};
 
-use crate::model::Block;
+use crate::model::{Block, FullBlock, TreeState};
 
-pub trait RpcClient {
+/// `Sync` so that blocks can be fetched from several threads during sync.
+pub trait RpcClient: Sync {
     fn get_best_block_hash(&self) -> Result<BlockHash, Box<dyn Error>>;
     fn get_block(&self, height: u32) -> Result<Block, Box<dyn Error>>;
+    /// Fetch the block at `height` with its header and all of its
+    /// transactions in a single call (`getblock` with verbosity 0).
+    fn get_full_block(&self, height: u32) -> Result<FullBlock, Box<dyn Error>>;
+    /// The note commitment tree state after the block at `height` (`z_gettreestate`).
+    fn get_tree_state(&self, height: u32) -> Result<TreeState, Box<dyn Error>>;
     fn send_transaction(&mut self, tx: Transaction) -> Result<TxId, Box<dyn Error>>;
     fn get_transaction(&self, txid: &TxId) -> Result<Transaction, Box<dyn Error>>;
     fn get_block_template(&self) -> Result<BlockTemplate, Box<dyn Error>>;
@@ -43,6 +49,42 @@ pub struct GetBlock {
 
     /// List of transaction IDs in block order, hex-encoded.
     tx: Vec<String>,
+
+    /// The hash of the previous block in hex, absent for the genesis block
+    /// and on nodes that do not report it.
+    #[serde(rename = "previousblockhash", skip_serializing_if = "Option::is_none")]
+    previous_block_hash: Option<String>,
+}
+
+/// A `z_gettreestate` RPC response.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GetTreeState {
+    /// The hash of the block, hex-encoded.
+    pub hash: String,
+
+    /// The height of the block.
+    pub height: u32,
+
+    /// The Sapling note commitment tree state.
+    #[serde(default)]
+    pub sapling: Option<GetTreeStatePool>,
+
+    /// The Orchard note commitment tree state.
+    #[serde(default)]
+    pub orchard: Option<GetTreeStatePool>,
+}
+
+/// The note commitment tree state of a single shielded pool.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GetTreeStatePool {
+    pub commitments: GetTreeStateCommitments,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GetTreeStateCommitments {
+    /// The serialized `CommitmentTree`, hex-encoded. Absent for an empty tree.
+    #[serde(rename = "finalState", default)]
+    pub final_state: Option<String>,
 }
 
 /// A serialized `getblocktemplate` RPC response in template mode.
@@ -336,3 +378,23 @@ pub(crate) fn decode_hex(hex: String) -> [u8; 32] {
     result_vec.reverse();
     result_vec.try_into().unwrap()
 }
+
+/// Parse a serialized block (header followed by its transactions), as returned
+/// by `getblock` with verbosity 0. Transactions are read with the consensus
+/// branch ID active at `height`.
+pub(crate) fn read_full_block<R: Read>(mut reader: R, height: u32) -> io::Result<FullBlock> {
+    let height = BlockHeight::from_u32(height);
+    let branch_id = BranchId::for_height(&REGTEST_NETWORK, height);
+    let header = BlockHeader::read(&mut reader)?;
+    let transactions = Vector::read(&mut reader, |r| Transaction::read(r, branch_id))?;
+    // `getblock` reports hashes in display (big-endian) order; keep the same
+    // byte order as `Block::hash` so both can be compared and stored alike.
+    let mut hash = header.hash().0;
+    hash.reverse();
+    Ok(FullBlock {
+        hash: BlockHash(hash),
+        height,
+        header,
+        transactions,
+    })
+}
@@ -1,5 +1,6 @@
+use crate::components::block_commitment::TxMerkleRoot;
 use crate::components::rpc_client::{BlockProposal, BlockTemplate, RpcClient};
-use crate::model::Block;
+use crate::model::{Block, FullBlock, TreeState};
 use crate::prelude::info;
 use rand::rngs::OsRng;
 use rand::RngCore;
@@ -7,7 +8,7 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::io;
 use std::io::ErrorKind;
-use zcash_primitives::block::BlockHash;
+use zcash_primitives::block::{BlockHash, BlockHeader, BlockHeaderData};
 use zcash_protocol::consensus::{BlockHeight, BranchId};
 use zcash_primitives::transaction::{Transaction, TxId};
 
@@ -54,6 +55,53 @@ impl RpcClient for MockZcashNode {
             .cloned()
     }
 
+    fn get_full_block(&self, height: u32) -> Result<FullBlock, Box<dyn Error>> {
+        let block = self.get_block(height)?;
+        let transactions = block
+            .tx_ids
+            .iter()
+            .map(|txid| self.get_transaction(txid))
+            .collect::<Result<Vec<_>, _>>()?;
+        // The mock does not keep headers, synthesize one committing to the
+        // block's transactions.
+        let merkle_root = if transactions.is_empty() {
+            [0; 32]
+        } else {
+            transactions
+                .iter()
+                .map(|tx| *tx.txid().as_ref())
+                .collect::<TxMerkleRoot>()
+                .0
+        };
+        // Block hashes are kept in display order, headers use internal order.
+        let mut prev_block = block.previous_block_hash.0;
+        prev_block.reverse();
+        let header = BlockHeader::from_data(BlockHeaderData {
+            version: 4,
+            prev_block: BlockHash(prev_block),
+            merkle_root,
+            final_sapling_root: [0; 32],
+            time: 0,
+            bits: 0,
+            nonce: [0; 32],
+            solution: vec![],
+        })?;
+        Ok(FullBlock {
+            hash: block.hash,
+            height: block.height,
+            header,
+            transactions,
+        })
+    }
+
+    fn get_tree_state(&self, _height: u32) -> Result<TreeState, Box<dyn Error>> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "The mock node does not track note commitment trees",
+        )
+        .into())
+    }
+
     fn send_transaction(&mut self, tx: Transaction) -> Result<TxId, Box<dyn Error>> {
         let txid = tx.txid();
         let mut tx_bytes = vec![];
@@ -112,7 +160,7 @@ impl RpcClient for MockZcashNode {
             height: BlockHeight::from_u32(len as u32),
             confirmations: 0,
             tx_ids: block.transactions.iter().map(|tx| tx.txid()).collect(),
-            previous_block_hash: BlockHash::from_slice(&[len as u8; 32]),
+            previous_block_hash: self.blockchain.last().unwrap().hash,
         });
 
         // Step 3: Insert the collected transactions into the self.transactions map
@@ -1,5 +1,7 @@
-use crate::components::rpc_client::{BlockProposal, BlockTemplate, GetBlock, RpcClient};
-use crate::model::Block;
+use crate::components::rpc_client::{
+    read_full_block, BlockProposal, BlockTemplate, GetBlock, GetTreeState, RpcClient,
+};
+use crate::model::{Block, FullBlock, TreeState};
 use crate::prelude::{debug, info};
 use reqwest::blocking::Client;
 use serde::de::DeserializeOwned;
@@ -102,7 +104,43 @@ impl RpcClient for ReqwestRpcClient {
                     )
                 })
                 .collect(),
-            previous_block_hash: BlockHash([0; 32]), // Previous block hash is not yet implemented in Zebra
+            previous_block_hash: match block.previous_block_hash {
+                Some(hash) => BlockHash(hex::decode(hash)?.as_slice().try_into()?),
+                None => BlockHash([0; 32]),
+            },
+        })
+    }
+
+    fn get_full_block(&self, height: u32) -> Result<FullBlock, Box<dyn Error>> {
+        let params: Vec<ParamType> = vec![
+            ParamType::String(height.to_string()), // Height
+            ParamType::Number(0),                  // Verbosity
+        ];
+        let block_hex: String = self.request(&RpcRequest::new_with_params("getblock", params))?;
+        let block_bytes = hex::decode(block_hex)?;
+        Ok(read_full_block(block_bytes.as_slice(), height)?)
+    }
+
+    fn get_tree_state(&self, height: u32) -> Result<TreeState, Box<dyn Error>> {
+        let params: Vec<ParamType> = vec![
+            ParamType::String(height.to_string()), // Height
+        ];
+        let state: GetTreeState =
+            self.request(&RpcRequest::new_with_params("z_gettreestate", params))?;
+
+        Ok(TreeState {
+            hash: BlockHash(hex::decode(state.hash)?.as_slice().try_into()?),
+            height: BlockHeight::from_u32(state.height),
+            orchard_tree: state
+                .orchard
+                .and_then(|o| o.commitments.final_state)
+                .map(hex::decode)
+                .transpose()?,
+            sapling_tree: state
+                .sapling
+                .and_then(|s| s.commitments.final_state)
+                .map(hex::decode)
+                .transpose()?,
         })
     }
 
@@ -139,11 +177,6 @@ impl RpcClient for ReqwestRpcClient {
 
         let block_hex = hex::encode(&block_bytes);
 
-        // Opt-in block dump for external extraction.
-        if std::env::var_os("ZSA_DUMP_BLOCKS").is_some() {
-            println!("ZSA_BLOCK_HEX {block_hex}");
-        }
-
         let result = self.request(&RpcRequest::new_with_params(
             "submitblock",
             vec![ParamType::String(block_hex)],
@@ -0,0 +1,369 @@
+//! Sapling accounts of the wallet and the notes they receive.
+//!
+//! Every wallet account has a Sapling spending key, derived from the wallet
+//! seed along the ZIP-32 path `m/32'/coin_type'/account'`, and receives at
+//! its default address; change goes back to that address as well. Sync
+//! trial-decrypts Sapling outputs with the accounts' incoming viewing keys and
+//! stores the notes in `sapling_notes`.
+//!
+//! Unlike the Orchard tree, the Sapling note commitment tree is kept as a
+//! frontier plus one incremental witness per unspent note, which every new
+//! commitment is appended to. Both are small, so they are rewritten as a
+//! whole after each block.
+//!
+//! Free functions take `&mut SqliteConnection` so callers can participate in
+//! a transaction.
+
+use diesel::prelude::*;
+use sapling_crypto::note_encryption::Zip212Enforcement;
+use sapling_crypto::value::NoteValue;
+use sapling_crypto::zip32::ExtendedSpendingKey;
+use sapling_crypto::{CommitmentTree, IncrementalWitness, Note, PaymentAddress, Rseed};
+use zcash_primitives::merkle_tree::{
+    read_commitment_tree, read_incremental_witness, write_commitment_tree,
+    write_incremental_witness,
+};
+use zcash_protocol::constants;
+use zip32::ChildIndex;
+
+use crate::components::locks;
+use crate::schema::{sapling_notes, sapling_tree_state};
+
+/// ZIP-212 is active from Canopy on, which regtest activates at height 1, so
+/// every Sapling note the wallet sees uses the new note plaintext format.
+pub const ZIP212_ENFORCEMENT: Zip212Enforcement = Zip212Enforcement::On;
+
+/// The Sapling spending key of `account`.
+pub fn account_spending_key(seed: &[u8; 64], account: usize) -> ExtendedSpendingKey {
+    ExtendedSpendingKey::from_path(
+        &ExtendedSpendingKey::master(seed),
+        &[
+            ChildIndex::hardened(32),
+            ChildIndex::hardened(constants::regtest::COIN_TYPE),
+            ChildIndex::hardened(account as u32),
+        ],
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = sapling_notes)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct SaplingNoteData {
+    pub txid: Vec<u8>,
+    pub output_index: i32,
+    pub account: i32,
+    pub amount: i64,
+    pub recipient: Vec<u8>,
+    pub rseed: Vec<u8>,
+    pub nullifier: Vec<u8>,
+    pub memo: Vec<u8>,
+    /// The serialized incremental witness of the note commitment.
+    pub witness: Vec<u8>,
+    pub mined_height: i32,
+    pub spend_tx_id: Option<Vec<u8>>,
+    pub spend_height: Option<i32>,
+    pub locked_by_tx_id: Option<Vec<u8>>,
+    pub lock_expiry_height: Option<i32>,
+}
+
+impl SaplingNoteData {
+    /// An unspent note received in output `output_index` of `txid`, mined at
+    /// `mined_height`. Returns `None` for notes predating ZIP-212.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        txid: [u8; 32],
+        output_index: usize,
+        account: usize,
+        note: &Note,
+        nullifier: [u8; 32],
+        memo: [u8; 512],
+        witness: &IncrementalWitness,
+        mined_height: u32,
+    ) -> Option<Self> {
+        let Rseed::AfterZip212(rseed) = note.rseed() else {
+            return None;
+        };
+        Some(SaplingNoteData {
+            txid: txid.to_vec(),
+            output_index: output_index as i32,
+            account: account as i32,
+            amount: note.value().inner() as i64,
+            recipient: note.recipient().to_bytes().to_vec(),
+            rseed: rseed.to_vec(),
+            nullifier: nullifier.to_vec(),
+            memo: memo.to_vec(),
+            witness: encode_witness(witness),
+            mined_height: mined_height as i32,
+            spend_tx_id: None,
+            spend_height: None,
+            locked_by_tx_id: None,
+            lock_expiry_height: None,
+        })
+    }
+
+    pub fn note(&self) -> Note {
+        let recipient =
+            PaymentAddress::from_bytes(self.recipient.as_slice().try_into().unwrap()).unwrap();
+        Note::from_parts(
+            recipient,
+            NoteValue::from_raw(self.amount as u64),
+            Rseed::AfterZip212(self.rseed.as_slice().try_into().unwrap()),
+        )
+    }
+
+    pub fn witness(&self) -> Result<IncrementalWitness, String> {
+        read_incremental_witness(self.witness.as_slice())
+            .map_err(|e| format!("Invalid Sapling witness: {e}"))
+    }
+}
+
+fn encode_witness(witness: &IncrementalWitness) -> Vec<u8> {
+    let mut bytes = vec![];
+    write_incremental_witness(witness, &mut bytes).expect("writing to a Vec does not fail");
+    bytes
+}
+
+/// Store a received note. Notes that are already known are left as they are.
+pub fn insert(conn: &mut SqliteConnection, note: &SaplingNoteData) {
+    use crate::schema::sapling_notes::dsl::*;
+    diesel::insert_into(sapling_notes)
+        .values(note)
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .expect("Error inserting Sapling note");
+}
+
+/// The stored note with the given nullifier, spent or not.
+pub fn find_by_nullifier(conn: &mut SqliteConnection, nf: &[u8; 32]) -> Option<SaplingNoteData> {
+    use crate::schema::sapling_notes::dsl::*;
+    sapling_notes
+        .filter(nullifier.eq(nf.to_vec()))
+        .select(SaplingNoteData::as_select())
+        .first(conn)
+        .optional()
+        .expect("Error loading Sapling note")
+}
+
+/// Mark the note with nullifier `nf` as spent by `spend_txid` in the block at
+/// `height` and release its lock. Returns whether it belongs to the wallet.
+pub fn mark_spent(
+    conn: &mut SqliteConnection,
+    nf: &[u8; 32],
+    spend_txid: &[u8; 32],
+    height: u32,
+) -> bool {
+    use crate::schema::sapling_notes::dsl::*;
+    diesel::update(sapling_notes)
+        .filter(nullifier.eq(nf.to_vec()))
+        .set((
+            spend_tx_id.eq(spend_txid.to_vec()),
+            spend_height.eq(height as i32),
+            locked_by_tx_id.eq(None::<Vec<u8>>),
+            lock_expiry_height.eq(None::<i32>),
+        ))
+        .execute(conn)
+        .expect("Error marking Sapling note spent")
+        > 0
+}
+
+/// The unspent notes of all accounts, oldest first.
+pub fn unspent(conn: &mut SqliteConnection) -> Vec<SaplingNoteData> {
+    use crate::schema::sapling_notes::dsl::*;
+    sapling_notes
+        .filter(spend_tx_id.is_null())
+        .order((mined_height, txid, output_index))
+        .select(SaplingNoteData::as_select())
+        .load(conn)
+        .expect("Error loading Sapling notes")
+}
+
+/// The unspent, unlocked notes of account `owner`, oldest first. Their
+/// witnesses are only parsed by [`SaplingNoteData::witness`], for the notes
+/// actually spent.
+pub fn spendable(conn: &mut SqliteConnection, owner: usize) -> Vec<SaplingNoteData> {
+    use crate::schema::sapling_notes::dsl::*;
+    sapling_notes
+        .filter(
+            account
+                .eq(owner as i32)
+                .and(spend_tx_id.is_null())
+                .and(locked_by_tx_id.is_null()),
+        )
+        .order((mined_height, txid, output_index))
+        .select(SaplingNoteData::as_select())
+        .load(conn)
+        .expect("Error loading Sapling notes")
+}
+
+/// The sum of the unspent notes of account `owner`, including locked ones.
+pub fn balance(conn: &mut SqliteConnection, owner: usize) -> u64 {
+    use crate::schema::sapling_notes::dsl::*;
+    sapling_notes
+        .filter(account.eq(owner as i32).and(spend_tx_id.is_null()))
+        .select(amount)
+        .load::<i64>(conn)
+        .expect("Error loading Sapling notes")
+        .into_iter()
+        .map(|value| value as u64)
+        .sum()
+}
+
+/// Replace the stored witness of `note`.
+pub fn update_witness(
+    conn: &mut SqliteConnection,
+    note: &SaplingNoteData,
+    new_witness: &IncrementalWitness,
+) {
+    use crate::schema::sapling_notes::dsl::*;
+    diesel::update(sapling_notes)
+        .filter(txid.eq(&note.txid))
+        .filter(output_index.eq(note.output_index))
+        .set(witness.eq(encode_witness(new_witness)))
+        .execute(conn)
+        .expect("Error updating Sapling witness");
+}
+
+/// Lock the notes with the given nullifiers for the transaction `lock_txid`
+/// expiring at `expiry_height`.
+pub fn lock(
+    conn: &mut SqliteConnection,
+    nullifiers: &[Vec<u8>],
+    lock_txid: &[u8; 32],
+    expiry_height: u32,
+) {
+    use crate::schema::sapling_notes::dsl::*;
+    diesel::update(sapling_notes)
+        .filter(nullifier.eq_any(nullifiers).and(spend_tx_id.is_null()))
+        .set((
+            locked_by_tx_id.eq(lock_txid.to_vec()),
+            lock_expiry_height.eq(expiry_height as i32),
+        ))
+        .execute(conn)
+        .expect("Error locking Sapling notes");
+}
+
+/// Release locks whose transaction can no longer be mined at `height`.
+/// Returns the number of notes that became spendable again.
+pub fn unlock_expired(conn: &mut SqliteConnection, height: u32) -> usize {
+    locks::unlock_expired(conn, "sapling_notes", height)
+}
+
+/// Persist the frontier of the Sapling note commitment tree.
+pub fn save_tree(conn: &mut SqliteConnection, tree: &CommitmentTree) -> Result<(), String> {
+    let mut bytes = vec![];
+    write_commitment_tree(tree, &mut bytes).expect("writing to a Vec does not fail");
+    diesel::replace_into(sapling_tree_state::table)
+        .values((
+            sapling_tree_state::id.eq(1),
+            sapling_tree_state::tree.eq(bytes),
+        ))
+        .execute(conn)
+        .map_err(|e| format!("Failed to save Sapling tree: {e}"))?;
+    Ok(())
+}
+
+/// Load the stored Sapling note commitment tree, if any.
+pub fn load_tree(conn: &mut SqliteConnection) -> Result<Option<CommitmentTree>, String> {
+    let bytes: Option<Vec<u8>> = sapling_tree_state::table
+        .select(sapling_tree_state::tree)
+        .first(conn)
+        .optional()
+        .map_err(|e| format!("Failed to query sapling_tree_state: {e}"))?;
+    bytes
+        .map(|bytes| {
+            read_commitment_tree(bytes.as_slice())
+                .map_err(|e| format!("Invalid Sapling tree state: {e}"))
+        })
+        .transpose()
+}
+
+/// Forget all Sapling notes and the stored tree.
+pub fn clear(conn: &mut SqliteConnection) {
+    diesel::delete(sapling_notes::table)
+        .execute(conn)
+        .expect("Error clearing Sapling notes");
+    diesel::delete(sapling_tree_state::table)
+        .execute(conn)
+        .expect("Error clearing Sapling tree");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::db;
+    use crate::components::history_tree::EMPTY_SAPLING_ROOT;
+    use sapling_crypto::Node;
+    use tempfile::NamedTempFile;
+
+    fn note_data(id: u8, account: usize, amount: u64, tree: &CommitmentTree) -> SaplingNoteData {
+        let extsk = account_spending_key(&[id; 64], account);
+        let (_, address) = extsk.to_diversifiable_full_viewing_key().default_address();
+        let note = Note::from_parts(
+            address,
+            NoteValue::from_raw(amount),
+            Rseed::AfterZip212([id; 32]),
+        );
+        let witness = IncrementalWitness::from_tree(tree.clone()).unwrap();
+        SaplingNoteData::new(
+            [id; 32], 0, account, &note, [id; 32], [0; 512], &witness, 10,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_empty_tree_root() {
+        assert_eq!(
+            CommitmentTree::empty().root().to_bytes(),
+            EMPTY_SAPLING_ROOT
+        );
+    }
+
+    #[test]
+    fn test_sapling_note_operations() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        assert!(load_tree(&mut c).unwrap().is_none());
+        let mut tree = CommitmentTree::empty();
+        tree.append(Node::from_bytes([1; 32]).unwrap()).unwrap();
+        save_tree(&mut c, &tree).unwrap();
+        assert_eq!(load_tree(&mut c).unwrap().unwrap().root(), tree.root());
+
+        let first = note_data(1, 0, 50, &tree);
+        let second = note_data(2, 0, 20, &tree);
+        let other = note_data(3, 1, 7, &tree);
+        for note in [&first, &second, &other] {
+            insert(&mut c, note);
+        }
+        assert_eq!(first.note().value().inner(), 50);
+        assert_eq!(balance(&mut c, 0), 70);
+        assert_eq!(balance(&mut c, 1), 7);
+
+        // Appending to a witness keeps it at the tree root.
+        let mut witness = first.witness().unwrap();
+        let node = Node::from_bytes([2; 32]).unwrap();
+        tree.append(node).unwrap();
+        witness.append(node).unwrap();
+        update_witness(&mut c, &first, &witness);
+        let stored = find_by_nullifier(&mut c, &[1; 32]).unwrap();
+        assert_eq!(stored.witness().unwrap().root(), tree.root());
+
+        lock(&mut c, &[second.nullifier.clone()], &[9; 32], 15);
+        assert_eq!(spendable(&mut c, 0).len(), 1);
+        assert_eq!(unlock_expired(&mut c, 16), 1);
+        assert_eq!(spendable(&mut c, 0).len(), 2);
+
+        // Observing the spend on chain releases the lock.
+        lock(&mut c, &[first.nullifier.clone()], &[9; 32], 20);
+        assert!(mark_spent(&mut c, &[1; 32], &[9; 32], 16));
+        assert!(!mark_spent(&mut c, &[4; 32], &[9; 32], 16));
+        assert_eq!(balance(&mut c, 0), 20);
+        let spent = find_by_nullifier(&mut c, &[1; 32]).unwrap();
+        assert!(spent.locked_by_tx_id.is_none());
+
+        clear(&mut c);
+        assert_eq!(balance(&mut c, 0), 0);
+        assert!(load_tree(&mut c).unwrap().is_none());
+    }
+}
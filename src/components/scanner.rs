@@ -0,0 +1,291 @@
+//! Block download and trial decryption pipeline used by sync.
+//!
+//! Blocks are fetched ahead of the wallet by a pool of worker threads, which
+//! also trial-decrypt every Orchard and Sapling output with the wallet's
+//! incoming viewing keys and try OVK recovery on the Orchard ones. When root verification is enabled they
+//! also fetch the node's Orchard tree root after each block. The results are
+//! handed back to the sync thread strictly in height order, where
+//! [`Wallet::process_scanned_block`] applies them.
+//!
+//! [`Wallet::process_scanned_block`]: crate::components::wallet::Wallet::process_scanned_block
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, sync_channel};
+use std::sync::Mutex;
+use std::thread;
+
+use orchard::bundle::Authorized;
+use orchard::keys::{IncomingViewingKey, OutgoingViewingKey};
+use orchard::primitives::OrchardPrimitives;
+use orchard::tree::MerkleHashOrchard;
+use orchard::{Address, Bundle, Note};
+use sapling_crypto::keys::PreparedIncomingViewingKey;
+use sapling_crypto::note_encryption::try_sapling_note_decryption;
+use zcash_primitives::block::{BlockHash, BlockHeader};
+use zcash_primitives::transaction::{OrchardBundle, Transaction};
+use zcash_protocol::consensus::BlockHeight;
+use zcash_protocol::value::ZatBalance;
+
+use crate::components::rpc_client::RpcClient;
+use crate::components::sapling::ZIP212_ENFORCEMENT;
+use crate::components::tree_state;
+use crate::prelude::info;
+
+/// Maximum number of worker threads fetching and decrypting blocks.
+pub const MAX_SCAN_WORKERS: usize = 8;
+/// Maximum number of blocks fetched ahead of the last applied block.
+pub const SCAN_LOOKAHEAD: usize = 32;
+
+/// An output decrypted with one of the wallet's incoming viewing keys:
+/// action index, key, note, recipient and memo.
+pub type DecryptedOutput = (usize, IncomingViewingKey, Note, Address, [u8; 512]);
+
+/// A Sapling output decrypted with the incoming viewing key of a wallet
+/// account: output index, account, note and memo.
+pub type SaplingDecryptedOutput = (usize, usize, sapling_crypto::Note, [u8; 512]);
+
+/// An output recovered with the outgoing viewing key of a wallet account.
+pub struct RecoveredOutput {
+    pub account: usize,
+    pub action_index: usize,
+    pub note: Note,
+    pub recipient: Address,
+    pub memo: [u8; 512],
+}
+
+/// The keys outputs are trial-decrypted with, detached from the wallet so
+/// they can be shared with the worker threads.
+pub struct ScanKeys {
+    pub ivks: Vec<IncomingViewingKey>,
+    /// Outgoing viewing keys (both scopes) per account.
+    pub ovks: Vec<(usize, Vec<OutgoingViewingKey>)>,
+    /// Sapling incoming viewing keys of the accounts' default addresses.
+    pub sapling_ivks: Vec<(usize, PreparedIncomingViewingKey)>,
+}
+
+/// A transaction with its Orchard and Sapling outputs already decrypted.
+pub struct ScannedTransaction {
+    pub tx: Transaction,
+    pub decrypted: Vec<DecryptedOutput>,
+    pub recovered: Vec<RecoveredOutput>,
+    pub sapling_decrypted: Vec<SaplingDecryptedOutput>,
+}
+
+/// A block whose transactions were scanned, ready to be applied to the wallet.
+pub struct ScannedBlock {
+    pub height: BlockHeight,
+    pub hash: BlockHash,
+    pub transactions: Vec<ScannedTransaction>,
+    /// The block header, when the block was fetched from the node in full.
+    pub header: Option<BlockHeader>,
+    /// The node's Orchard commitment tree root after this block, when the
+    /// wallet verifies its own root against it.
+    pub orchard_root: Option<MerkleHashOrchard>,
+}
+
+impl ScanKeys {
+    /// Trial-decrypt the Orchard outputs of `tx` with the incoming viewing
+    /// keys and try to recover them with the outgoing viewing keys.
+    pub fn decrypt(&self, tx: &Transaction) -> (Vec<DecryptedOutput>, Vec<RecoveredOutput>) {
+        match tx.orchard_bundle() {
+            Some(OrchardBundle::OrchardVanilla(b)) => self.decrypt_bundle(b),
+            Some(OrchardBundle::OrchardZSA(b)) => self.decrypt_bundle(b),
+            None => (vec![], vec![]),
+        }
+    }
+
+    fn decrypt_bundle<O: OrchardPrimitives>(
+        &self,
+        bundle: &Bundle<Authorized, ZatBalance, O>,
+    ) -> (Vec<DecryptedOutput>, Vec<RecoveredOutput>) {
+        let decrypted = bundle.decrypt_outputs_with_keys(&self.ivks);
+        let recovered = self
+            .ovks
+            .iter()
+            .flat_map(|(account, ovks)| {
+                bundle.recover_outputs_with_ovks(ovks).into_iter().map(
+                    move |(action_index, _ovk, note, recipient, memo)| RecoveredOutput {
+                        account: *account,
+                        action_index,
+                        note,
+                        recipient,
+                        memo,
+                    },
+                )
+            })
+            .collect();
+        (decrypted, recovered)
+    }
+
+    /// Trial-decrypt the Sapling outputs of `tx` with the accounts' incoming
+    /// viewing keys.
+    pub fn decrypt_sapling(&self, tx: &Transaction) -> Vec<SaplingDecryptedOutput> {
+        let Some(bundle) = tx.sapling_bundle() else {
+            return vec![];
+        };
+        bundle
+            .shielded_outputs()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, output)| {
+                self.sapling_ivks.iter().find_map(|(account, ivk)| {
+                    try_sapling_note_decryption(ivk, output, ZIP212_ENFORCEMENT)
+                        .map(|(note, _recipient, memo)| (index, *account, note, memo))
+                })
+            })
+            .collect()
+    }
+
+    pub fn scan_transaction(&self, tx: Transaction) -> ScannedTransaction {
+        let (decrypted, recovered) = self.decrypt(&tx);
+        let sapling_decrypted = self.decrypt_sapling(&tx);
+        ScannedTransaction {
+            tx,
+            decrypted,
+            recovered,
+            sapling_decrypted,
+        }
+    }
+
+    pub fn scan_block(
+        &self,
+        height: BlockHeight,
+        hash: BlockHash,
+        transactions: Vec<Transaction>,
+    ) -> ScannedBlock {
+        ScannedBlock {
+            height,
+            hash,
+            transactions: transactions
+                .into_iter()
+                .map(|tx| self.scan_transaction(tx))
+                .collect(),
+            header: None,
+            orchard_root: None,
+        }
+    }
+}
+
+/// Fetch and scan the block at `height`, along with the node's Orchard root
+/// after it if `verify_root` is set.
+fn scan_height(
+    rpc: &dyn RpcClient,
+    keys: &ScanKeys,
+    height: u32,
+    verify_root: bool,
+) -> Result<ScannedBlock, Box<dyn Error>> {
+    let block = rpc.get_full_block(height)?;
+    let mut scanned = keys.scan_block(block.height, block.hash, block.transactions);
+    scanned.header = Some(block.header);
+    if verify_root {
+        let state = rpc.get_tree_state(height)?;
+        scanned.orchard_root = Some(tree_state::node_orchard_root(&state)?);
+    }
+    Ok(scanned)
+}
+
+/// Fetch and scan the blocks in `heights` on a worker pool and call `apply`
+/// on each of them in height order, stopping at the first error it returns.
+///
+/// With `verify_roots`, the workers also fetch the node's tree state after
+/// each block so that its Orchard root can be checked once the block is
+/// applied. At most [`SCAN_LOOKAHEAD`] blocks are in flight or waiting to be
+/// applied at any time. Panics if a block cannot be fetched.
+pub fn scan_blocks<F, E>(
+    rpc: &dyn RpcClient,
+    keys: &ScanKeys,
+    heights: RangeInclusive<u32>,
+    verify_roots: bool,
+    mut apply: F,
+) -> Result<(), E>
+where
+    F: FnMut(ScannedBlock) -> Result<(), E>,
+{
+    if heights.is_empty() {
+        return Ok(());
+    }
+    let (first, last) = (*heights.start(), *heights.end());
+    let block_count = (last - first) as usize + 1;
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .clamp(1, MAX_SCAN_WORKERS)
+        .min(block_count);
+    info!(
+        "Scanning blocks {}..={} with {} workers",
+        first, last, workers
+    );
+
+    // A worker must take a permit before claiming a height; the sync thread
+    // hands one back every time it applies a block, which bounds how far the
+    // workers can run ahead.
+    let (permit_tx, permit_rx) = channel::<()>();
+    for _ in 0..SCAN_LOOKAHEAD {
+        permit_tx.send(()).unwrap();
+    }
+    let permit_rx = Mutex::new(permit_rx);
+    let next_height = AtomicU32::new(first);
+    let (result_tx, result_rx) =
+        sync_channel::<(u32, Result<ScannedBlock, String>)>(SCAN_LOOKAHEAD);
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            let result_tx = result_tx.clone();
+            let (permit_rx, next_height) = (&permit_rx, &next_height);
+            s.spawn(move || {
+                loop {
+                    if permit_rx.lock().unwrap().recv().is_err() {
+                        return;
+                    }
+                    let h = next_height.fetch_add(1, Ordering::SeqCst);
+                    if h > last {
+                        return;
+                    }
+                    // Report a panicking worker as an error rather than leaving
+                    // the sync thread waiting for a block that never arrives.
+                    let scanned = panic::catch_unwind(AssertUnwindSafe(|| {
+                        scan_height(rpc, keys, h, verify_roots).map_err(|e| e.to_string())
+                    }))
+                    .unwrap_or_else(|_| Err("worker panicked".to_string()));
+                    if result_tx.send((h, scanned)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(result_tx);
+
+        let mut pending = BTreeMap::new();
+        for h in heights {
+            let block = loop {
+                if let Some(block) = pending.remove(&h) {
+                    break block;
+                }
+                let (height, scanned) = result_rx
+                    .recv()
+                    .expect("block scanning workers stopped unexpectedly");
+                pending.insert(height, scanned);
+            };
+            let result = match block {
+                Ok(block) => apply(block),
+                Err(e) => panic!("RPC error fetching block {}: {}", h, e),
+            };
+            if result.is_err() {
+                // Stop the workers from claiming further heights; dropping the
+                // channels below releases any that are blocked on them.
+                next_height.store(last.saturating_add(1), Ordering::SeqCst);
+                drop(permit_tx);
+                drop(result_rx);
+                return result;
+            }
+            // Workers may already have exited once every height was claimed.
+            let _ = permit_tx.send(());
+        }
+        drop(permit_tx);
+        drop(result_rx);
+        Ok(())
+    })
+}
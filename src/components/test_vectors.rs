@@ -0,0 +1,387 @@
+//! Export of submitted blocks as test vectors.
+//!
+//! With `--export-dir` every block the tool submits, and every transaction in
+//! it, is written to the directory for other OrchardZSA implementations to
+//! check themselves against:
+//!
+//! - `blocks/<sequence>-<height>.json`: the block hash, raw hex, txids,
+//!   whether the block is expected to be valid and what the node answered;
+//! - `transactions/<txid>.json`: the height, raw hex, a decoded summary of
+//!   the transaction's bundles, burns, issuance and asset bases, the outputs
+//!   the wallet decrypts together with the incoming viewing keys that decrypt
+//!   them, and the expected validity of the transaction itself;
+//! - `blocks.txt`: the hex of every block, one per line in submission order.
+//!
+//! Blocks are numbered in submission order, since a height is submitted again
+//! after the node rejects a block. Numbering continues after the blocks
+//! already in the directory, so several runs can export to it. A transaction
+//! submitted in several blocks keeps the vector of the last one, unless that
+//! block is expected to be invalid and the transaction was exported before.
+//!
+//! Only submitted transactions are exported: a vector records the block a
+//! transaction was submitted in and whether it is expected to be valid there,
+//! which a transaction that was built but never submitted does not have.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+
+use orchard::bundle::Authorized;
+use orchard::primitives::OrchardPrimitives;
+use orchard::Bundle;
+use serde_json::{json, Value};
+use zcash_primitives::transaction::{OrchardBundle, Transaction};
+use zcash_protocol::value::ZatBalance;
+
+use crate::components::history::memo_hex;
+use crate::components::scanner::ScanKeys;
+use crate::components::transactions::{AssembledBlock, BlockAssembly};
+use crate::components::wallet::Wallet;
+
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+/// Export every submitted block to `dir`, numbering them after the blocks
+/// already exported there. Only the first call has an effect.
+pub fn set_dir(dir: PathBuf) {
+    let names = fs::read_dir(dir.join("blocks"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok());
+    let next = next_sequence(names);
+    if DIR.set(dir).is_ok() {
+        SEQUENCE.store(next, Ordering::Relaxed);
+    }
+}
+
+/// A block ready to be exported once the node answered.
+pub struct BlockVector {
+    height: u32,
+    hash: String,
+    hex: String,
+    txids: Vec<String>,
+    expected: Value,
+    transactions: Vec<Value>,
+}
+
+/// Prepare the vectors of `block`, whose header must already be solved, if
+/// an export directory was set. Outputs are decrypted with the keys of the
+/// wallet `assembly` was made for.
+pub fn prepare(block: &AssembledBlock, assembly: &BlockAssembly<'_>) -> Option<BlockVector> {
+    DIR.get()?;
+    let mut bytes = vec![];
+    block.proposal.write(&mut bytes).unwrap();
+    let expected = expected_validity(assembly.violates);
+    let violates = |tx: &Transaction| {
+        assembly
+            .violates
+            .filter(|_| assembly.violating_txids.contains(&tx.txid()))
+    };
+    let keys = assembly.wallet.map(Wallet::scan_keys);
+
+    let transactions: Vec<Value> = block
+        .proposal
+        .transactions
+        .iter()
+        .map(|tx| {
+            let mut assets = BTreeSet::new();
+            let summary = summary(tx, &mut assets);
+            let decryptions = match (assembly.wallet, &keys) {
+                (Some(wallet), Some(keys)) => decryptions(wallet, keys, tx, &mut assets),
+                _ => vec![],
+            };
+            let mut tx_bytes = vec![];
+            tx.write(&mut tx_bytes).unwrap();
+            json!({
+                "txid": tx.txid().to_string(),
+                "height": block.height,
+                "hex": hex::encode(tx_bytes),
+                "summary": summary,
+                "asset_bases": assets,
+                "decryptions": decryptions,
+                "expected": expected_validity(violates(tx)),
+            })
+        })
+        .collect();
+
+    Some(BlockVector {
+        height: block.height,
+        hash: block.proposal.header.hash().to_string(),
+        hex: hex::encode(bytes),
+        txids: transactions
+            .iter()
+            .map(|tx| tx["txid"].as_str().unwrap().to_string())
+            .collect(),
+        expected,
+        transactions,
+    })
+}
+
+impl BlockVector {
+    /// Write the vectors with the node's `result` of submitting the block.
+    pub fn write(self, result: &Result<Option<String>, Box<dyn Error>>) -> io::Result<()> {
+        let dir = DIR.get().expect("export directory is set");
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        fs::create_dir_all(dir.join("blocks"))?;
+        fs::create_dir_all(dir.join("transactions"))?;
+
+        let block = json!({
+            "sequence": sequence,
+            "height": self.height,
+            "hash": self.hash,
+            "hex": self.hex,
+            "txids": self.txids,
+            "expected": self.expected,
+            "node_result": node_result(result),
+        });
+        fs::write(
+            dir.join("blocks")
+                .join(block_file_name(sequence, self.height)),
+            serde_json::to_string_pretty(&block).unwrap(),
+        )?;
+        let block_valid = self.expected["valid"] == true;
+        for (txid, tx) in self.txids.iter().zip(&self.transactions) {
+            let path = dir.join("transactions").join(format!("{txid}.json"));
+            if !block_valid && path.exists() {
+                continue;
+            }
+            let mut tx = tx.clone();
+            tx["block_hash"] = Value::from(self.hash.as_str());
+            fs::write(path, serde_json::to_string_pretty(&tx).unwrap())?;
+        }
+
+        let mut blocks = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("blocks.txt"))?;
+        writeln!(blocks, "{}", self.hex)
+    }
+}
+
+fn block_file_name(sequence: u32, height: u32) -> String {
+    format!("{sequence:05}-{height}.json")
+}
+
+/// The sequence number following the block files `names`.
+fn next_sequence(names: impl Iterator<Item = String>) -> u32 {
+    names
+        .filter_map(|name| name.split_once('-')?.0.parse::<u32>().ok())
+        .map(|sequence| sequence + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// A block or transaction is expected to be valid unless it deliberately
+/// breaks the consensus rule `violates`.
+fn expected_validity(violates: Option<&str>) -> Value {
+    json!({
+        "valid": violates.is_none(),
+        "violates": violates,
+    })
+}
+
+/// The node's answer to `submitblock`: accepted outright, another result such
+/// as `"duplicate"`, or an error.
+fn node_result(result: &Result<Option<String>, Box<dyn Error>>) -> Value {
+    match result {
+        Ok(None) => json!({ "accepted": true }),
+        Ok(Some(result)) => json!({ "accepted": false, "result": result }),
+        Err(e) => json!({ "accepted": false, "error": e.to_string() }),
+    }
+}
+
+/// The decoded bundles of `tx`. Burnt and issued asset bases are added to
+/// `assets`.
+fn summary(tx: &Transaction, assets: &mut BTreeSet<String>) -> Value {
+    let transparent = tx.transparent_bundle().map(|b| {
+        json!({
+            "coinbase": b.is_coinbase(),
+            "inputs": b.vin.len(),
+            "outputs": b.vout.len(),
+        })
+    });
+    let sapling = tx.sapling_bundle().map(|b| {
+        json!({
+            "spends": b.shielded_spends().len(),
+            "outputs": b.shielded_outputs().len(),
+            "value_balance": i64::from(*b.value_balance()),
+        })
+    });
+    let orchard = tx.orchard_bundle().map(|bundle| match bundle {
+        OrchardBundle::OrchardVanilla(b) => orchard_summary(b, "vanilla"),
+        OrchardBundle::OrchardZSA(b) => {
+            let burns: Vec<Value> = b
+                .burn()
+                .iter()
+                .map(|(asset, value)| {
+                    let asset = hex::encode(asset.to_bytes());
+                    assets.insert(asset.clone());
+                    json!({ "asset": asset, "amount": value.inner() })
+                })
+                .collect();
+            let mut summary = orchard_summary(b, "zsa");
+            summary["burns"] = Value::from(burns);
+            summary
+        }
+    });
+    let issuance = tx.issue_bundle().map(|bundle| {
+        let actions: Vec<Value> = bundle
+            .actions()
+            .iter()
+            .map(|action| {
+                let notes: Vec<Value> = action
+                    .notes()
+                    .iter()
+                    .map(|note| {
+                        let asset = hex::encode(note.asset().to_bytes());
+                        assets.insert(asset.clone());
+                        json!({
+                            "recipient": hex::encode(note.recipient().to_raw_address_bytes()),
+                            "asset": asset,
+                            "amount": note.value().inner(),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "finalize": action.is_finalized(),
+                    "notes": notes,
+                })
+            })
+            .collect();
+        json!({ "actions": actions })
+    });
+
+    json!({
+        "version": format!("{:?}", tx.version()),
+        "expiry_height": u32::from(tx.expiry_height()),
+        "transparent": transparent,
+        "sapling": sapling,
+        "orchard": orchard,
+        "issuance": issuance,
+    })
+}
+
+fn orchard_summary<O: OrchardPrimitives>(
+    bundle: &Bundle<Authorized, ZatBalance, O>,
+    flavor: &str,
+) -> Value {
+    let actions: Vec<Value> = bundle
+        .actions()
+        .iter()
+        .map(|action| {
+            json!({
+                "nullifier": hex::encode(action.nullifier().to_bytes()),
+                "cmx": hex::encode(action.cmx().to_bytes()),
+            })
+        })
+        .collect();
+    json!({
+        "flavor": flavor,
+        "anchor": hex::encode(bundle.anchor().to_bytes()),
+        "value_balance": i64::from(*bundle.value_balance()),
+        "actions": actions,
+    })
+}
+
+/// The Orchard and Sapling outputs of `tx` that `wallet` decrypts, with the
+/// incoming viewing key that decrypts each of them. Decrypted asset bases are
+/// added to `assets`.
+fn decryptions(
+    wallet: &Wallet,
+    keys: &ScanKeys,
+    tx: &Transaction,
+    assets: &mut BTreeSet<String>,
+) -> Vec<Value> {
+    let (decrypted, _) = keys.decrypt(tx);
+    let mut outputs: Vec<Value> = decrypted
+        .iter()
+        .map(|(index, ivk, note, recipient, memo)| {
+            let asset = hex::encode(note.asset().to_bytes());
+            assets.insert(asset.clone());
+            json!({
+                "pool": "orchard",
+                "index": index,
+                "ivk": hex::encode(ivk.to_bytes()),
+                "account": wallet.account_for_address(recipient),
+                "recipient": hex::encode(recipient.to_raw_address_bytes()),
+                "asset": asset,
+                "amount": note.value().inner(),
+                "memo": memo_hex(memo),
+            })
+        })
+        .collect();
+    outputs.extend(
+        keys.decrypt_sapling(tx)
+            .iter()
+            .map(|(index, account, note, memo)| {
+                json!({
+                    "pool": "sapling",
+                    "index": index,
+                    "ivk": hex::encode(wallet.sapling_ivk(*account).to_repr()),
+                    "account": account,
+                    "recipient": hex::encode(note.recipient().to_bytes()),
+                    "amount": note.value().inner(),
+                    "memo": memo_hex(memo),
+                })
+            }),
+    );
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_results() {
+        assert_eq!(node_result(&Ok(None)), json!({ "accepted": true }));
+        assert_eq!(
+            node_result(&Ok(Some("duplicate".to_string()))),
+            json!({ "accepted": false, "result": "duplicate" })
+        );
+        assert_eq!(
+            node_result(&Err("Block rejected".into())),
+            json!({ "accepted": false, "error": "Block rejected" })
+        );
+    }
+
+    #[test]
+    fn test_expected_validity_names_the_broken_rule() {
+        assert_eq!(
+            expected_validity(None),
+            json!({ "valid": true, "violates": null })
+        );
+        assert_eq!(
+            expected_validity(Some("no double spends")),
+            json!({ "valid": false, "violates": "no double spends" })
+        );
+    }
+
+    #[test]
+    fn test_block_files_sort_in_submission_order() {
+        assert_eq!(block_file_name(7, 120), "00007-120.json");
+        assert!(block_file_name(9, 200) < block_file_name(10, 200));
+    }
+
+    #[test]
+    fn test_next_sequence_follows_existing_blocks() {
+        assert_eq!(next_sequence(std::iter::empty()), 0);
+        let names = [block_file_name(3, 120), block_file_name(11, 121)];
+        assert_eq!(next_sequence(names.into_iter()), 12);
+        let names = ["notes.txt".to_string(), block_file_name(0, 5)];
+        assert_eq!(next_sequence(names.into_iter()), 1);
+    }
+
+    #[test]
+    fn test_empty_memos_are_omitted() {
+        let mut memo = [0u8; 512];
+        memo[0] = 0xF6;
+        assert_eq!(memo_hex(&memo), None);
+        memo[0] = b'h';
+        assert_eq!(memo_hex(&memo).unwrap()[..4], *"6800");
+    }
+}
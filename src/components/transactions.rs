@@ -1,21 +1,34 @@
 use crate::components::block_data;
-use crate::components::miner::MinerKey;
+use crate::components::equihash;
+use crate::components::history_tree::{self, HistoryTree};
+use crate::components::miner::CoinbaseRecipient;
 use crate::components::rpc_client::{BlockProposal, BlockTemplate, RpcClient};
-use crate::components::wallet::Wallet;
+use crate::components::sapling::{self, SaplingNoteData};
+use crate::components::scanner::scan_blocks;
+use crate::components::test_vectors;
+use crate::components::transparent::{self, Utxo};
+use crate::components::wallet::{fee_with_transparent_inputs, SyncError, Wallet};
 use diesel::SqliteConnection;
 use crate::components::block_commitment::{
     block_commitment_from_parts, AuthDataRoot, TxMerkleRoot, AUTH_COMMITMENT_PLACEHOLDER,
 };
+use crate::config::HistoryRootMode;
+use crate::output;
+use crate::report::{self, Step, StepKind, StepStatus};
 use crate::prelude::info;
 use orchard::issuance::{IssueInfo, auth::IssueValidatingKey};
 use orchard::note::{AssetId, AssetBase};
 use orchard::value::NoteValue;
 use orchard::Address;
-use orchard::keys::Scope;
+use orchard::keys::{OutgoingViewingKey, Scope};
 use rand::rngs::OsRng;
+use serde_json::json;
+use sapling_crypto::zip32::ExtendedSpendingKey;
+use sapling_crypto::PaymentAddress;
 use std::error::Error;
 use std::convert::TryFrom;
 use std::ops::Add;
+use std::time::Instant;
 use orchard::keys::SpendAuthorizingKey;
 use secp256k1::Secp256k1;
 use zcash_primitives::block::{BlockHash, BlockHeader, BlockHeaderData};
@@ -26,10 +39,12 @@ use zcash_primitives::transaction::fees::zip317::{FeeError, FeeRule};
 use zcash_primitives::transaction::{Transaction, TxId};
 use zcash_proofs::prover::LocalTxProver;
 use zcash_protocol::value::Zatoshis;
+use zcash_transparent::address::TransparentAddress;
 use zcash_transparent::builder::TransparentSigningSet;
-use zcash_transparent::bundle::{OutPoint, TxOut};
+use zcash_transparent::bundle::TxOut;
 
-const COINBASE_VALUE: u64 = 625_000_000;
+/// Maximum number of transparent inputs of a shielding transaction.
+pub const MAX_SHIELDING_INPUTS: usize = 50;
 
 pub fn mine(
     conn: &mut SqliteConnection,
@@ -37,76 +52,647 @@ pub fn mine(
     rpc_client: &mut dyn RpcClient,
     txs: Vec<Transaction>,
 ) -> Result<(), Box<dyn Error>> {
-    mine_block(rpc_client, txs)?;
+    mine_block(rpc_client, txs, BlockAssembly::for_wallet(wallet))?;
     sync(conn, wallet, rpc_client);
     Ok(())
 }
 
+/// How the tool assembles the blocks it mines on top of the node's block
+/// template.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockAssembly<'a> {
+    /// Recipient of a locally built coinbase, `None` to use the template's.
+    pub coinbase_recipient: Option<&'a CoinbaseRecipient>,
+    /// The local chain history tree, if available.
+    pub history_tree: Option<&'a HistoryTree>,
+    /// Where the chain history root comes from.
+    pub history_root_mode: HistoryRootMode,
+    /// Equihash parameters to solve the header for, `None` for a dummy
+    /// solution.
+    pub equihash_params: Option<equihash::Params>,
+    /// The wallet whose keys decrypt the outputs of exported test vectors.
+    pub wallet: Option<&'a Wallet>,
+    /// The consensus rule the block deliberately breaks, `None` for a block
+    /// expected to be valid.
+    pub violates: Option<&'a str>,
+    /// The transactions of the block that break `violates`, empty if only the
+    /// block as a whole does, e.g. its header. The others are expected to be
+    /// valid on their own.
+    pub violating_txids: &'a [TxId],
+}
+
+impl<'a> BlockAssembly<'a> {
+    /// Assemble blocks as configured for `wallet`.
+    pub fn for_wallet(wallet: &'a Wallet) -> Self {
+        BlockAssembly {
+            coinbase_recipient: wallet.coinbase_recipient(),
+            history_tree: wallet.history_tree(),
+            history_root_mode: wallet.history_root_mode(),
+            equihash_params: wallet.equihash_params(),
+            wallet: Some(wallet),
+            violates: None,
+            violating_txids: &[],
+        }
+    }
+
+    /// The chain history root the block built from `block_template` commits
+    /// to. The local root is only known when the history tree ends right
+    /// below the template; the node's root is used otherwise.
+    fn chain_history_root(&self, block_template: &BlockTemplate) -> Result<[u8; 32], String> {
+        let node_root = crate::components::rpc_client::decode_hex(
+            block_template.default_roots.chain_history_root.clone(),
+        );
+        let local_root = match self.history_root_mode {
+            HistoryRootMode::Node => None,
+            HistoryRootMode::Local | HistoryRootMode::Verify => {
+                history_tree::root_at(self.history_tree, block_template.height)
+            }
+        };
+        match (self.history_root_mode, local_root) {
+            (_, None) => {
+                if self.history_root_mode != HistoryRootMode::Node {
+                    info!(
+                        "No local chain history root for height {}, using the node's",
+                        block_template.height
+                    );
+                }
+                Ok(node_root)
+            }
+            (HistoryRootMode::Verify, Some(local_root)) if local_root != node_root => Err(format!(
+                "Chain history root mismatch at height {}: local {}, node {}",
+                block_template.height,
+                block_data::display_hex(&local_root),
+                block_template.default_roots.chain_history_root
+            )),
+            (_, Some(local_root)) => Ok(local_root),
+        }
+    }
+}
+
+/// A block assembled on top of the node's block template, not solved yet.
+pub struct AssembledBlock {
+    pub height: u32,
+    /// The chain history root the header commits to.
+    pub chain_history_root: [u8; 32],
+    pub proposal: BlockProposal,
+}
+
+/// Mine a block with `txs` on top of the node's block template.
+///
+/// Without a coinbase recipient the template's coinbase is used as is,
+/// otherwise the coinbase is built locally and pays the miner reward to the
+/// given recipient.
 pub fn mine_block(
     rpc_client: &mut dyn RpcClient,
     txs: Vec<Transaction>,
+    assembly: BlockAssembly<'_>,
 ) -> Result<(u32, TxId), Box<dyn Error>> {
-    let block_template = rpc_client.get_block_template()?;
-    let block_height = block_template.height;
+    let started = Instant::now();
+    let block = assemble_block(rpc_client, txs, assembly)?;
+    let coinbase_txid = block.proposal.transactions[0].txid();
+    let txids: Vec<String> = block.proposal.transactions[1..]
+        .iter()
+        .map(|tx| tx.txid().to_string())
+        .collect();
 
-    let block_proposal = template_into_proposal(block_template, txs);
-    let coinbase_txid = block_proposal.transactions.first().unwrap().txid();
+    let height = block.height;
+    let result = submit_block(rpc_client, block, assembly);
+    let mut step = Step::new(StepKind::Mine, "block", started.elapsed());
+    step.height = Some(height);
+    step.txids = txids.clone();
+    if let Err(e) = &result {
+        step.status = StepStatus::Rejected;
+        step.error = Some(e.to_string());
+    }
+    report::record(step);
+    result?;
 
-    rpc_client.submit_block(block_proposal)?;
+    output::emit(
+        "block",
+        &json!({
+            "height": height,
+            "coinbase_txid": coinbase_txid.to_string(),
+            "txids": txids,
+        }),
+    );
+    Ok((height, coinbase_txid))
+}
 
-    Ok((block_height, coinbase_txid))
+/// Assemble a block with `txs` on top of the node's block template, without
+/// solving its header.
+pub fn assemble_block(
+    rpc_client: &mut dyn RpcClient,
+    txs: Vec<Transaction>,
+    assembly: BlockAssembly<'_>,
+) -> Result<AssembledBlock, Box<dyn Error>> {
+    let block_template = rpc_client.get_block_template()?;
+    let height = block_template.height;
+
+    let chain_history_root = assembly.chain_history_root(&block_template)?;
+    let coinbase = match assembly.coinbase_recipient {
+        None => node_coinbase(&block_template)?,
+        Some(recipient) => {
+            let fees = block_fees(&*rpc_client, &txs)?;
+            create_coinbase_transaction(&block_template, fees, recipient)?
+        }
+    };
+    let proposal = template_into_proposal(block_template, coinbase, chain_history_root, txs);
+    Ok(AssembledBlock {
+        height,
+        chain_history_root,
+        proposal,
+    })
+}
+
+/// Solve the header of `block` if `assembly` asks for it and submit it,
+/// exporting it as test vectors if an export directory was set. Returns the
+/// node's result string for blocks it did not accept outright, e.g.
+/// `"duplicate"`.
+pub fn submit_block(
+    rpc_client: &mut dyn RpcClient,
+    mut block: AssembledBlock,
+    assembly: BlockAssembly<'_>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(params) = assembly.equihash_params {
+        block.proposal.header = equihash::solve_header(params, &block.proposal.header);
+    }
+    let vector = test_vectors::prepare(&block, &assembly);
+    let result = rpc_client.submit_block(block.proposal);
+    if let Some(vector) = vector {
+        vector
+            .write(&result)
+            .unwrap_or_else(|e| panic!("failed to export block {}: {e}", block.height));
+    }
+    result
 }
 
 pub fn mine_empty_blocks(
     num_blocks: u32,
     rpc_client: &mut dyn RpcClient,
+    assembly: BlockAssembly<'_>,
 ) -> Result<(u32, TxId), Box<dyn Error>> {
     if num_blocks == 0 {
         panic!("num_blocks must be greater than 0")
     }
 
-    let (block_height, coinbase_txid) = mine_block(rpc_client, vec![])?;
+    let (block_height, coinbase_txid) = mine_block(rpc_client, vec![], assembly)?;
 
     for _ in 1..num_blocks {
-        mine_block(rpc_client, vec![])?;
+        mine_block(rpc_client, vec![], assembly)?;
     }
 
     Ok((block_height, coinbase_txid))
 }
 
-pub fn create_shield_coinbase_transaction(
+/// The coinbase transaction of the node's block template.
+fn node_coinbase(block_template: &BlockTemplate) -> Result<Transaction, Box<dyn Error>> {
+    let data = hex::decode(&block_template.coinbase_txn.data)?;
+    Ok(Transaction::read(data.as_slice(), BranchId::Nu7)?)
+}
+
+/// Build the coinbase of the block at the template's height, paying the miner
+/// reward to `recipient`.
+///
+/// The height is encoded in the coinbase input script by the builder. The
+/// funding stream outputs are copied from the node's coinbase: the node pays
+/// the miner reward either to a shielded output or to its largest transparent
+/// output (funding streams never take more than 20% of the subsidy), every
+/// other transparent output belongs to a funding stream. The miner reward is
+/// the node's minus the fees of the template's transactions plus `fees`, the
+/// fees of the transactions actually included in the block.
+pub fn create_coinbase_transaction(
+    block_template: &BlockTemplate,
+    fees: Zatoshis,
+    recipient: &CoinbaseRecipient,
+) -> Result<Transaction, Box<dyn Error>> {
+    let node_coinbase = node_coinbase(block_template)?;
+    let node_bundle = node_coinbase.transparent_bundle();
+    let mut funding_streams: Vec<&TxOut> = node_bundle
+        .map(|b| b.vout.iter().collect())
+        .unwrap_or_default();
+
+    let shielded_reward = -(fee_with_transparent_inputs(&node_coinbase, 0)
+        + funding_streams
+            .iter()
+            .map(|o| u64::from(o.value()) as i64)
+            .sum::<i64>());
+    let node_reward = if shielded_reward > 0 {
+        shielded_reward
+    } else {
+        let (miner_idx, miner_output) = funding_streams
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, o)| o.value())
+            .ok_or("node coinbase has no miner output")?;
+        let value = u64::from(miner_output.value()) as i64;
+        funding_streams.remove(miner_idx);
+        value
+    };
+    // The coinbase `fee` of the template is the negated sum of the fees of the
+    // template's transactions.
+    let subsidy = node_reward + block_template.coinbase_txn.fee;
+    let reward = Zatoshis::from_nonnegative_i64(subsidy + u64::from(fees) as i64)
+        .map_err(|_| format!("invalid miner reward {} + {}", subsidy, u64::from(fees)))?;
+
+    info!(
+        "Building coinbase for height {} paying {} to {:?}",
+        block_template.height,
+        u64::from(reward),
+        recipient
+    );
+    let build_config = BuildConfig::Coinbase {
+        miner_data: vec![],
+        sequence: u32::MAX,
+    };
+    let mut tx = Builder::new(
+        REGTEST_NETWORK,
+        BlockHeight::from_u32(block_template.height),
+        build_config,
+    );
+    for output in funding_streams {
+        let address = output
+            .recipient_address()
+            .ok_or("funding stream output without a transparent address")?;
+        tx.add_transparent_output(&address, output.value())
+            .map_err(|e| format!("failed to add funding stream output: {:?}", e))?;
+    }
+    match recipient {
+        CoinbaseRecipient::Transparent(address) => tx
+            .add_transparent_output(address, reward)
+            .map_err(|e| format!("failed to add miner output: {:?}", e))?,
+        CoinbaseRecipient::Orchard(address) => tx
+            .add_orchard_output::<FeeError>(
+                Some(OutgoingViewingKey::from([0; 32])),
+                *address,
+                reward,
+                AssetBase::zatoshi(),
+                MemoBytes::empty(),
+            )
+            .map_err(|e| format!("failed to add miner output: {:?}", e))?,
+    }
+
+    Ok(build_tx(tx, &TransparentSigningSet::new(), &[], &[], None))
+}
+
+/// The sum of the fees paid by `txs`. The values of transparent inputs are
+/// looked up on the node.
+fn block_fees(rpc_client: &dyn RpcClient, txs: &[Transaction]) -> Result<Zatoshis, Box<dyn Error>> {
+    let mut fees = 0;
+    for tx in txs {
+        let mut transparent_in = 0;
+        for input in tx
+            .transparent_bundle()
+            .map(|b| b.vin.as_slice())
+            .unwrap_or_default()
+        {
+            let prevout = input.prevout();
+            let prev_tx = rpc_client.get_transaction(&TxId::from_bytes(*prevout.hash()))?;
+            let spent = prev_tx
+                .transparent_bundle()
+                .and_then(|b| b.vout.get(prevout.n() as usize))
+                .ok_or_else(|| format!("unknown transparent input {:?}", prevout))?;
+            transparent_in += u64::from(spent.value()) as i64;
+        }
+        fees += fee_with_transparent_inputs(tx, transparent_in);
+    }
+    Zatoshis::from_nonnegative_i64(fees).map_err(|_| format!("invalid block fees {}", fees).into())
+}
+
+/// Shield every mature coinbase output paid to the registered
+/// [`MinerKey`](crate::components::miner::MinerKey) into `recipient`, at most
+/// [`MAX_SHIELDING_INPUTS`] outputs per transaction. The values are those seen
+/// on chain during sync.
+///
+/// Returns no transaction when nothing is mature yet.
+pub fn create_shield_mature_coinbase_transactions(
+    conn: &mut SqliteConnection,
     recipient: Address,
-    coinbase_txid: TxId,
     rpc_client: &dyn RpcClient,
     wallet: &mut Wallet,
-    miner_key: &MinerKey,
+) -> Vec<Transaction> {
+    let utxos = wallet.mature_coinbase_utxos(conn);
+    info!("Shielding {} mature coinbase outputs", utxos.len());
+    utxos
+        .chunks(MAX_SHIELDING_INPUTS)
+        .map(|batch| create_shielding_transaction(conn, batch, recipient, rpc_client, wallet))
+        .collect()
+}
+
+/// Send `amount` zatoshis from the transparent address of a wallet account
+/// to `recipient`, with change back to the sender's address.
+///
+/// Coinbase outputs are never selected: they may only be spent by
+/// transactions without transparent outputs, see
+/// [`create_shielding_transaction`].
+pub fn create_transparent_transfer_transaction(
+    conn: &mut SqliteConnection,
+    sender: TransparentAddress,
+    recipient: TransparentAddress,
+    amount: u64,
+    rpc_client: &dyn RpcClient,
+    wallet: &mut Wallet,
 ) -> Transaction {
-    info!("Shielding coinbase output from tx {}", coinbase_txid);
+    info!("Transparent transfer of {} zatoshis", amount);
+
+    let account = wallet
+        .account_for_transparent_address(&sender)
+        .expect("sender address does not belong to the wallet");
+    let mut inputs = Vec::new();
+    let mut total_inputs_amount = 0;
+    for utxo in wallet.spendable_utxos(conn, account) {
+        if total_inputs_amount >= amount {
+            break;
+        }
+        if !utxo.is_coinbase {
+            total_inputs_amount += utxo.amount as u64;
+            inputs.push(utxo);
+        }
+    }
+    if total_inputs_amount < amount {
+        panic!(
+            "insufficient transparent inputs: required {} but found {}",
+            amount, total_inputs_amount
+        );
+    }
+
     let target_height = rpc_client
         .get_target_height()
         .expect("failed to get target height");
     let mut tx = create_tx(target_height, wallet);
+    let tss = add_transparent_inputs(&mut tx, wallet, &inputs);
 
-    let coinbase_amount = Zatoshis::from_u64(COINBASE_VALUE).unwrap();
-    let coinbase_recipient = miner_key.address();
-    let pk = miner_key.secret_key().public_key(&Secp256k1::new());
+    tx.add_transparent_output(&recipient, Zatoshis::from_u64(amount).unwrap())
+        .unwrap();
+    let change_amount = total_inputs_amount - amount;
+    if change_amount != 0 {
+        tx.add_transparent_output(&sender, Zatoshis::from_u64(change_amount).unwrap())
+            .unwrap();
+    }
+
+    let tx = build_tx(tx, &tss, &[], &[], None);
+    wallet.lock_utxos(conn, &inputs, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
+}
 
-    tx.add_transparent_p2pkh_input(
-        pk,
-        OutPoint::new(coinbase_txid.into(), 0),
-        TxOut::new(coinbase_amount, coinbase_recipient.script().into()),
+/// Shield the transparent outputs `utxos` of wallet accounts into a single
+/// Orchard output to `recipient`, encrypted to the OVK of the account owning
+/// `recipient`, if any.
+pub fn create_shielding_transaction(
+    conn: &mut SqliteConnection,
+    utxos: &[Utxo],
+    recipient: Address,
+    rpc_client: &dyn RpcClient,
+    wallet: &mut Wallet,
+) -> Transaction {
+    assert!(!utxos.is_empty(), "no transparent outputs to shield");
+    let total_amount: u64 = utxos.iter().map(|utxo| utxo.amount as u64).sum();
+    info!(
+        "Shielding {} zatoshis from {} transparent outputs",
+        total_amount,
+        utxos.len()
+    );
+
+    let target_height = rpc_client
+        .get_target_height()
+        .expect("failed to get target height");
+    let mut tx = create_tx(target_height, wallet);
+    let tss = add_transparent_inputs(&mut tx, wallet, utxos);
+
+    tx.add_orchard_output::<FeeError>(
+        Some(wallet.account_for_address(&recipient).map_or_else(
+            || wallet.orchard_ovk(),
+            |account| wallet.account_ovk(account),
+        )),
+        recipient,
+        Zatoshis::from_u64(total_amount).unwrap(),
+        AssetBase::zatoshi(),
+        MemoBytes::empty(),
+    )
+    .unwrap();
+
+    let tx = build_tx(tx, &tss, &[], &[], None);
+    wallet.lock_utxos(conn, utxos, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
+}
+
+/// Send `amount` ZEC from the Orchard address `sender` of a wallet account to
+/// the transparent `recipient`, with Orchard change.
+pub fn create_deshielding_transaction(
+    conn: &mut SqliteConnection,
+    sender: Address,
+    recipient: TransparentAddress,
+    amount: u64,
+    rpc_client: &dyn RpcClient,
+    wallet: &mut Wallet,
+) -> Transaction {
+    info!("De-shield {} zatoshis", amount);
+
+    let account = wallet
+        .account_for_address(&sender)
+        .expect("sender address does not belong to the wallet");
+    let inputs = wallet.select_spendable_notes(conn, account, amount, AssetBase::zatoshi());
+    let total_inputs_amount = inputs
+        .iter()
+        .fold(0, |acc, input| acc + input.note.value().inner());
+
+    let target_height = rpc_client
+        .get_target_height()
+        .expect("failed to get target height");
+    let mut tx = create_tx(target_height, wallet);
+
+    let note_ids: Vec<i32> = inputs.iter().map(|input| input.note_id).collect();
+    let orchard_keys: Vec<SpendAuthorizingKey> = inputs
+        .into_iter()
+        .map(|input| {
+            tx.add_orchard_spend::<FeeError>((&input.sk).into(), input.note, input.merkle_path)
+                .unwrap();
+            SpendAuthorizingKey::from(&input.sk)
+        })
+        .collect();
+
+    tx.add_transparent_output(&recipient, Zatoshis::from_u64(amount).unwrap())
+        .unwrap();
+    let change_amount = total_inputs_amount - amount;
+    if change_amount != 0 {
+        add_change_output(
+            &mut tx,
+            wallet,
+            account,
+            change_amount,
+            AssetBase::zatoshi(),
+        );
+    }
+
+    let tx = build_tx(
+        tx,
+        &TransparentSigningSet::new(),
+        &[],
+        orchard_keys.as_slice(),
+        None,
+    );
+    wallet.lock_notes(conn, &note_ids, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
+}
+
+/// Send `amount` zatoshis from the Sapling address `sender` of a wallet
+/// account to the Sapling `recipient`, with change back to the sender's
+/// default address.
+pub fn create_sapling_transfer_transaction(
+    conn: &mut SqliteConnection,
+    sender: PaymentAddress,
+    recipient: PaymentAddress,
+    amount: u64,
+    rpc_client: &dyn RpcClient,
+    wallet: &mut Wallet,
+) -> Transaction {
+    info!("Sapling transfer of {} zatoshis", amount);
+
+    let account = wallet
+        .account_for_sapling_address(&sender)
+        .expect("sender address does not belong to the wallet");
+    let target_height = rpc_client
+        .get_target_height()
+        .expect("failed to get target height");
+    let mut tx = create_tx(target_height, wallet);
+    let (inputs, total_inputs_amount) = add_sapling_spends(&mut tx, conn, wallet, account, amount);
+
+    tx.add_sapling_output::<FeeError>(
+        Some(wallet.sapling_ovk(account)),
+        recipient,
+        Zatoshis::from_u64(amount).unwrap(),
+        MemoBytes::empty(),
     )
     .unwrap();
+    let change_amount = total_inputs_amount - amount;
+    if change_amount != 0 {
+        add_sapling_change_output(&mut tx, wallet, account, change_amount);
+    }
+
+    let tx = build_tx(
+        tx,
+        &TransparentSigningSet::new(),
+        &[wallet.sapling_spending_key(account)],
+        &[],
+        None,
+    );
+    wallet.lock_sapling_notes(conn, &inputs, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
+}
+
+/// Migrate `amount` zatoshis from the Sapling address `sender` of a wallet
+/// account to the Orchard `recipient`, with Sapling change back to the
+/// sender's default address.
+pub fn create_sapling_to_orchard_transaction(
+    conn: &mut SqliteConnection,
+    sender: PaymentAddress,
+    recipient: Address,
+    amount: u64,
+    rpc_client: &dyn RpcClient,
+    wallet: &mut Wallet,
+) -> Transaction {
+    info!("Migrate {} zatoshis from Sapling to Orchard", amount);
+
+    let account = wallet
+        .account_for_sapling_address(&sender)
+        .expect("sender address does not belong to the wallet");
+    let target_height = rpc_client
+        .get_target_height()
+        .expect("failed to get target height");
+    let mut tx = create_tx(target_height, wallet);
+    let (inputs, total_inputs_amount) = add_sapling_spends(&mut tx, conn, wallet, account, amount);
+
     tx.add_orchard_output::<FeeError>(
-        Some(wallet.orchard_ovk()),
+        Some(wallet.account_ovk(account)),
         recipient,
-        Zatoshis::from_u64(COINBASE_VALUE).unwrap(),
+        Zatoshis::from_u64(amount).unwrap(),
         AssetBase::zatoshi(),
         MemoBytes::empty(),
     )
     .unwrap();
+    let change_amount = total_inputs_amount - amount;
+    if change_amount != 0 {
+        add_sapling_change_output(&mut tx, wallet, account, change_amount);
+    }
 
-    build_tx(tx, &miner_key.signing_set(), &[], None)
+    let tx = build_tx(
+        tx,
+        &TransparentSigningSet::new(),
+        &[wallet.sapling_spending_key(account)],
+        &[],
+        None,
+    );
+    wallet.lock_sapling_notes(conn, &inputs, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
+}
+
+/// Send `amount` zatoshis from the Orchard address `sender` of a wallet
+/// account to the Sapling `recipient`, with Orchard change.
+pub fn create_orchard_to_sapling_transaction(
+    conn: &mut SqliteConnection,
+    sender: Address,
+    recipient: PaymentAddress,
+    amount: u64,
+    rpc_client: &dyn RpcClient,
+    wallet: &mut Wallet,
+) -> Transaction {
+    info!("Send {} zatoshis from Orchard to Sapling", amount);
+
+    let account = wallet
+        .account_for_address(&sender)
+        .expect("sender address does not belong to the wallet");
+    let inputs = wallet.select_spendable_notes(conn, account, amount, AssetBase::zatoshi());
+    let total_inputs_amount = inputs
+        .iter()
+        .fold(0, |acc, input| acc + input.note.value().inner());
+
+    let target_height = rpc_client
+        .get_target_height()
+        .expect("failed to get target height");
+    let mut tx = create_tx(target_height, wallet);
+
+    let note_ids: Vec<i32> = inputs.iter().map(|input| input.note_id).collect();
+    let orchard_keys: Vec<SpendAuthorizingKey> = inputs
+        .into_iter()
+        .map(|input| {
+            tx.add_orchard_spend::<FeeError>((&input.sk).into(), input.note, input.merkle_path)
+                .unwrap();
+            SpendAuthorizingKey::from(&input.sk)
+        })
+        .collect();
+
+    tx.add_sapling_output::<FeeError>(
+        Some(wallet.sapling_ovk(account)),
+        recipient,
+        Zatoshis::from_u64(amount).unwrap(),
+        MemoBytes::empty(),
+    )
+    .unwrap();
+    let change_amount = total_inputs_amount - amount;
+    if change_amount != 0 {
+        add_change_output(
+            &mut tx,
+            wallet,
+            account,
+            change_amount,
+            AssetBase::zatoshi(),
+        );
+    }
+
+    let tx = build_tx(
+        tx,
+        &TransparentSigningSet::new(),
+        &[],
+        orchard_keys.as_slice(),
+        None,
+    );
+    wallet.lock_notes(conn, &note_ids, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
 }
 
 pub fn sync(conn: &mut SqliteConnection, wallet: &mut Wallet, rpc: &mut dyn RpcClient) {
@@ -117,14 +703,21 @@ pub fn sync(conn: &mut SqliteConnection, wallet: &mut Wallet, rpc: &mut dyn RpcC
     sync_from_height(conn, current_height, wallet, rpc);
 }
 
+/// How many times in a row a sync starts over from scratch after a reorg
+/// before giving up.
+const MAX_REORG_RESYNCS: u32 = 3;
+
 /// Sync the user with the node from the given height.
 ///
 /// On each run the stored chain head is compared to the live chain. If it
 /// matches and the persisted wallet state is consistent with `block_data`, we
 /// resume from where we left off. Any inconsistency (wallet state ahead of /
-/// out of sync with `block_data`, or a chain reorg detected at the stored head)
-/// is treated as a hard failure: we wipe everything and resync from
+/// out of sync with `block_data`, a chain reorg detected at the stored head, or
+/// a synced block whose header does not link to the stored block below it) is
+/// treated as a hard failure: we wipe everything and resync from
 /// `from_height`. We do not attempt per-block rollback or partial rewinds.
+/// A chain that is still being reorganized after [`MAX_REORG_RESYNCS`]
+/// resyncs in a row fails the sync with [`SyncError::UnstableChain`].
 pub fn sync_from_height(
     conn: &mut SqliteConnection,
     from_height: u32,
@@ -132,6 +725,7 @@ pub fn sync_from_height(
     rpc: &mut dyn RpcClient,
 ) {
     info!("Starting sync from height {}", from_height);
+    let started = Instant::now();
 
     let start_height = match block_data::last_height(conn) {
         Some(head) if head_matches_chain(conn, head, rpc) => match wallet.last_block_height() {
@@ -146,11 +740,11 @@ pub fn sync_from_height(
             Some(wallet_head) => {
                 info!(
                     "Wallet state at height {} does not match stored block data; \
-                     clearing all persisted data and resyncing from block 0",
+                     clearing all persisted data and resyncing",
                     u32::from(wallet_head),
                 );
                 wallet.reset(conn);
-                0
+                cold_start(conn, 0, wallet, rpc)
             }
             None => {
                 info!(
@@ -163,64 +757,152 @@ pub fn sync_from_height(
         Some(head) => {
             info!(
                 "Chain reorganization detected at stored head {}; clearing all \
-                         persisted data and resyncing from block 0",
+                         persisted data and resyncing",
                 head,
             );
             wallet.reset(conn);
-            0
+            cold_start(conn, 0, wallet, rpc)
         }
         None => {
             if wallet.last_block_height().is_some() {
                 info!(
                     "Wallet state exists but block data is empty; \
-                     clearing all persisted state and resyncing from block 0"
+                     clearing all persisted state and resyncing"
                 );
                 wallet.reset(conn);
-                0
+                cold_start(conn, 0, wallet, rpc)
             } else {
-                info!("No block data found, starting from {}", from_height);
-                from_height
+                cold_start(conn, from_height, wallet, rpc)
             }
         }
     };
 
-    // Catch up in passes: read the chain tip, drain start_height..=tip, then
-    // re-read the tip in case the chain advanced during the pass. Loop exits
-    // when no new blocks appeared in the last pass.
+    match catch_up(conn, start_height, wallet, rpc) {
+        Ok(chain_tip) => {
+            info!("Synced up to height {}", chain_tip);
+            output::emit(
+                "sync",
+                &json!({ "from_height": start_height, "to_height": chain_tip }),
+            );
+            let mut step = Step::new(StepKind::Sync, "sync", started.elapsed());
+            step.height = Some(chain_tip);
+            report::record(step);
+            if let Some(policy) = wallet.block_data_retention() {
+                let pruned = block_data::prune(conn, &policy);
+                if pruned > 0 {
+                    info!("Pruned {} stored block hashes", pruned);
+                }
+            }
+        }
+        Err(SyncError::RootMismatch {
+            height,
+            local,
+            node,
+        }) => panic!(
+            "Orchard tree root diverges from the node at height {}: local {}, node {}",
+            height, local, node
+        ),
+        Err(SyncError::UnstableChain { height, resyncs }) => panic!(
+            "Chain still reorganizing at height {} after {} resyncs",
+            height, resyncs
+        ),
+        Err(e) => panic!("process_block: {:?}", e),
+    }
+}
+
+/// Scan the chain from `start_height` up to its tip and return the tip.
+///
+/// Catches up in passes: read the chain tip, drain `start_height..=tip`, then
+/// re-read the tip in case the chain advanced during the pass, until no new
+/// blocks appeared in the last pass. A reorg wipes the wallet and starts over
+/// from scratch, at most [`MAX_REORG_RESYNCS`] times in a row.
+fn catch_up(
+    conn: &mut SqliteConnection,
+    start_height: u32,
+    wallet: &mut Wallet,
+    rpc: &mut dyn RpcClient,
+) -> Result<u32, SyncError> {
     let mut next_height = start_height;
+    let mut resyncs = 0;
     loop {
         let target = rpc
             .get_target_height()
             .expect("failed to get target height");
         let chain_tip = u32::from(target).saturating_sub(1);
-
         if next_height > chain_tip {
-            info!("Synced up to height {}", chain_tip);
-            return;
+            return Ok(chain_tip);
+        }
+
+        let keys = wallet.scan_keys();
+        let verify_roots = wallet.verifies_orchard_root();
+        let result = scan_blocks(
+            &*rpc,
+            &keys,
+            next_height..=chain_tip,
+            verify_roots,
+            |block| {
+                info!(
+                    "Adding {} transactions from block {} at height {}",
+                    block.transactions.len(),
+                    block.hash,
+                    block.height
+                );
+                wallet.process_scanned_block(conn, block)
+            },
+        );
+        match result {
+            Ok(()) => {
+                resyncs = 0;
+                next_height = chain_tip + 1;
+            }
+            Err(SyncError::Reorg { height }) if resyncs < MAX_REORG_RESYNCS => {
+                info!(
+                    "Block {} does not build on the stored chain; clearing all \
+                     persisted data and resyncing",
+                    height
+                );
+                resyncs += 1;
+                wallet.reset(conn);
+                next_height = cold_start(conn, 0, wallet, rpc);
+            }
+            Err(SyncError::Reorg { height }) => {
+                return Err(SyncError::UnstableChain { height, resyncs });
+            }
+            Err(e) => return Err(e),
         }
+    }
+}
 
-        for h in next_height..=chain_tip {
-            let block = rpc
-                .get_block(h)
-                .unwrap_or_else(|e| panic!("RPC error fetching block {}: {}", h, e));
+/// Pick the first height to scan for a wallet without any persisted state.
+///
+/// If the wallet has a birthday above `from_height`, its commitment tree is
+/// seeded from the node's tree state at the block just below the birthday and
+/// scanning starts at the birthday. Otherwise scanning starts at `from_height`.
+fn cold_start(
+    conn: &mut SqliteConnection,
+    from_height: u32,
+    wallet: &mut Wallet,
+    rpc: &mut dyn RpcClient,
+) -> u32 {
+    match wallet.birthday() {
+        Some(birthday) if birthday > from_height => {
+            let state = rpc.get_tree_state(birthday - 1).unwrap_or_else(|e| {
+                panic!("RPC error fetching tree state at {}: {}", birthday - 1, e)
+            });
+            wallet
+                .init_from_tree_state(conn, &state)
+                .expect("failed to initialize wallet from tree state");
             info!(
-                "Adding transactions from block {} at height {}",
-                block.hash, block.height
+                "Seeded wallet from tree state at height {}, starting from birthday {}",
+                birthday - 1,
+                birthday
             );
-            let transactions = block
-                .tx_ids
-                .iter()
-                .map(|tx_id| {
-                    rpc.get_transaction(tx_id).unwrap_or_else(|e| {
-                        panic!("RPC error fetching tx {} in block {}: {}", tx_id, h, e)
-                    })
-                })
-                .collect();
-            wallet
-                .process_block(conn, block.height, block.hash, transactions)
-                .expect("process_block");
+            birthday
+        }
+        _ => {
+            info!("No block data found, starting from {}", from_height);
+            from_height
         }
-        next_height = chain_tip + 1;
     }
 }
 
@@ -267,9 +949,11 @@ pub fn create_transfer_transaction(
 ) -> Transaction {
     info!("Transfer {} units", amount);
 
-    let ovk = wallet.orchard_ovk();
-
-    let inputs = wallet.select_spendable_notes(conn, sender, amount, asset);
+    let account = wallet
+        .account_for_address(&sender)
+        .expect("sender address does not belong to the wallet");
+    let ovk = wallet.account_ovk(account);
+    let inputs = wallet.select_spendable_notes(conn, account, amount, asset);
     let total_inputs_amount = inputs
         .iter()
         .fold(0, |acc, input| acc + input.note.value().inner());
@@ -284,6 +968,7 @@ pub fn create_transfer_transaction(
         .expect("failed to get target height");
     let mut tx = create_tx(target_height, wallet);
 
+    let note_ids: Vec<i32> = inputs.iter().map(|input| input.note_id).collect();
     let orchard_keys: Vec<SpendAuthorizingKey> = inputs
         .into_iter()
         .map(|input| {
@@ -294,7 +979,7 @@ pub fn create_transfer_transaction(
         .collect();
 
     tx.add_orchard_output::<FeeError>(
-        Some(ovk.clone()),
+        Some(ovk),
         recipient,
         Zatoshis::from_u64(amount).unwrap(),
         asset,
@@ -304,22 +989,19 @@ pub fn create_transfer_transaction(
 
     let change_amount = total_inputs_amount - amount;
     if change_amount != 0 {
-        tx.add_orchard_output::<FeeError>(
-            Some(ovk),
-            sender,
-            Zatoshis::from_u64(change_amount).unwrap(),
-            asset,
-            MemoBytes::empty(),
-        )
-        .unwrap();
+        add_change_output(&mut tx, wallet, account, change_amount, asset);
     }
 
-    build_tx(
+    let tx = build_tx(
         tx,
         &TransparentSigningSet::new(),
+        &[],
         orchard_keys.as_slice(),
         None,
-    )
+    );
+    wallet.lock_notes(conn, &note_ids, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
 }
 
 pub fn create_burn_transaction(
@@ -332,7 +1014,10 @@ pub fn create_burn_transaction(
 ) -> Transaction {
     info!("Burn {} units", amount);
 
-    let inputs = wallet.select_spendable_notes(conn, arsonist, amount, asset);
+    let account = wallet
+        .account_for_address(&arsonist)
+        .expect("burner address does not belong to the wallet");
+    let inputs = wallet.select_spendable_notes(conn, account, amount, asset);
     let total_inputs_amount = inputs
         .iter()
         .fold(0, |acc, input| acc + input.note.value().inner());
@@ -347,6 +1032,7 @@ pub fn create_burn_transaction(
         .expect("failed to get target height");
     let mut tx = create_tx(target_height, wallet);
 
+    let note_ids: Vec<i32> = inputs.iter().map(|input| input.note_id).collect();
     let orchard_keys: Vec<SpendAuthorizingKey> = inputs
         .into_iter()
         .map(|input| {
@@ -360,23 +1046,19 @@ pub fn create_burn_transaction(
 
     let change_amount = total_inputs_amount - amount;
     if change_amount != 0 {
-        let ovk = wallet.orchard_ovk();
-        tx.add_orchard_output::<FeeError>(
-            Some(ovk),
-            arsonist,
-            Zatoshis::from_u64(change_amount).unwrap(),
-            asset,
-            MemoBytes::empty(),
-        )
-        .unwrap();
+        add_change_output(&mut tx, wallet, account, change_amount, asset);
     }
 
-    build_tx(
+    let tx = build_tx(
         tx,
         &TransparentSigningSet::new(),
+        &[],
         orchard_keys.as_slice(),
         None,
-    )
+    );
+    wallet.lock_notes(conn, &note_ids, &tx);
+    wallet.record_transaction(conn, &tx, None);
+    tx
 }
 
 pub fn create_issue_transaction(
@@ -427,6 +1109,7 @@ pub fn create_issue_transaction(
             tx,
             &TransparentSigningSet::new(),
             &[],
+            &[],
             first_issuance.then_some(asset),
         ),
         asset,
@@ -464,34 +1147,23 @@ pub fn create_finalization_transaction(
     )
     .unwrap();
 
-    build_tx(tx, &TransparentSigningSet::new(), &[], Some(asset))
+    build_tx(tx, &TransparentSigningSet::new(), &[], &[], Some(asset))
 }
 
 pub fn template_into_proposal(
     block_template: BlockTemplate,
+    coinbase: Transaction,
+    chain_history_root: [u8; 32],
     mut txs: Vec<Transaction>,
 ) -> BlockProposal {
-    let coinbase = Transaction::read(
-        hex::decode(block_template.coinbase_txn.data)
-            .unwrap()
-            .as_slice(),
-        BranchId::Nu7,
-    )
-    .unwrap();
-
     let mut txs_with_coinbase = vec![coinbase];
     txs_with_coinbase.append(&mut txs);
 
-    let merkle_root = if txs_with_coinbase.len() == 1 {
-        // only coinbase tx is present, no need to calculate
-        crate::components::rpc_client::decode_hex(block_template.default_roots.merkle_root)
-    } else {
-        txs_with_coinbase
-            .iter()
-            .map(|tx| *tx.txid().clone().as_ref())
-            .collect::<TxMerkleRoot>()
-            .0
-    };
+    let merkle_root = txs_with_coinbase
+        .iter()
+        .map(|tx| *tx.txid().clone().as_ref())
+        .collect::<TxMerkleRoot>()
+        .0;
 
     let auth_data_root = txs_with_coinbase
         .iter()
@@ -504,10 +1176,7 @@ pub fn template_into_proposal(
         })
         .collect::<AuthDataRoot>();
 
-    let hash_block_commitments = block_commitment_from_parts(
-        crate::components::rpc_client::decode_hex(block_template.default_roots.chain_history_root),
-        auth_data_root.0,
-    );
+    let hash_block_commitments = block_commitment_from_parts(chain_history_root, auth_data_root.0);
 
     let block_header_data = BlockHeaderData {
         version: block_template.version as i32,
@@ -518,8 +1187,10 @@ pub fn template_into_proposal(
         final_sapling_root: hash_block_commitments,
         time: block_template.cur_time,
         bits: u32::from_str_radix(block_template.bits.as_str(), 16).unwrap(),
-        nonce: [2; 32],                 // Currently PoW is switched off in Zebra
-        solution: Vec::from([0; 1344]), // Currently PoW is switched off in Zebra
+        // Replaced by a solved nonce and solution when the node checks PoW,
+        // see `equihash::solve_header`.
+        nonce: [2; 32],
+        solution: Vec::from([0; 1344]),
     };
 
     let header = BlockHeader::from_data(block_header_data).unwrap();
@@ -530,24 +1201,123 @@ pub fn template_into_proposal(
     }
 }
 
-fn create_tx(target_height: BlockHeight, wallet: &Wallet) -> Builder<'_, RegtestNetwork, ()> {
+/// Send change back to the account's internal-scope address, encrypted to its
+/// internal OVK, as ZIP-32/ZIP-316 wallets do.
+fn add_change_output(
+    tx: &mut Builder<'_, RegtestNetwork, ()>,
+    wallet: &mut Wallet,
+    account: usize,
+    change_amount: u64,
+    asset: AssetBase,
+) {
+    let change_address = wallet.change_address(account);
+    tx.add_orchard_output::<FeeError>(
+        Some(wallet.internal_ovk(account)),
+        change_address,
+        Zatoshis::from_u64(change_amount).unwrap(),
+        asset,
+        MemoBytes::empty(),
+    )
+    .unwrap();
+}
+
+/// Spend `utxos` as P2PKH inputs and return the keys signing them.
+fn add_transparent_inputs(
+    tx: &mut Builder<'_, RegtestNetwork, ()>,
+    wallet: &Wallet,
+    utxos: &[Utxo],
+) -> TransparentSigningSet {
+    let secp = Secp256k1::new();
+    let mut tss = TransparentSigningSet::new();
+    for utxo in utxos {
+        let sk = wallet.utxo_secret_key(utxo);
+        let address = transparent::p2pkh_address(&sk);
+        tx.add_transparent_p2pkh_input(
+            sk.public_key(&secp),
+            utxo.outpoint(),
+            utxo.tx_out(&address),
+        )
+        .unwrap();
+        tss.add_key(sk);
+    }
+    tss
+}
+
+/// Spend unlocked Sapling notes of `account`, oldest first, until they cover
+/// `amount`. Returns the spent notes and their total value.
+fn add_sapling_spends(
+    tx: &mut Builder<'_, RegtestNetwork, ()>,
+    conn: &mut SqliteConnection,
+    wallet: &Wallet,
+    account: usize,
+    amount: u64,
+) -> (Vec<SaplingNoteData>, u64) {
+    let fvk = wallet
+        .sapling_spending_key(account)
+        .to_diversifiable_full_viewing_key()
+        .fvk()
+        .clone();
+    let mut inputs = Vec::new();
+    let mut total_inputs_amount = 0;
+    for note in sapling::spendable(conn, account) {
+        if total_inputs_amount >= amount {
+            break;
+        }
+        let witness = note.witness().unwrap();
+        tx.add_sapling_spend::<FeeError>(fvk.clone(), note.note(), witness.path().unwrap())
+            .unwrap();
+        total_inputs_amount += note.amount as u64;
+        inputs.push(note);
+    }
+    if total_inputs_amount < amount {
+        panic!(
+            "insufficient Sapling inputs: required {} but found {}",
+            amount, total_inputs_amount
+        );
+    }
+    (inputs, total_inputs_amount)
+}
+
+/// Send Sapling change back to the default address of `account`.
+fn add_sapling_change_output(
+    tx: &mut Builder<'_, RegtestNetwork, ()>,
+    wallet: &mut Wallet,
+    account: usize,
+    change_amount: u64,
+) {
+    let change_address = wallet.sapling_address(account);
+    tx.add_sapling_output::<FeeError>(
+        Some(wallet.sapling_ovk(account)),
+        change_address,
+        Zatoshis::from_u64(change_amount).unwrap(),
+        MemoBytes::empty(),
+    )
+    .unwrap();
+}
+
+pub(crate) fn create_tx(
+    target_height: BlockHeight,
+    wallet: &Wallet,
+) -> Builder<'static, RegtestNetwork, ()> {
     // V6 is the default for the Nu7 branch (which Regtest activates at height 1),
     // so Standard auto-selects V6 here. No need to call propose_version.
     let build_config = BuildConfig::Standard {
-        sapling_anchor: None,
+        sapling_anchor: Some(wallet.sapling_anchor()),
         orchard_anchor: wallet.orchard_anchor(),
     };
     Builder::new(REGTEST_NETWORK, target_height, build_config)
 }
 
-fn build_tx(
+pub(crate) fn build_tx(
     builder: Builder<'_, RegtestNetwork, ()>,
     tss: &TransparentSigningSet,
+    sapling_extsks: &[ExtendedSpendingKey],
     orchard_saks: &[SpendAuthorizingKey],
     new_asset: Option<AssetBase>,
 ) -> Transaction {
     // FIXME: the last arg of `non_standard` (creation_cost) is set to 0, use proper value instead
     let fee_rule = &FeeRule::non_standard(Zatoshis::from_u64(0).unwrap(), 20, 150, 34, 0).unwrap();
+    let started = Instant::now();
     let prover = LocalTxProver::with_default_location();
     match prover {
         None => {
@@ -557,7 +1327,7 @@ fn build_tx(
             let tx = builder
                 .build(
                     tss,
-                    &[],
+                    sapling_extsks,
                     orchard_saks,
                     OsRng,
                     &prover,
@@ -568,6 +1338,9 @@ fn build_tx(
                 .unwrap()
                 .into_transaction();
             info!("Build tx: {}", tx.txid());
+            let mut step = Step::new(StepKind::Build, "transaction", started.elapsed());
+            step.txids = vec![tx.txid().to_string()];
+            report::record(step);
             tx
         }
     }
@@ -0,0 +1,322 @@
+//! Transparent accounts of the wallet and the UTXOs paying them.
+//!
+//! Every wallet account has one transparent P2PKH address: the first external
+//! key of the BIP-44 account with the same index, derived from the wallet
+//! seed. Sync stores the outputs paying these addresses in
+//! `transparent_utxos` and marks them spent when a later transaction consumes
+//! them. Like notes, UTXOs spent by a transaction the wallet built are locked
+//! until it is mined or expires.
+//!
+//! Free functions take `&mut SqliteConnection` so callers can participate in
+//! a transaction.
+
+use diesel::prelude::*;
+use ripemd::{Digest, Ripemd160};
+use secp256k1::{Secp256k1, SecretKey};
+use sha2::Sha256;
+use zcash_protocol::consensus::REGTEST_NETWORK;
+use zcash_protocol::value::Zatoshis;
+use zcash_transparent::address::TransparentAddress;
+use zcash_transparent::bundle::{OutPoint, TxOut};
+use zcash_transparent::keys::{AccountPrivKey, NonHardenedChildIndex};
+use zip32::AccountId;
+
+use crate::components::locks;
+use crate::schema::transparent_utxos;
+
+/// Number of blocks after which coinbase outputs can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Whom a stored output pays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Owner {
+    /// The transparent address of a wallet account.
+    Account(usize),
+    /// The address of the registered [`MinerKey`]. It belongs to no account,
+    /// so its outputs count towards no balance and are not in the history;
+    /// they are only shielded once mature.
+    ///
+    /// [`MinerKey`]: crate::components::miner::MinerKey
+    Miner,
+}
+
+/// The secret key of the transparent address of `account`.
+pub fn account_secret_key(seed: &[u8; 64], account: usize) -> SecretKey {
+    let account = AccountId::try_from(account as u32).unwrap();
+    AccountPrivKey::from_seed(&REGTEST_NETWORK, seed, account)
+        .unwrap()
+        .derive_external_secret_key(NonHardenedChildIndex::ZERO)
+        .unwrap()
+}
+
+/// The P2PKH address of `sk`.
+pub fn p2pkh_address(sk: &SecretKey) -> TransparentAddress {
+    let pubkey = sk.public_key(&Secp256k1::new()).serialize();
+    let hash = &Ripemd160::digest(Sha256::digest(pubkey))[..];
+    TransparentAddress::PublicKeyHash(hash.try_into().unwrap())
+}
+
+#[derive(Clone, Debug, PartialEq, Queryable, Selectable, Insertable)]
+#[diesel(table_name = transparent_utxos)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Utxo {
+    pub txid: Vec<u8>,
+    pub output_index: i32,
+    /// The owning account, `None` for outputs paying the miner.
+    pub account: Option<i32>,
+    pub is_miner: bool,
+    pub amount: i64,
+    pub is_coinbase: bool,
+    pub mined_height: i32,
+    pub spend_tx_id: Option<Vec<u8>>,
+    pub spend_height: Option<i32>,
+    pub locked_by_tx_id: Option<Vec<u8>>,
+    pub lock_expiry_height: Option<i32>,
+}
+
+impl Utxo {
+    /// An unspent output of a transaction mined at `mined_height`.
+    pub fn new(
+        txid: [u8; 32],
+        output_index: u32,
+        owner: Owner,
+        amount: u64,
+        is_coinbase: bool,
+        mined_height: u32,
+    ) -> Self {
+        let (account, is_miner) = owner.columns();
+        Utxo {
+            txid: txid.to_vec(),
+            output_index: output_index as i32,
+            account,
+            is_miner,
+            amount: amount as i64,
+            is_coinbase,
+            mined_height: mined_height as i32,
+            spend_tx_id: None,
+            spend_height: None,
+            locked_by_tx_id: None,
+            lock_expiry_height: None,
+        }
+    }
+
+    pub fn owner(&self) -> Owner {
+        match self.account {
+            Some(account) => Owner::Account(account as usize),
+            None => Owner::Miner,
+        }
+    }
+
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint::new(
+            self.txid.as_slice().try_into().unwrap(),
+            self.output_index as u32,
+        )
+    }
+
+    /// The output as the transaction builder needs it to spend it, assuming
+    /// it pays `address`.
+    pub fn tx_out(&self, address: &TransparentAddress) -> TxOut {
+        TxOut::new(
+            Zatoshis::from_u64(self.amount as u64).unwrap(),
+            address.script().into(),
+        )
+    }
+
+    /// Whether the output can be spent in a block at `height`: coinbase
+    /// outputs need [`COINBASE_MATURITY`] confirmations first.
+    pub fn is_mature(&self, height: u32) -> bool {
+        !self.is_coinbase || self.mined_height as u32 + COINBASE_MATURITY <= height
+    }
+}
+
+impl Owner {
+    /// The `account` and `is_miner` columns of the outputs paying the owner.
+    fn columns(self) -> (Option<i32>, bool) {
+        match self {
+            Owner::Account(account) => (Some(account as i32), false),
+            Owner::Miner => (None, true),
+        }
+    }
+}
+
+/// Store a discovered output. Outputs that are already known are left as they
+/// are.
+pub fn insert(conn: &mut SqliteConnection, utxo: &Utxo) {
+    use crate::schema::transparent_utxos::dsl::*;
+    diesel::insert_into(transparent_utxos)
+        .values(utxo)
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .expect("Error inserting transparent output");
+}
+
+/// The stored output at `outpoint`, spent or not.
+pub fn find(conn: &mut SqliteConnection, outpoint: &OutPoint) -> Option<Utxo> {
+    use crate::schema::transparent_utxos::dsl::*;
+    transparent_utxos
+        .filter(txid.eq(outpoint.hash().to_vec()))
+        .filter(output_index.eq(outpoint.n() as i32))
+        .select(Utxo::as_select())
+        .first(conn)
+        .optional()
+        .expect("Error loading transparent output")
+}
+
+/// Mark the output at `outpoint` as spent by `spend_txid` in the block at
+/// `height` and release its lock. Returns whether it belongs to the wallet.
+pub fn mark_spent(
+    conn: &mut SqliteConnection,
+    outpoint: &OutPoint,
+    spend_txid: &[u8; 32],
+    height: u32,
+) -> bool {
+    use crate::schema::transparent_utxos::dsl::*;
+    diesel::update(transparent_utxos)
+        .filter(txid.eq(outpoint.hash().to_vec()))
+        .filter(output_index.eq(outpoint.n() as i32))
+        .set((
+            spend_tx_id.eq(spend_txid.to_vec()),
+            spend_height.eq(height as i32),
+            locked_by_tx_id.eq(None::<Vec<u8>>),
+            lock_expiry_height.eq(None::<i32>),
+        ))
+        .execute(conn)
+        .expect("Error marking transparent output spent")
+        > 0
+}
+
+/// The unspent outputs paying `owner`, oldest first.
+pub fn unspent(conn: &mut SqliteConnection, owner: Owner) -> Vec<Utxo> {
+    use crate::schema::transparent_utxos::dsl::*;
+    let (owner_account, miner) = owner.columns();
+    transparent_utxos
+        .filter(account.is(owner_account).and(is_miner.eq(miner)))
+        .filter(spend_tx_id.is_null())
+        .order((mined_height, txid, output_index))
+        .select(Utxo::as_select())
+        .load(conn)
+        .expect("Error loading transparent outputs")
+}
+
+/// The unspent, unlocked outputs paying `owner` that can be spent in a block
+/// at `height`, oldest first.
+pub fn spendable(conn: &mut SqliteConnection, owner: Owner, height: u32) -> Vec<Utxo> {
+    unspent(conn, owner)
+        .into_iter()
+        .filter(|utxo| utxo.locked_by_tx_id.is_none() && utxo.is_mature(height))
+        .collect()
+}
+
+/// The sum of the unspent outputs of `account`, including locked and immature
+/// ones.
+pub fn balance(conn: &mut SqliteConnection, account: usize) -> u64 {
+    unspent(conn, Owner::Account(account))
+        .iter()
+        .map(|utxo| utxo.amount as u64)
+        .sum()
+}
+
+/// Lock `outpoints` for the transaction `lock_txid` expiring at
+/// `expiry_height`.
+pub fn lock(
+    conn: &mut SqliteConnection,
+    outpoints: &[OutPoint],
+    lock_txid: &[u8; 32],
+    expiry_height: u32,
+) {
+    use crate::schema::transparent_utxos::dsl::*;
+    for outpoint in outpoints {
+        diesel::update(transparent_utxos)
+            .filter(txid.eq(outpoint.hash().to_vec()))
+            .filter(output_index.eq(outpoint.n() as i32))
+            .filter(spend_tx_id.is_null())
+            .set((
+                locked_by_tx_id.eq(lock_txid.to_vec()),
+                lock_expiry_height.eq(expiry_height as i32),
+            ))
+            .execute(conn)
+            .expect("Error locking transparent outputs");
+    }
+}
+
+/// Release locks whose transaction can no longer be mined at `height`.
+/// Returns the number of outputs that became spendable again.
+pub fn unlock_expired(conn: &mut SqliteConnection, height: u32) -> usize {
+    locks::unlock_expired(conn, "transparent_utxos", height)
+}
+
+/// Forget all transparent outputs.
+pub fn clear(conn: &mut SqliteConnection) {
+    use crate::schema::transparent_utxos::dsl::*;
+    diesel::delete(transparent_utxos)
+        .execute(conn)
+        .expect("Error clearing transparent outputs");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::db;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_account_addresses() {
+        let seed = [7; 64];
+        let a0 = p2pkh_address(&account_secret_key(&seed, 0));
+        let a1 = p2pkh_address(&account_secret_key(&seed, 1));
+        assert_eq!(a0, p2pkh_address(&account_secret_key(&seed, 0)));
+        assert_ne!(a0, a1);
+    }
+
+    #[test]
+    fn test_utxo_operations() {
+        let db_file = NamedTempFile::new().unwrap();
+        let url = db_file.path().to_string_lossy().to_string();
+        let mut c = db::establish_connection(&url);
+
+        let first = Owner::Account(0);
+        let coinbase = Utxo::new([1; 32], 0, first, 625, true, 10);
+        let payment = Utxo::new([2; 32], 1, first, 40, false, 20);
+        let other = Utxo::new([3; 32], 0, Owner::Account(1), 7, false, 20);
+        let mined = Utxo::new([5; 32], 0, Owner::Miner, 500, true, 10);
+        for utxo in [&coinbase, &payment, &other, &mined] {
+            insert(&mut c, utxo);
+        }
+        // Seeing the same output again keeps the stored row.
+        insert(&mut c, &Utxo::new([2; 32], 1, first, 1, false, 21));
+        assert_eq!(find(&mut c, &payment.outpoint()), Some(payment.clone()));
+        assert_eq!(balance(&mut c, 0), 665);
+        assert_eq!(balance(&mut c, 1), 7);
+        // Outputs paying the miner belong to no account.
+        assert_eq!(mined.owner(), Owner::Miner);
+        assert_eq!(spendable(&mut c, Owner::Miner, 110), vec![mined.clone()]);
+
+        // The coinbase output matures 100 blocks after it was mined.
+        assert_eq!(spendable(&mut c, first, 109), vec![payment.clone()]);
+        assert_eq!(spendable(&mut c, first, 110).len(), 2);
+
+        lock(&mut c, &[payment.outpoint()], &[9; 32], 115);
+        assert_eq!(spendable(&mut c, first, 110), vec![coinbase.clone()]);
+        assert_eq!(unlock_expired(&mut c, 115), 0);
+        assert_eq!(unlock_expired(&mut c, 116), 1);
+        assert_eq!(spendable(&mut c, first, 116).len(), 2);
+
+        // Observing the spend on chain releases the lock.
+        lock(&mut c, &[coinbase.outpoint()], &[9; 32], 120);
+        assert!(mark_spent(&mut c, &coinbase.outpoint(), &[9; 32], 116));
+        assert!(!mark_spent(
+            &mut c,
+            &OutPoint::new([4; 32], 0),
+            &[9; 32],
+            116
+        ));
+        assert_eq!(balance(&mut c, 0), 40);
+        let spent = find(&mut c, &coinbase.outpoint()).unwrap();
+        assert_eq!(spent.spend_height, Some(116));
+        assert!(spent.locked_by_tx_id.is_none());
+
+        clear(&mut c);
+        assert_eq!(balance(&mut c, 0), 0);
+    }
+}
@@ -1,14 +1,22 @@
 //! SQLite-backed persistence for the note commitment tree and sync position.
+//!
+//! The tree is stored in a compact, versioned binary encoding split across
+//! several tables, so that each sync step only rewrites the parts of the tree
+//! that changed.
 
 use bridgetree::{BridgeTree, Checkpoint, MerkleBridge};
 use diesel::prelude::*;
-use incrementalmerkletree::frontier::NonEmptyFrontier;
+use incrementalmerkletree::frontier::{CommitmentTree, NonEmptyFrontier};
 use incrementalmerkletree::{Address, Level, Position};
 use orchard::tree::MerkleHashOrchard;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{self, Read, Write};
+use zcash_encoding::{CompactSize, Vector};
+use zcash_primitives::merkle_tree::read_commitment_tree;
 
 use crate::components::wallet::NOTE_COMMITMENT_TREE_DEPTH;
+use crate::model::TreeState;
 
 #[derive(Serialize, Deserialize)]
 struct SerAddress {
@@ -218,9 +226,182 @@ impl TryFrom<SerCheckpoint> for Checkpoint<u32> {
     }
 }
 
+// ---------------------------------------------------------------------------
+// Binary encoding
+// ---------------------------------------------------------------------------
+
+/// Format of the tree state written by [`save_tree_state`]. Format 0 is the
+/// legacy single JSON document, which is still read and is replaced by the
+/// current format on the next save.
+const TREE_STATE_FORMAT_VERSION: i32 = 1;
+
+/// Personalization of the digests that let [`save_tree_state`] skip bridges
+/// that did not change.
+const BRIDGE_DIGEST_PERSONALIZATION: &[u8; 16] = b"ZTxToolTreeBrdge";
+
+fn encode(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> Vec<u8> {
+    let mut bytes = vec![];
+    write(&mut bytes).expect("writing to a Vec does not fail");
+    bytes
+}
+
+fn decode<'a, T>(
+    bytes: &'a [u8],
+    read: impl FnOnce(&mut &'a [u8]) -> io::Result<T>,
+) -> Result<T, String> {
+    let mut reader = bytes;
+    let value = read(&mut reader).map_err(|e| format!("Invalid tree state encoding: {e}"))?;
+    if !reader.is_empty() {
+        return Err("Invalid tree state encoding: trailing bytes".to_string());
+    }
+    Ok(value)
+}
+
+fn bridge_digest(data: &[u8]) -> Vec<u8> {
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .personal(BRIDGE_DIGEST_PERSONALIZATION)
+        .hash(data)
+        .as_bytes()
+        .to_vec()
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_hash<W: Write>(writer: &mut W, hash: &MerkleHashOrchard) -> io::Result<()> {
+    writer.write_all(&hash.to_bytes())
+}
+
+fn read_hash<R: Read>(reader: &mut R) -> io::Result<MerkleHashOrchard> {
+    let mut bytes = [0; 32];
+    reader.read_exact(&mut bytes)?;
+    Option::from(MerkleHashOrchard::from_bytes(&bytes))
+        .ok_or_else(|| invalid_data("invalid Merkle hash".to_string()))
+}
+
+fn write_address<W: Write>(writer: &mut W, addr: &Address) -> io::Result<()> {
+    writer.write_all(&[u8::from(addr.level())])?;
+    write_u64(writer, addr.index())
+}
+
+fn read_address<R: Read>(reader: &mut R) -> io::Result<Address> {
+    let mut level = [0; 1];
+    reader.read_exact(&mut level)?;
+    Ok(Address::from_parts(
+        Level::from(level[0]),
+        read_u64(reader)?,
+    ))
+}
+
+fn write_positions<W: Write>(writer: &mut W, positions: &BTreeSet<Position>) -> io::Result<()> {
+    let positions: Vec<u64> = positions.iter().map(|p| u64::from(*p)).collect();
+    Vector::write(writer, &positions, |w, p| write_u64(w, *p))
+}
+
+fn read_positions<R: Read>(reader: &mut R) -> io::Result<BTreeSet<Position>> {
+    Ok(Vector::read(reader, read_u64)?
+        .into_iter()
+        .map(Position::from)
+        .collect())
+}
+
+fn write_frontier<W: Write>(
+    writer: &mut W,
+    frontier: &NonEmptyFrontier<MerkleHashOrchard>,
+) -> io::Result<()> {
+    write_u64(writer, u64::from(frontier.position()))?;
+    write_hash(writer, frontier.leaf())?;
+    Vector::write(writer, frontier.ommers(), write_hash)
+}
+
+fn read_frontier<R: Read>(reader: &mut R) -> io::Result<NonEmptyFrontier<MerkleHashOrchard>> {
+    let position = Position::from(read_u64(reader)?);
+    let leaf = read_hash(reader)?;
+    let ommers = Vector::read(&mut *reader, read_hash)?;
+    NonEmptyFrontier::from_parts(position, leaf, ommers)
+        .map_err(|e| invalid_data(format!("invalid frontier: {:?}", e)))
+}
+
+fn write_bridge<W: Write>(
+    writer: &mut W,
+    bridge: &MerkleBridge<MerkleHashOrchard>,
+) -> io::Result<()> {
+    match bridge.prior_position() {
+        Some(position) => {
+            writer.write_all(&[1])?;
+            write_u64(writer, u64::from(position))?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    let tracking: Vec<&Address> = bridge.tracking().iter().collect();
+    Vector::write(&mut *writer, &tracking, |w, addr| write_address(w, addr))?;
+    let ommers: Vec<(&Address, &MerkleHashOrchard)> = bridge.ommers().iter().collect();
+    Vector::write(&mut *writer, &ommers, |w, (addr, hash)| {
+        write_address(w, addr)?;
+        write_hash(w, hash)
+    })?;
+    write_frontier(writer, bridge.frontier())
+}
+
+fn read_bridge<R: Read>(reader: &mut R) -> io::Result<MerkleBridge<MerkleHashOrchard>> {
+    let mut flag = [0; 1];
+    reader.read_exact(&mut flag)?;
+    let prior_position = match flag[0] {
+        0 => None,
+        1 => Some(Position::from(read_u64(reader)?)),
+        f => return Err(invalid_data(format!("invalid prior position flag {f}"))),
+    };
+    let tracking = Vector::read(&mut *reader, read_address)?
+        .into_iter()
+        .collect();
+    let ommers = Vector::read(&mut *reader, |r| Ok((read_address(r)?, read_hash(r)?)))?
+        .into_iter()
+        .collect();
+    let frontier = read_frontier(reader)?;
+    Ok(MerkleBridge::from_parts(
+        prior_position,
+        tracking,
+        ommers,
+        frontier,
+    ))
+}
+
+/// Encode a checkpoint without its id, which is stored as the row key.
+fn write_checkpoint<W: Write>(writer: &mut W, checkpoint: &Checkpoint<u32>) -> io::Result<()> {
+    CompactSize::write(&mut *writer, checkpoint.bridges_len())?;
+    write_positions(writer, checkpoint.marked())?;
+    write_positions(writer, checkpoint.forgotten())
+}
+
+fn read_checkpoint<R: Read>(reader: &mut R, id: u32) -> io::Result<Checkpoint<u32>> {
+    let bridges_len = CompactSize::read(&mut *reader)? as usize;
+    let marked = read_positions(reader)?;
+    let forgotten = read_positions(reader)?;
+    Ok(Checkpoint::from_parts(id, bridges_len, marked, forgotten))
+}
+
 // ---------------------------------------------------------------------------
 // SQLite persistence
 // ---------------------------------------------------------------------------
+//
+// The tree is stored in parts so that a save only writes what changed since
+// the previous one: every prior bridge, checkpoint and marked position has its
+// own row, and the `wallet_state` row holds the current bridge together with
+// the sync position. Prior bridges are only ever appended to in practice, so
+// the per-block write is the current bridge plus any bridge created by the
+// block's marks.
 
 pub struct LoadedTreeState {
     pub commitment_tree: BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH>,
@@ -242,9 +423,19 @@ pub fn load_tree_state(conn: &mut SqliteConnection) -> Result<Option<LoadedTreeS
         return Ok(None);
     };
 
-    let ser_tree: SerTree = serde_json::from_str(&r.commitment_tree_json)
-        .map_err(|e| format!("Failed to deserialize saved tree state: {e}"))?;
-    let tree = BridgeTree::try_from(ser_tree)?;
+    let tree = match r.format_version {
+        0 => {
+            let json = r
+                .commitment_tree_json
+                .ok_or("Missing commitment tree in legacy tree state")?;
+            let ser_tree: SerTree = serde_json::from_str(&json)
+                .map_err(|e| format!("Failed to deserialize saved tree state: {e}"))?;
+            BridgeTree::try_from(ser_tree)?
+        }
+        TREE_STATE_FORMAT_VERSION => load_tree_parts(conn, r.current_bridge, r.max_checkpoints)
+            .map_err(|e| format!("Failed to load saved tree state: {e}"))?,
+        version => return Err(format!("Unsupported tree state format {version}")),
+    };
     Ok(Some(LoadedTreeState {
         commitment_tree: tree,
         last_block_height: r.last_block_height as u32,
@@ -252,29 +443,160 @@ pub fn load_tree_state(conn: &mut SqliteConnection) -> Result<Option<LoadedTreeS
     }))
 }
 
+fn load_tree_parts(
+    conn: &mut SqliteConnection,
+    current_bridge: Option<Vec<u8>>,
+    max_checkpoints: i32,
+) -> Result<BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH>, String> {
+    use crate::schema::{tree_bridges, tree_checkpoints, tree_marks};
+    let db_err = |e: diesel::result::Error| e.to_string();
+
+    let bridge_rows = tree_bridges::table
+        .order(tree_bridges::idx.asc())
+        .select(TreeBridgeRow::as_select())
+        .load(conn)
+        .map_err(db_err)?;
+    let mut prior_bridges = Vec::with_capacity(bridge_rows.len());
+    for (idx, row) in bridge_rows.into_iter().enumerate() {
+        if row.idx as usize != idx {
+            return Err(format!("missing bridge {idx}"));
+        }
+        prior_bridges.push(decode(&row.data, read_bridge)?);
+    }
+    let current_bridge = current_bridge
+        .map(|data| decode(&data, read_bridge))
+        .transpose()?;
+
+    let saved: BTreeMap<Position, usize> = tree_marks::table
+        .select(TreeMarkRow::as_select())
+        .load(conn)
+        .map_err(db_err)?
+        .into_iter()
+        .map(|m| (Position::from(m.position as u64), m.bridge_idx as usize))
+        .collect();
+
+    let checkpoints: VecDeque<Checkpoint<u32>> = tree_checkpoints::table
+        .order(tree_checkpoints::id.asc())
+        .select(TreeCheckpointRow::as_select())
+        .load(conn)
+        .map_err(db_err)?
+        .into_iter()
+        .map(|c| decode(&c.data, |r| read_checkpoint(r, c.id as u32)))
+        .collect::<Result<_, _>>()?;
+
+    BridgeTree::from_parts(
+        prior_bridges,
+        current_bridge,
+        saved,
+        checkpoints,
+        max_checkpoints as usize,
+    )
+    .map_err(|e| format!("BridgeTree::from_parts failed: {:?}", e))
+}
+
 /// Persist the commitment tree, block height, and block hash to SQLite.
+///
+/// Only the parts of the tree that differ from the stored ones are written.
 pub fn save_tree_state(
     conn: &mut SqliteConnection,
     tree: &BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH>,
     last_block_height: u32,
     last_block_hash: &str,
 ) -> Result<(), String> {
-    let ser_tree = SerTree::from(tree);
-    let json = serde_json::to_string(&ser_tree)
-        .map_err(|e| format!("Failed to serialize commitment tree: {e}"))?;
-
-    let new_row = WalletStateRow {
+    let bridges: Vec<TreeBridgeRow> = tree
+        .prior_bridges()
+        .iter()
+        .enumerate()
+        .map(|(idx, bridge)| {
+            let data = encode(|w| write_bridge(w, bridge));
+            TreeBridgeRow {
+                idx: idx as i32,
+                digest: bridge_digest(&data),
+                data,
+            }
+        })
+        .collect();
+    let checkpoints: BTreeMap<i32, Vec<u8>> = tree
+        .checkpoints()
+        .iter()
+        .map(|c| (*c.id() as i32, encode(|w| write_checkpoint(w, c))))
+        .collect();
+    let marks: BTreeMap<i64, i32> = tree
+        .marked_indices()
+        .iter()
+        .map(|(pos, idx)| (u64::from(*pos) as i64, *idx as i32))
+        .collect();
+    let state_row = WalletStateRow {
         id: 1,
-        commitment_tree_json: json,
+        format_version: TREE_STATE_FORMAT_VERSION,
+        commitment_tree_json: None,
+        current_bridge: tree
+            .current_bridge()
+            .as_ref()
+            .map(|b| encode(|w| write_bridge(w, b))),
+        max_checkpoints: tree.max_checkpoints() as i32,
         last_block_height: last_block_height as i32,
         last_block_hash: last_block_hash.to_string(),
     };
 
     conn.transaction(|c| {
-        use crate::schema::wallet_state::dsl as ws;
-        diesel::delete(ws::wallet_state).execute(c)?;
-        diesel::insert_into(ws::wallet_state)
-            .values(&new_row)
+        use crate::schema::{tree_bridges, tree_checkpoints, tree_marks, wallet_state};
+
+        let stored: BTreeMap<i32, Vec<u8>> = tree_bridges::table
+            .select((tree_bridges::idx, tree_bridges::digest))
+            .load::<(i32, Vec<u8>)>(c)?
+            .into_iter()
+            .collect();
+        for row in bridges
+            .iter()
+            .filter(|b| stored.get(&b.idx) != Some(&b.digest))
+        {
+            diesel::replace_into(tree_bridges::table)
+                .values(row)
+                .execute(c)?;
+        }
+        diesel::delete(tree_bridges::table.filter(tree_bridges::idx.ge(bridges.len() as i32)))
+            .execute(c)?;
+
+        let stored: BTreeMap<i32, Vec<u8>> = tree_checkpoints::table
+            .select((tree_checkpoints::id, tree_checkpoints::data))
+            .load::<(i32, Vec<u8>)>(c)?
+            .into_iter()
+            .collect();
+        for id in stored.keys().filter(|id| !checkpoints.contains_key(id)) {
+            diesel::delete(tree_checkpoints::table.find(*id)).execute(c)?;
+        }
+        for (id, data) in checkpoints
+            .iter()
+            .filter(|(id, data)| stored.get(id) != Some(data))
+        {
+            diesel::replace_into(tree_checkpoints::table)
+                .values(&TreeCheckpointRow {
+                    id: *id,
+                    data: data.clone(),
+                })
+                .execute(c)?;
+        }
+
+        let stored: BTreeMap<i64, i32> = tree_marks::table
+            .select((tree_marks::position, tree_marks::bridge_idx))
+            .load::<(i64, i32)>(c)?
+            .into_iter()
+            .collect();
+        for position in stored.keys().filter(|p| !marks.contains_key(p)) {
+            diesel::delete(tree_marks::table.find(*position)).execute(c)?;
+        }
+        for (position, bridge_idx) in marks.iter().filter(|(p, idx)| stored.get(p) != Some(idx)) {
+            diesel::replace_into(tree_marks::table)
+                .values(&TreeMarkRow {
+                    position: *position,
+                    bridge_idx: *bridge_idx,
+                })
+                .execute(c)?;
+        }
+
+        diesel::replace_into(wallet_state::table)
+            .values(&state_row)
             .execute(c)?;
         Ok::<_, diesel::result::Error>(())
     })
@@ -283,11 +605,50 @@ pub fn save_tree_state(
 
 /// Delete the persisted tree state from SQLite.
 pub fn delete_tree_state(conn: &mut SqliteConnection) -> Result<(), String> {
-    use crate::schema::wallet_state::dsl as ws;
-    diesel::delete(ws::wallet_state)
-        .execute(conn)
-        .map_err(|e| format!("Failed to delete tree state: {e}"))?;
-    Ok(())
+    use crate::schema::{tree_bridges, tree_checkpoints, tree_marks, wallet_state};
+    conn.transaction(|c| {
+        diesel::delete(wallet_state::table).execute(c)?;
+        diesel::delete(tree_bridges::table).execute(c)?;
+        diesel::delete(tree_checkpoints::table).execute(c)?;
+        diesel::delete(tree_marks::table).execute(c)?;
+        Ok::<_, diesel::result::Error>(())
+    })
+    .map_err(|e| format!("Failed to delete tree state: {e}"))
+}
+
+/// The node's Orchard commitment tree in `state`, empty if the node has no
+/// Orchard commitment yet.
+fn node_orchard_tree(
+    state: &TreeState,
+) -> Result<CommitmentTree<MerkleHashOrchard, NOTE_COMMITMENT_TREE_DEPTH>, String> {
+    match &state.orchard_tree {
+        Some(bytes) => read_commitment_tree(bytes.as_slice())
+            .map_err(|e| format!("Invalid Orchard tree state: {e}")),
+        None => Ok(CommitmentTree::empty()),
+    }
+}
+
+/// The frontier of the node's Orchard commitment tree in `state`, `None` if
+/// the tree is empty.
+pub fn node_orchard_frontier(
+    state: &TreeState,
+) -> Result<Option<NonEmptyFrontier<MerkleHashOrchard>>, String> {
+    Ok(node_orchard_tree(state)?.to_frontier().take())
+}
+
+/// The root of the node's Orchard commitment tree in `state`.
+pub fn node_orchard_root(state: &TreeState) -> Result<MerkleHashOrchard, String> {
+    Ok(node_orchard_tree(state)?.root())
+}
+
+/// The node's Sapling commitment tree in `state`, empty if the node has no
+/// Sapling commitment yet.
+pub fn node_sapling_tree(state: &TreeState) -> Result<sapling_crypto::CommitmentTree, String> {
+    match &state.sapling_tree {
+        Some(bytes) => read_commitment_tree(bytes.as_slice())
+            .map_err(|e| format!("Invalid Sapling tree state: {e}")),
+        None => Ok(sapling_crypto::CommitmentTree::empty()),
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -299,11 +660,40 @@ pub fn delete_tree_state(conn: &mut SqliteConnection) -> Result<(), String> {
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 struct WalletStateRow {
     id: i32,
-    commitment_tree_json: String,
+    format_version: i32,
+    /// The whole tree as JSON, only set by format 0.
+    commitment_tree_json: Option<String>,
+    current_bridge: Option<Vec<u8>>,
+    max_checkpoints: i32,
     last_block_height: i32,
     last_block_hash: String,
 }
 
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::tree_bridges)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct TreeBridgeRow {
+    idx: i32,
+    digest: Vec<u8>,
+    data: Vec<u8>,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::tree_checkpoints)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct TreeCheckpointRow {
+    id: i32,
+    data: Vec<u8>,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::tree_marks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct TreeMarkRow {
+    position: i64,
+    bridge_idx: i32,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -352,57 +742,117 @@ mod tests {
         assert_eq!(tree, restored);
     }
 
+    #[test]
+    fn test_binary_bridge_roundtrip() {
+        let mut tree: BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH> =
+            BridgeTree::new(MAX_CHECKPOINTS);
+        for i in 0..5u8 {
+            tree.append(MerkleHashOrchard::from_bytes(&[i; 32]).unwrap());
+            tree.mark();
+        }
+
+        for bridge in tree.prior_bridges().iter().chain(tree.current_bridge()) {
+            let data = encode(|w| write_bridge(w, bridge));
+            assert_eq!(&decode(&data, read_bridge).unwrap(), bridge);
+        }
+        let data = encode(|w| write_bridge(w, tree.current_bridge().as_ref().unwrap()));
+        assert!(decode(&data[..data.len() - 1], read_bridge).is_err());
+    }
+
     #[test]
     fn test_db_save_load_delete() {
         let (_db, url) = test_db_url();
         let mut conn = db::establish_connection(&url);
 
         // No state initially
-        {
-            use crate::schema::wallet_state::dsl as ws;
-            let rows: Vec<WalletStateRow> = ws::wallet_state
-                .select(WalletStateRow::as_select())
-                .load(&mut conn)
-                .unwrap_or_default();
-            assert!(rows.is_empty());
-        }
+        assert!(load_tree_state(&mut conn).unwrap().is_none());
 
-        // Save
+        // Save and load
         let tree: BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH> =
             BridgeTree::new(MAX_CHECKPOINTS);
+        save_tree_state(&mut conn, &tree, 42, "abcdef").unwrap();
+        let loaded = load_tree_state(&mut conn).unwrap().unwrap();
+        assert_eq!(loaded.commitment_tree, tree);
+        assert_eq!(loaded.last_block_height, 42);
+        assert_eq!(loaded.last_block_hash, "abcdef");
+
+        // Incremental saves: new bridges, marks and checkpoints, with old
+        // checkpoints dropped again once there are more than two.
+        let mut tree: BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH> =
+            BridgeTree::new(2);
+        for i in 0..6u8 {
+            tree.append(MerkleHashOrchard::from_bytes(&[i; 32]).unwrap());
+            if i % 2 == 0 {
+                tree.mark();
+            }
+            tree.checkpoint(u32::from(i));
+            save_tree_state(&mut conn, &tree, 43 + u32::from(i), "abcdef").unwrap();
+            let loaded = load_tree_state(&mut conn).unwrap().unwrap();
+            assert_eq!(loaded.commitment_tree, tree);
+            assert_eq!(loaded.last_block_height, 43 + u32::from(i));
+        }
+
+        // A tree rebuilt with a different early leaf changes bridges in the
+        // middle, not just at the end.
+        let mut rebuilt: BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH> =
+            BridgeTree::new(2);
+        for i in 0..6u8 {
+            let leaf = if i == 1 { 0xff } else { i };
+            rebuilt.append(MerkleHashOrchard::from_bytes(&[leaf; 32]).unwrap());
+            if i % 2 == 0 {
+                rebuilt.mark();
+            }
+            rebuilt.checkpoint(u32::from(i));
+        }
+        save_tree_state(&mut conn, &rebuilt, 49, "abcdef").unwrap();
+        let loaded = load_tree_state(&mut conn).unwrap().unwrap();
+        assert_eq!(loaded.commitment_tree, rebuilt);
+
+        // Delete
+        delete_tree_state(&mut conn).unwrap();
+        assert!(load_tree_state(&mut conn).unwrap().is_none());
+        use crate::schema::tree_bridges::dsl as tb;
+        assert_eq!(
+            tb::tree_bridges
+                .count()
+                .get_result::<i64>(&mut conn)
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_load_legacy_json_state() {
+        let (_db, url) = test_db_url();
+        let mut conn = db::establish_connection(&url);
+
+        let mut tree: BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH> =
+            BridgeTree::new(MAX_CHECKPOINTS);
+        tree.append(MerkleHashOrchard::from_bytes(&[1u8; 32]).unwrap());
+        tree.mark();
+        tree.checkpoint(0);
         let json = serde_json::to_string(&SerTree::from(&tree)).unwrap();
-        let new_row = WalletStateRow {
-            id: 1,
-            commitment_tree_json: json,
-            last_block_height: 42,
-            last_block_hash: "abcdef".to_string(),
-        };
         diesel::insert_into(crate::schema::wallet_state::table)
-            .values(&new_row)
+            .values(&WalletStateRow {
+                id: 1,
+                format_version: 0,
+                commitment_tree_json: Some(json),
+                current_bridge: None,
+                max_checkpoints: 0,
+                last_block_height: 7,
+                last_block_hash: "abcdef".to_string(),
+            })
             .execute(&mut conn)
             .unwrap();
 
-        // Load
-        {
-            use crate::schema::wallet_state::dsl as ws;
-            let rows: Vec<WalletStateRow> = ws::wallet_state
-                .select(WalletStateRow::as_select())
-                .load(&mut conn)
-                .unwrap();
-            assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].last_block_height, 42);
-            assert_eq!(rows[0].last_block_hash, "abcdef");
-        }
+        let loaded = load_tree_state(&mut conn).unwrap().unwrap();
+        assert_eq!(loaded.commitment_tree, tree);
+        assert_eq!(loaded.last_block_height, 7);
 
-        // Delete
-        {
-            use crate::schema::wallet_state::dsl as ws;
-            diesel::delete(ws::wallet_state).execute(&mut conn).unwrap();
-            let rows: Vec<WalletStateRow> = ws::wallet_state
-                .select(WalletStateRow::as_select())
-                .load(&mut conn)
-                .unwrap();
-            assert!(rows.is_empty());
-        }
+        // The next save replaces the JSON with the binary format.
+        save_tree_state(&mut conn, &loaded.commitment_tree, 8, "abcdef").unwrap();
+        let loaded = load_tree_state(&mut conn).unwrap().unwrap();
+        assert_eq!(loaded.commitment_tree, tree);
+        assert_eq!(loaded.last_block_height, 8);
     }
 }
@@ -3,8 +3,9 @@ mod structs;
 
 use bridgetree::{self, BridgeTree};
 use incrementalmerkletree::Position;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
+use std::fmt;
 
 use abscissa_core::prelude::info;
 
@@ -18,9 +19,22 @@ use orchard::tree::{MerkleHashOrchard, MerklePath};
 use orchard::value::NoteValue;
 use orchard::{bundle::Authorized, Address, Anchor, Bundle, Note};
 
-use crate::components::persistence::model::NoteData;
+use crate::components::persistence::model::{NoteData, SentNoteData};
 use crate::components::persistence::sqlite as notes_db;
 use crate::components::wallet::structs::OrderedAddress;
+use crate::components::miner::{CoinbaseRecipient, MinerKey};
+use crate::config::{CoinbaseMode, HistoryRootMode, WalletSection};
+use crate::model::TreeState;
+pub use crate::components::wallet::structs::AssetBalances;
+use crate::components::history::{self, TxRecord, TxValue};
+use crate::components::sapling::{self, SaplingNoteData};
+use crate::components::scanner::{
+    DecryptedOutput, SaplingDecryptedOutput, ScanKeys, ScannedBlock, ScannedTransaction,
+};
+use crate::components::block_data::RetentionPolicy;
+use crate::components::equihash;
+use crate::components::history_tree::{self, HistoryTree};
+use crate::components::transparent::{self, Owner, Utxo};
 use crate::components::{block_data, tree_state};
 use diesel::prelude::*;
 use diesel::SqliteConnection;
@@ -33,6 +47,15 @@ use orchard::primitives::OrchardPrimitives;
 use zip32::AccountId;
 use zcash_protocol::constants;
 use zcash_protocol::value::ZatBalance;
+use serde_json::json;
+use sapling_crypto::keys::PreparedIncomingViewingKey;
+use sapling_crypto::zip32::{DiversifiableFullViewingKey, ExtendedSpendingKey};
+use sapling_crypto::{
+    Bundle as SaplingBundle, IncrementalWitness, Node as SaplingNode, PaymentAddress, SaplingIvk,
+};
+use secp256k1::SecretKey;
+use zcash_transparent::address::TransparentAddress;
+use zcash_transparent::bundle::{Authorized as TransparentAuthorized, Bundle as TransparentBundle};
 
 pub const MAX_CHECKPOINTS: usize = 100;
 pub const NOTE_COMMITMENT_TREE_DEPTH: u8 = 32;
@@ -55,6 +78,24 @@ pub enum SyncError {
     Bundle(BundleError),
     Diesel(diesel::result::Error),
     TreeState(String),
+    /// The block at `height` does not build on the stored block below it: the
+    /// chain was reorganized.
+    Reorg {
+        height: u32,
+    },
+    /// The chain was still being reorganized at `height` after resyncing
+    /// `resyncs` times in a row.
+    UnstableChain {
+        height: u32,
+        resyncs: u32,
+    },
+    /// The local Orchard tree root after the block at `height` differs from
+    /// the root reported by the node.
+    RootMismatch {
+        height: u32,
+        local: String,
+        node: String,
+    },
 }
 
 impl From<BundleError> for SyncError {
@@ -73,8 +114,10 @@ impl From<String> for SyncError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct NoteSpendMetadata {
+    /// Row id of the note in the wallet database, used to lock it once spent.
+    pub note_id: i32,
     pub note: Note,
     pub sk: SpendingKey,
     pub merkle_path: MerklePath,
@@ -84,6 +127,8 @@ struct KeyStore {
     payment_addresses: BTreeMap<OrderedAddress, IncomingViewingKey>,
     viewing_keys: BTreeMap<IncomingViewingKey, FullViewingKey>,
     spending_keys: BTreeMap<FullViewingKey, SpendingKey>,
+    /// ZIP-32 account index of every full viewing key derived from the seed.
+    accounts: BTreeMap<usize, FullViewingKey>,
 }
 
 impl KeyStore {
@@ -92,6 +137,7 @@ impl KeyStore {
             payment_addresses: BTreeMap::new(),
             viewing_keys: BTreeMap::new(),
             spending_keys: BTreeMap::new(),
+            accounts: BTreeMap::new(),
         }
     }
 
@@ -119,14 +165,36 @@ impl KeyStore {
         has_fvk
     }
 
-    pub fn spending_key_for_ivk(&self, ivk: &IncomingViewingKey) -> Option<&SpendingKey> {
-        self.viewing_keys
-            .get(ivk)
+    pub fn spending_key_for_account(&self, account: usize) -> Option<&SpendingKey> {
+        self.accounts
+            .get(&account)
             .and_then(|fvk| self.spending_keys.get(fvk))
     }
 
+    /// Looks up the IVK for `addr`, falling back to trial-deriving it from every
+    /// known viewing key so that internal-scope and diversified addresses which
+    /// were never explicitly registered are still recognised.
     pub fn ivk_for_address(&self, addr: &Address) -> Option<&IncomingViewingKey> {
-        self.payment_addresses.get(&OrderedAddress::new(*addr))
+        self.payment_addresses
+            .get(&OrderedAddress::new(*addr))
+            .or_else(|| {
+                self.viewing_keys
+                    .keys()
+                    .find(|ivk| ivk.diversifier_index(addr).is_some())
+            })
+    }
+
+    pub fn add_account(&mut self, account: usize, fvk: FullViewingKey) {
+        self.accounts.insert(account, fvk);
+    }
+
+    /// Returns the account whose full viewing key can derive `addr`, in either
+    /// scope and at any diversifier index.
+    pub fn account_for_address(&self, addr: &Address) -> Option<usize> {
+        self.accounts
+            .iter()
+            .find(|(_, fvk)| fvk.scope_for_address(addr).is_some())
+            .map(|(account, _)| *account)
     }
 }
 
@@ -142,6 +210,41 @@ pub struct Wallet {
     last_block_hash: Option<BlockHash>,
     /// The seed used to derive the user's keys.
     seed: [u8; 64],
+    /// Height of the first block that can contain wallet transactions, if known.
+    birthday: Option<u32>,
+    /// Whether sync compares the local Orchard root with the node's.
+    verify_orchard_root: bool,
+    /// Which block hashes sync keeps in `block_data`, `None` for all of them.
+    block_data_retention: Option<RetentionPolicy>,
+    /// Who the coinbase of mined blocks pays, `None` for the node's coinbase.
+    coinbase_recipient: Option<CoinbaseRecipient>,
+    /// The ZIP-221 history tree up to the last synced block, when every block
+    /// of the current network upgrade was synced.
+    history_tree: Option<HistoryTree>,
+    /// How mined blocks get their chain history root.
+    history_root_mode: HistoryRootMode,
+    /// Equihash parameters mined blocks are solved for, `None` to skip it.
+    equihash_params: Option<equihash::Params>,
+    /// The transparent addresses of the registered accounts, whose outputs
+    /// sync stores as UTXOs.
+    transparent_accounts: HashMap<TransparentAddress, usize>,
+    /// The miner's transparent address and its key. Sync stores the outputs
+    /// paying it as owned by [`Owner::Miner`].
+    miner_key: Option<(TransparentAddress, SecretKey)>,
+    /// The Sapling note commitment tree up to the last synced block.
+    sapling_tree: sapling_crypto::CommitmentTree,
+    /// The Sapling viewing keys of the registered accounts.
+    sapling_accounts: BTreeMap<usize, DiversifiableFullViewingKey>,
+}
+
+impl fmt::Debug for Wallet {
+    /// Only the sync position, to keep the keys out of logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wallet")
+            .field("last_block_height", &self.last_block_height)
+            .field("last_block_hash", &self.last_block_hash)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Wallet {
@@ -152,9 +255,42 @@ impl Wallet {
             last_block_height: None,
             last_block_hash: None,
             seed,
+            birthday: None,
+            verify_orchard_root: false,
+            block_data_retention: None,
+            coinbase_recipient: None,
+            history_tree: None,
+            history_root_mode: HistoryRootMode::Node,
+            equihash_params: None,
+            transparent_accounts: HashMap::new(),
+            miner_key: None,
+            sapling_tree: sapling_crypto::CommitmentTree::empty(),
+            sapling_accounts: BTreeMap::new(),
         }
     }
 
+    /// [`Wallet::new`] with the seed phrase and birthday from the `[wallet]`
+    /// config section.
+    pub fn open(conn: &mut SqliteConnection, config: &WalletSection) -> Self {
+        let mut wallet = Self::new(conn, &config.seed_phrase);
+        wallet.birthday = config.birthday_height;
+        wallet.verify_orchard_root = config.verify_orchard_root;
+        wallet.block_data_retention = config.block_data_retention();
+        wallet.history_root_mode = config.chain_history_root;
+        wallet.equihash_params = config.proof_of_work.equihash_params();
+        wallet.register_miner_key(&MinerKey::new(&config.miner_seed_phrase));
+        wallet.coinbase_recipient = match config.coinbase {
+            CoinbaseMode::Node => None,
+            CoinbaseMode::Transparent => Some(CoinbaseRecipient::Transparent(
+                MinerKey::new(&config.miner_seed_phrase).address(),
+            )),
+            CoinbaseMode::Orchard => Some(CoinbaseRecipient::Orchard(
+                wallet.address_for_account(config.coinbase_account as usize, Scope::External),
+            )),
+        };
+        wallet
+    }
+
     /// Construct a `Wallet` from a seed phrase and restore any persisted
     /// commitment tree / sync position from SQLite. If no `wallet_state` row
     /// exists, returns a fresh wallet.
@@ -183,13 +319,28 @@ impl Wallet {
                         state.last_block_hash
                     )
                 })?;
+            // Wallets synced before Sapling support have no Sapling tree. The
+            // chain may hold Sapling outputs the wallet never appended, so the
+            // wallet starts over and the next sync rebuilds both trees.
+            let Some(sapling_tree) = sapling::load_tree(conn)? else {
+                info!(
+                    "No Sapling tree saved at height {}, resetting wallet",
+                    state.last_block_height
+                );
+                self.reset(conn);
+                return Ok(());
+            };
             info!(
                 "Loaded saved tree state at height {}",
                 state.last_block_height
             );
             self.commitment_tree = state.commitment_tree;
+            self.sapling_tree = sapling_tree;
             self.last_block_height = Some(BlockHeight::from_u32(state.last_block_height));
             self.last_block_hash = Some(BlockHash(hash_bytes));
+            // A history tree that does not end at the sync position is stale.
+            self.history_tree =
+                history_tree::load(conn)?.filter(|t| t.end_height() == state.last_block_height);
         }
         Ok(())
     }
@@ -199,13 +350,88 @@ impl Wallet {
     /// when chain/wallet divergence is detected.
     pub fn reset(&mut self, conn: &mut SqliteConnection) {
         self.commitment_tree = BridgeTree::new(MAX_CHECKPOINTS);
+        self.sapling_tree = sapling_crypto::CommitmentTree::empty();
         self.last_block_height = None;
         self.last_block_hash = None;
+        self.history_tree = None;
         notes_db::delete_all_notes(conn);
+        notes_db::delete_all_sent_notes(conn);
+        history::clear(conn);
+        transparent::clear(conn);
+        sapling::clear(conn);
         tree_state::delete_tree_state(conn).expect("Failed to delete tree state");
+        history_tree::clear(conn).expect("Failed to delete history tree");
         block_data::clear(conn);
     }
 
+    pub fn birthday(&self) -> Option<u32> {
+        self.birthday
+    }
+
+    /// Whether sync checks the local Orchard root against the node's after
+    /// every block.
+    pub fn verifies_orchard_root(&self) -> bool {
+        self.verify_orchard_root
+    }
+
+    /// The policy sync prunes `block_data` with, if any.
+    pub fn block_data_retention(&self) -> Option<RetentionPolicy> {
+        self.block_data_retention
+    }
+
+    /// The recipient of locally built coinbase transactions, if the node's
+    /// coinbase is not used.
+    pub fn coinbase_recipient(&self) -> Option<&CoinbaseRecipient> {
+        self.coinbase_recipient.as_ref()
+    }
+
+    /// The chain history tree up to the last synced block, if available.
+    pub fn history_tree(&self) -> Option<&HistoryTree> {
+        self.history_tree.as_ref()
+    }
+
+    /// How mined blocks get their chain history root.
+    pub fn history_root_mode(&self) -> HistoryRootMode {
+        self.history_root_mode
+    }
+
+    /// The Equihash parameters mined blocks are solved for, if any.
+    pub fn equihash_params(&self) -> Option<equihash::Params> {
+        self.equihash_params
+    }
+
+    /// Start the wallet from the node's tree state after block `state.height`
+    /// instead of an empty commitment tree, so that sync can begin right
+    /// after it. Only meaningful for a wallet that has not synced any block.
+    pub fn init_from_tree_state(
+        &mut self,
+        conn: &mut SqliteConnection,
+        state: &TreeState,
+    ) -> Result<(), SyncError> {
+        let frontier = tree_state::node_orchard_frontier(state)?;
+        let sapling_tree = tree_state::node_sapling_tree(state)?;
+        let height = u32::from(state.height);
+        let hash_hex = hex::encode(state.hash.0);
+
+        self.commitment_tree = match frontier {
+            Some(frontier) => BridgeTree::from_frontier(MAX_CHECKPOINTS, frontier),
+            None => BridgeTree::new(MAX_CHECKPOINTS),
+        };
+        self.sapling_tree = sapling_tree;
+        self.last_block_height = Some(state.height);
+        self.last_block_hash = Some(state.hash);
+        // The blocks below the birthday are never synced, so there is no
+        // history tree until the next network upgrade.
+        self.history_tree = None;
+        conn.transaction(|c| {
+            block_data::insert(c, height, hash_hex.clone());
+            tree_state::save_tree_state(c, &self.commitment_tree, height, &hash_hex)?;
+            sapling::save_tree(c, &self.sapling_tree)?;
+            history_tree::clear(c)?;
+            Ok(())
+        })
+    }
+
     pub fn last_block_hash(&self) -> Option<BlockHash> {
         self.last_block_hash
     }
@@ -214,14 +440,24 @@ impl Wallet {
         self.last_block_height
     }
 
+    /// Select unspent notes of `asset` held by any address of `account` (both
+    /// scopes, all diversifiers) until their total covers `total_amount`.
+    /// Notes locked by a pending transaction are skipped.
     pub(crate) fn select_spendable_notes(
         &mut self,
         conn: &mut SqliteConnection,
-        address: Address,
+        account: usize,
         total_amount: u64,
         asset: AssetBase,
     ) -> Vec<NoteSpendMetadata> {
-        let all_notes = notes_db::find_non_spent_notes(conn, address, asset);
+        let sk = *self
+            .key_store
+            .spending_key_for_account(account)
+            .expect("SpendingKey not found for account");
+        let fvk = FullViewingKey::from(&sk);
+        let all_notes = notes_db::find_spendable_notes_for_asset(conn, asset)
+            .into_iter()
+            .filter(|n| fvk.scope_for_address(&note_recipient(n)).is_some());
         let mut selected_notes = Vec::new();
         let mut total_amount_selected = 0;
 
@@ -241,14 +477,6 @@ impl Wallet {
             .unwrap();
 
             let note_value = note.value().inner();
-            let sk = self
-                .key_store
-                .spending_key_for_ivk(
-                    self.key_store
-                        .ivk_for_address(&note.recipient())
-                        .expect("IVK not found for address"),
-                )
-                .expect("SpendingKey not found for IVK");
 
             let merkle_path = MerklePath::from_parts(
                 note_data.position as u32,
@@ -260,8 +488,9 @@ impl Wallet {
             );
 
             selected_notes.push(NoteSpendMetadata {
+                note_id: note_data.id,
                 note,
-                sk: *sk,
+                sk,
                 merkle_path,
             });
             total_amount_selected += note_value;
@@ -280,35 +509,215 @@ impl Wallet {
         selected_notes
     }
 
-    pub fn address_for_account(&mut self, account: usize, scope: Scope) -> Address {
-        let sk = SpendingKey::from_zip32_seed(
+    /// Lock the notes spent by `tx` until it is mined or expires, so that
+    /// transactions built before the next sync do not double-spend them.
+    pub(crate) fn lock_notes(
+        &self,
+        conn: &mut SqliteConnection,
+        note_ids: &[i32],
+        tx: &Transaction,
+    ) {
+        notes_db::lock_notes(conn, note_ids, &tx.txid(), u32::from(tx.expiry_height()));
+    }
+
+    /// Number of confirmations of `note` relative to the last synced block.
+    pub fn confirmations(&self, note: &NoteData) -> u32 {
+        self.last_block_height
+            .map(|h| note.confirmations(u32::from(h)))
+            .unwrap_or(0)
+    }
+
+    fn account_spending_key(&self, account: usize) -> SpendingKey {
+        SpendingKey::from_zip32_seed(
             self.seed.as_slice(),
             constants::regtest::COIN_TYPE,
             AccountId::try_from(account as u32).unwrap(),
         )
-        .unwrap();
+        .unwrap()
+    }
+
+    pub fn address_for_account(&mut self, account: usize, scope: Scope) -> Address {
+        let sk = self.account_spending_key(account);
         let fvk = FullViewingKey::from(&sk);
+        // Register both scopes so that notes sent to the account's internal
+        // (change) address are recognised during sync even when only the
+        // external address was ever requested.
+        for s in [Scope::External, Scope::Internal] {
+            self.key_store
+                .add_raw_address(fvk.address_at(0u32, s), fvk.to_ivk(s));
+        }
         let address = fvk.address_at(0u32, scope);
-        self.key_store.add_raw_address(address, fvk.to_ivk(scope));
-        self.key_store.add_full_viewing_key(fvk);
+        let t_address = transparent::p2pkh_address(&self.transparent_secret_key(account));
+        self.transparent_accounts.insert(t_address, account);
+        self.sapling_accounts.insert(
+            account,
+            self.sapling_spending_key(account)
+                .to_diversifiable_full_viewing_key(),
+        );
+        self.key_store.add_full_viewing_key(fvk.clone());
         self.key_store.add_spending_key(sk);
+        self.key_store.add_account(account, fvk);
         address
     }
 
+    /// The ZIP-32 internal-scope address of `account`, used as the recipient of
+    /// change outputs so that change is not linkable to the public address.
+    pub fn change_address(&mut self, account: usize) -> Address {
+        self.address_for_account(account, Scope::Internal)
+    }
+
+    /// The account owning `address`, if it was derived from this wallet's seed
+    /// for an account previously registered via [`Wallet::address_for_account`].
+    pub fn account_for_address(&self, address: &Address) -> Option<usize> {
+        self.key_store.account_for_address(address)
+    }
+
+    /// The transparent P2PKH address of `account`. Like
+    /// [`Wallet::address_for_account`], this registers the account so that
+    /// sync picks up the outputs paying it.
+    pub fn transparent_address(&mut self, account: usize) -> TransparentAddress {
+        self.address_for_account(account, Scope::External);
+        transparent::p2pkh_address(&self.transparent_secret_key(account))
+    }
+
+    /// The account owning the transparent `address`, if it was registered.
+    pub fn account_for_transparent_address(&self, address: &TransparentAddress) -> Option<usize> {
+        self.transparent_accounts.get(address).copied()
+    }
+
+    /// Track the coinbase outputs paid to `miner_key` during sync, apart from
+    /// the accounts' outputs, so they can be shielded once mature.
+    pub fn register_miner_key(&mut self, miner_key: &MinerKey) {
+        self.miner_key = Some((miner_key.address(), miner_key.secret_key()));
+    }
+
+    /// Whom the outputs paying the transparent `address` belong to, if sync
+    /// tracks them.
+    fn transparent_owner(&self, address: &TransparentAddress) -> Option<Owner> {
+        match self.account_for_transparent_address(address) {
+            Some(account) => Some(Owner::Account(account)),
+            None => self
+                .miner_key
+                .as_ref()
+                .filter(|(miner, _)| miner == address)
+                .map(|_| Owner::Miner),
+        }
+    }
+
+    /// The default Sapling address of `account`, which also receives its
+    /// Sapling change. Like [`Wallet::address_for_account`], this registers
+    /// the account so that sync picks up the notes paying it.
+    pub fn sapling_address(&mut self, account: usize) -> PaymentAddress {
+        self.address_for_account(account, Scope::External);
+        self.sapling_accounts[&account].default_address().1
+    }
+
+    /// The account owning the Sapling `address`, if it was registered.
+    pub fn account_for_sapling_address(&self, address: &PaymentAddress) -> Option<usize> {
+        self.sapling_accounts
+            .iter()
+            .find(|(_, dfvk)| dfvk.decrypt_diversifier(address).is_some())
+            .map(|(account, _)| *account)
+    }
+
+    /// The Sapling incoming viewing key of the default address of `account`,
+    /// which must be registered.
+    pub(crate) fn sapling_ivk(&self, account: usize) -> SaplingIvk {
+        self.sapling_accounts[&account].to_ivk(zip32::Scope::External)
+    }
+
+    pub(crate) fn sapling_spending_key(&self, account: usize) -> ExtendedSpendingKey {
+        sapling::account_spending_key(&self.seed, account)
+    }
+
+    /// Lock the Sapling notes spent by `tx` until it is mined or expires, like
+    /// [`Wallet::lock_notes`].
+    pub(crate) fn lock_sapling_notes(
+        &self,
+        conn: &mut SqliteConnection,
+        notes: &[SaplingNoteData],
+        tx: &Transaction,
+    ) {
+        let nullifiers: Vec<_> = notes.iter().map(|note| note.nullifier.clone()).collect();
+        sapling::lock(
+            conn,
+            &nullifiers,
+            tx.txid().as_ref(),
+            u32::from(tx.expiry_height()),
+        );
+    }
+
+    pub(crate) fn transparent_secret_key(&self, account: usize) -> SecretKey {
+        transparent::account_secret_key(&self.seed, account)
+    }
+
+    /// The key spending `utxo`.
+    pub(crate) fn utxo_secret_key(&self, utxo: &Utxo) -> SecretKey {
+        match utxo.owner() {
+            Owner::Account(account) => self.transparent_secret_key(account),
+            Owner::Miner => self.miner_key.as_ref().expect("miner key is registered").1,
+        }
+    }
+
+    /// The unspent transparent outputs of `account` that can be spent in the
+    /// next block, oldest first.
+    pub(crate) fn spendable_utxos(&self, conn: &mut SqliteConnection, account: usize) -> Vec<Utxo> {
+        let next_height = self.last_block_height.map_or(0, |h| u32::from(h) + 1);
+        transparent::spendable(conn, Owner::Account(account), next_height)
+    }
+
+    /// The coinbase outputs paid to the registered miner key that can be
+    /// spent in the next block, oldest first.
+    pub(crate) fn mature_coinbase_utxos(&self, conn: &mut SqliteConnection) -> Vec<Utxo> {
+        let next_height = self.last_block_height.map_or(0, |h| u32::from(h) + 1);
+        transparent::spendable(conn, Owner::Miner, next_height)
+            .into_iter()
+            .filter(|utxo| utxo.is_coinbase)
+            .collect()
+    }
+
+    /// Lock the transparent outputs spent by `tx` until it is mined or
+    /// expires, like [`Wallet::lock_notes`].
+    pub(crate) fn lock_utxos(&self, conn: &mut SqliteConnection, utxos: &[Utxo], tx: &Transaction) {
+        let outpoints: Vec<_> = utxos.iter().map(Utxo::outpoint).collect();
+        transparent::lock(
+            conn,
+            &outpoints,
+            tx.txid().as_ref(),
+            u32::from(tx.expiry_height()),
+        );
+    }
+
     pub(crate) fn orchard_ovk(&self) -> OutgoingViewingKey {
-        let sk = SpendingKey::from_zip32_seed(
-            self.seed.as_slice(),
-            constants::regtest::COIN_TYPE,
-            AccountId::try_from(0).unwrap(),
-        )
-        .unwrap();
-        FullViewingKey::from(&sk).to_ovk(Scope::External)
+        FullViewingKey::from(&self.account_spending_key(0)).to_ovk(Scope::External)
+    }
+
+    /// The external-scope OVK of `account`, used to encrypt outputs paid by it.
+    pub(crate) fn account_ovk(&self, account: usize) -> OutgoingViewingKey {
+        FullViewingKey::from(&self.account_spending_key(account)).to_ovk(Scope::External)
+    }
+
+    /// The internal-scope OVK of `account`, used to encrypt change outputs.
+    pub(crate) fn internal_ovk(&self, account: usize) -> OutgoingViewingKey {
+        FullViewingKey::from(&self.account_spending_key(account)).to_ovk(Scope::Internal)
+    }
+
+    /// The Sapling OVK of `account`, used to encrypt Sapling outputs paid by
+    /// it.
+    pub(crate) fn sapling_ovk(&self, account: usize) -> sapling_crypto::keys::OutgoingViewingKey {
+        self.sapling_spending_key(account)
+            .to_diversifiable_full_viewing_key()
+            .to_ovk(zip32::Scope::External)
     }
 
     pub(crate) fn orchard_anchor(&self) -> Option<Anchor> {
         Some(Anchor::from(self.commitment_tree.root(0).unwrap()))
     }
 
+    pub(crate) fn sapling_anchor(&self) -> sapling_crypto::Anchor {
+        sapling_crypto::Anchor::from(self.sapling_tree.root())
+    }
+
     pub(crate) fn issuance_key(&self) -> IssueAuthKey<ZSASchnorr> {
         IssueAuthKey::from_zip32_seed(self.seed.as_slice(), constants::regtest::COIN_TYPE, 0)
             .unwrap()
@@ -318,6 +727,7 @@ impl Wallet {
         self.balance(conn, address, AssetBase::zatoshi())
     }
 
+    /// Balance of `asset` held by notes sent to exactly `address`.
     pub fn balance(&self, conn: &mut SqliteConnection, address: Address, asset: AssetBase) -> u64 {
         notes_db::find_non_spent_notes(conn, address, asset)
             .iter()
@@ -325,6 +735,71 @@ impl Wallet {
             .sum::<i64>() as u64
     }
 
+    /// Balance of `asset` held by `account`, summed over every address of the
+    /// account: both ZIP-32 scopes and all diversifier indices.
+    pub fn account_balance(
+        &self,
+        conn: &mut SqliteConnection,
+        account: usize,
+        asset: AssetBase,
+    ) -> u64 {
+        let fvk = FullViewingKey::from(&self.account_spending_key(account));
+        notes_db::find_non_spent_notes_for_asset(conn, asset)
+            .iter()
+            .filter(|n| fvk.scope_for_address(&note_recipient(n)).is_some())
+            .map(|n| n.amount as u64)
+            .sum()
+    }
+
+    /// Balance of `asset` that `account` can spend right away: like
+    /// [`Wallet::account_balance`], without the notes locked by pending
+    /// transactions.
+    pub fn spendable_balance(
+        &self,
+        conn: &mut SqliteConnection,
+        account: usize,
+        asset: AssetBase,
+    ) -> u64 {
+        let fvk = FullViewingKey::from(&self.account_spending_key(account));
+        notes_db::find_spendable_notes_for_asset(conn, asset)
+            .iter()
+            .filter(|n| fvk.scope_for_address(&note_recipient(n)).is_some())
+            .map(|n| n.amount as u64)
+            .sum()
+    }
+
+    /// The unspent transparent balance of `account` in zatoshis, including
+    /// immature coinbase outputs.
+    pub fn transparent_balance(&self, conn: &mut SqliteConnection, account: usize) -> u64 {
+        transparent::balance(conn, account)
+    }
+
+    /// The unspent Sapling balance of `account` in zatoshis.
+    pub fn sapling_balance(&self, conn: &mut SqliteConnection, account: usize) -> u64 {
+        sapling::balance(conn, account)
+    }
+
+    /// Per-asset balances of `account`, summed over every address of the account.
+    pub fn account_balances(&self, conn: &mut SqliteConnection, account: usize) -> AssetBalances {
+        let fvk = FullViewingKey::from(&self.account_spending_key(account));
+        let mut balances = AssetBalances::default();
+        for n in notes_db::find_all_non_spent_notes(conn) {
+            if fvk.scope_for_address(&note_recipient(&n)).is_some() {
+                balances.credit(n.asset.as_slice().try_into().unwrap(), n.amount as u64);
+            }
+        }
+        balances
+    }
+
+    /// Wallet-wide per-asset balances across all accounts.
+    pub fn summary(&self, conn: &mut SqliteConnection) -> AssetBalances {
+        let mut balances = AssetBalances::default();
+        for n in notes_db::find_all_non_spent_notes(conn) {
+            balances.credit(n.asset.as_slice().try_into().unwrap(), n.amount as u64);
+        }
+        balances
+    }
+
     /// Atomic per-block sync step.
     ///
     /// Wraps `block_data` insert + per-tx note inserts + tree-state save in a
@@ -338,58 +813,192 @@ impl Wallet {
         block_hash: BlockHash,
         transactions: Vec<Transaction>,
     ) -> Result<(), SyncError> {
+        let block = self
+            .scan_keys()
+            .scan_block(block_height, block_hash, transactions);
+        self.process_scanned_block(conn, block)
+    }
+
+    /// The keys sync trial-decrypts outputs with: the incoming viewing keys
+    /// of both scopes and the outgoing viewing keys of every account, and the
+    /// Sapling incoming viewing key of every account.
+    pub fn scan_keys(&self) -> ScanKeys {
+        ScanKeys {
+            ivks: self.key_store.viewing_keys.keys().cloned().collect(),
+            ovks: self
+                .key_store
+                .accounts
+                .iter()
+                .map(|(account, fvk)| {
+                    (
+                        *account,
+                        vec![fvk.to_ovk(Scope::External), fvk.to_ovk(Scope::Internal)],
+                    )
+                })
+                .collect(),
+            sapling_ivks: self
+                .sapling_accounts
+                .keys()
+                .map(|&account| {
+                    let ivk = self.sapling_ivk(account);
+                    (account, PreparedIncomingViewingKey::new(&ivk))
+                })
+                .collect(),
+        }
+    }
+
+    /// [`Wallet::process_block`] for a block whose outputs were already
+    /// trial-decrypted with [`Wallet::scan_keys`], e.g. by the sync pipeline.
+    ///
+    /// If the block comes with its header, the header is stored as well and
+    /// its previous block hash must match the stored block below it;
+    /// otherwise [`SyncError::Reorg`] is returned and nothing is applied.
+    pub fn process_scanned_block(
+        &mut self,
+        conn: &mut SqliteConnection,
+        block: ScannedBlock,
+    ) -> Result<(), SyncError> {
+        let ScannedBlock {
+            height: block_height,
+            hash: block_hash,
+            transactions,
+            header,
+            orchard_root,
+        } = block;
         let height_u32 = u32::from(block_height);
         let hash_hex = hex::encode(block_hash.0);
 
         let saved_tree = self.commitment_tree.clone();
+        let saved_sapling_tree = self.sapling_tree.clone();
+        let saved_history = self.history_tree.clone();
         let saved_height = self.last_block_height;
         let saved_hash = self.last_block_hash;
 
         let result: Result<(), SyncError> = conn.transaction(|c| {
-            block_data::insert(c, height_u32, hash_hex.clone());
-            for tx in &transactions {
-                if tx.version().has_orchard() || tx.version().has_orchard_zsa() {
-                    self.add_notes_from_tx(c, tx)?;
+            match &header {
+                Some(header) => {
+                    let parent = height_u32
+                        .checked_sub(1)
+                        .and_then(|h| block_data::get_hash(c, h));
+                    if parent.is_some_and(|p| p != block_data::display_hex(&header.prev_block.0)) {
+                        return Err(SyncError::Reorg { height: height_u32 });
+                    }
+                    block_data::insert_with_header(c, height_u32, hash_hex.clone(), header);
                 }
+                None => block_data::insert(c, height_u32, hash_hex.clone()),
+            }
+            self.add_sapling_notes_from_block(c, &transactions, height_u32)?;
+            for stx in &transactions {
+                let tx = &stx.tx;
+                let has_orchard = tx.version().has_orchard() || tx.version().has_orchard_zsa();
+                if let Some(bundle) = tx.transparent_bundle() {
+                    self.add_utxos_from_bundle(c, &tx.txid(), bundle, height_u32);
+                }
+                if has_orchard {
+                    self.add_notes_from_tx(c, stx, height_u32)?;
+                }
+                if has_orchard || tx.transparent_bundle().is_some() || tx.sapling_bundle().is_some()
+                {
+                    self.record_decrypted_transaction(
+                        c,
+                        tx,
+                        &stx.decrypted,
+                        &stx.sapling_decrypted,
+                        Some((height_u32, &hash_hex)),
+                    );
+                }
+            }
+            if let Some(node_root) = orchard_root {
+                self.verify_orchard_root(height_u32, node_root)?;
+            }
+            self.history_tree = header.as_ref().and_then(|header| {
+                HistoryTree::append_block(
+                    self.history_tree.take(),
+                    height_u32,
+                    header,
+                    self.sapling_tree.root().to_bytes(),
+                    self.commitment_tree.root(0).unwrap().to_bytes(),
+                    transactions.iter().map(|stx| &stx.tx),
+                )
+            });
+            history_tree::save(c, self.history_tree.as_ref())?;
+            let unlocked = notes_db::unlock_expired_notes(c, height_u32)
+                + transparent::unlock_expired(c, height_u32)
+                + sapling::unlock_expired(c, height_u32);
+            if unlocked > 0 {
+                info!(
+                    "Released {} notes and transparent outputs locked by expired transactions",
+                    unlocked
+                );
             }
             self.last_block_height = Some(block_height);
             self.last_block_hash = Some(block_hash);
             tree_state::save_tree_state(c, &self.commitment_tree, height_u32, &hash_hex)?;
+            sapling::save_tree(c, &self.sapling_tree)?;
             Ok(())
         });
 
         if result.is_err() {
             self.commitment_tree = saved_tree;
+            self.sapling_tree = saved_sapling_tree;
+            self.history_tree = saved_history;
             self.last_block_height = saved_height;
             self.last_block_hash = saved_hash;
         }
         result
     }
 
+    /// Compare the root of the local commitment tree with `node_root`, the
+    /// node's Orchard root after the block at `height`.
+    fn verify_orchard_root(
+        &self,
+        height: u32,
+        node_root: MerkleHashOrchard,
+    ) -> Result<(), SyncError> {
+        let local_root = self.commitment_tree.root(0).unwrap();
+        if local_root == node_root {
+            Ok(())
+        } else {
+            Err(SyncError::RootMismatch {
+                height,
+                local: hex::encode(local_root.to_bytes()),
+                node: hex::encode(node_root.to_bytes()),
+            })
+        }
+    }
+
     fn add_notes_from_tx(
         &mut self,
         conn: &mut SqliteConnection,
-        tx: &Transaction,
+        stx: &ScannedTransaction,
+        height: u32,
     ) -> Result<(), BundleError> {
+        let tx = &stx.tx;
         let mut issued_notes_offset = 0;
 
         if let Some(orchard_bundle) = tx.orchard_bundle() {
+            self.add_notes_from_decrypted_outputs(conn, &tx.txid(), &stx.decrypted, height);
+            self.add_sent_notes(conn, &tx.txid(), stx, height);
             match orchard_bundle {
                 OrchardBundle::OrchardVanilla(b) => {
                     issued_notes_offset = b.actions().len();
-                    self.add_notes_from_orchard_bundle(conn, &tx.txid(), b);
-                    self.mark_potential_spends(conn, &tx.txid(), b);
+                    self.mark_potential_spends(conn, &tx.txid(), b, height);
                 }
                 OrchardBundle::OrchardZSA(b) => {
                     issued_notes_offset = b.actions().len();
-                    self.add_notes_from_orchard_bundle(conn, &tx.txid(), b);
-                    self.mark_potential_spends(conn, &tx.txid(), b);
+                    self.mark_potential_spends(conn, &tx.txid(), b, height);
                 }
             }
         };
 
         if let Some(issue_bundle) = tx.issue_bundle() {
-            self.add_notes_from_issue_bundle(conn, &tx.txid(), issue_bundle, issued_notes_offset);
+            self.add_notes_from_issue_bundle(
+                conn,
+                &tx.txid(),
+                issue_bundle,
+                issued_notes_offset,
+                height,
+            );
         };
 
         self.add_note_commitments(conn, &tx.txid(), tx.orchard_bundle(), tx.issue_bundle())
@@ -398,42 +1007,359 @@ impl Wallet {
         Ok(())
     }
 
-    fn add_notes_from_orchard_bundle<O: OrchardPrimitives>(
+    /// Record `tx` in the transaction history with its net value change per
+    /// account and asset. `mined` is the height and block hash the transaction
+    /// was mined in, `None` when it was just built by this wallet. Mined
+    /// transactions are only recorded if they touch a wallet account or were
+    /// recorded when built.
+    pub(crate) fn record_transaction(
+        &self,
+        conn: &mut SqliteConnection,
+        tx: &Transaction,
+        mined: Option<(u32, &str)>,
+    ) {
+        let keys = self.scan_keys();
+        let (decrypted, _) = keys.decrypt(tx);
+        let sapling_decrypted = keys.decrypt_sapling(tx);
+        self.record_decrypted_transaction(conn, tx, &decrypted, &sapling_decrypted, mined);
+    }
+
+    fn record_decrypted_transaction(
+        &self,
+        conn: &mut SqliteConnection,
+        tx: &Transaction,
+        decrypted: &[DecryptedOutput],
+        sapling_decrypted: &[SaplingDecryptedOutput],
+        mined: Option<(u32, &str)>,
+    ) {
+        let txid = tx.txid();
+        let mut changes: BTreeMap<(usize, [u8; 32]), i64> = BTreeMap::new();
+        let mut memo_refs = Vec::new();
+        let mut burns = Vec::new();
+
+        if let Some(bundle) = tx.transparent_bundle() {
+            self.transparent_values(conn, bundle, &mut changes);
+        }
+        if let Some(bundle) = tx.sapling_bundle() {
+            self.sapling_values(conn, bundle, sapling_decrypted, &mut changes);
+        }
+        match tx.orchard_bundle() {
+            Some(OrchardBundle::OrchardVanilla(b)) => {
+                self.orchard_spent_values(conn, b, &mut changes);
+            }
+            Some(OrchardBundle::OrchardZSA(b)) => {
+                self.orchard_spent_values(conn, b, &mut changes);
+                burns = b
+                    .burn()
+                    .iter()
+                    .map(|(asset, value)| {
+                        json!({
+                            "asset": hex::encode(asset.to_bytes()),
+                            "amount": value.inner(),
+                        })
+                    })
+                    .collect();
+            }
+            None => {}
+        }
+        for (action_idx, _ivk, note, _recipient, memo) in decrypted {
+            if let Some(account) = self.account_for_address(&note.recipient()) {
+                *changes
+                    .entry((account, note.asset().to_bytes()))
+                    .or_default() += note.value().inner() as i64;
+            }
+            if !history::is_empty_memo(memo) {
+                memo_refs.push(*action_idx);
+            }
+        }
+
+        let mut issuance = Vec::new();
+        if let Some(bundle) = tx.issue_bundle() {
+            for action in bundle.actions() {
+                let notes: Vec<_> = action
+                    .notes()
+                    .iter()
+                    .map(|note| {
+                        let account = self.account_for_address(&note.recipient());
+                        if let Some(account) = account {
+                            *changes
+                                .entry((account, note.asset().to_bytes()))
+                                .or_default() += note.value().inner() as i64;
+                        }
+                        json!({
+                            "asset": hex::encode(note.asset().to_bytes()),
+                            "amount": note.value().inner(),
+                            "account": account,
+                        })
+                    })
+                    .collect();
+                issuance.push(json!({
+                    "finalize": action.is_finalized(),
+                    "notes": notes,
+                }));
+            }
+        }
+
+        if mined.is_some() && changes.is_empty() && !history::contains(conn, txid.as_ref()) {
+            return;
+        }
+
+        let record = TxRecord {
+            txid: txid.as_ref().to_vec(),
+            mined_height: mined.map(|(height, _)| height as i32),
+            block_hash: mined.map(|(_, hash)| hash.to_string()),
+            expiry_height: u32::from(tx.expiry_height()) as i32,
+            fee: tx_fee(tx),
+            memo_refs: serde_json::to_string(&memo_refs).unwrap(),
+            issuance: serde_json::to_string(&issuance).unwrap(),
+            burns: serde_json::to_string(&burns).unwrap(),
+        };
+        let values: Vec<TxValue> = changes
+            .into_iter()
+            .map(|((account, asset), value_change)| TxValue {
+                txid: txid.as_ref().to_vec(),
+                account: account as i32,
+                asset: asset.to_vec(),
+                value_change,
+            })
+            .collect();
+        history::record(conn, &record, &values);
+    }
+
+    /// Accumulate the value spent from wallet accounts by `bundle`.
+    fn orchard_spent_values<O: OrchardPrimitives>(
+        &self,
+        conn: &mut SqliteConnection,
+        bundle: &Bundle<Authorized, ZatBalance, O>,
+        changes: &mut BTreeMap<(usize, [u8; 32]), i64>,
+    ) {
+        for action in bundle.actions() {
+            if let Some(note) = notes_db::find_by_nullifier(conn, action.nullifier()) {
+                if let Some(account) = self.account_for_address(&note_recipient(&note)) {
+                    let asset: [u8; 32] = note.asset.as_slice().try_into().unwrap();
+                    *changes.entry((account, asset)).or_default() -= note.amount;
+                }
+            }
+        }
+    }
+
+    /// Accumulate the value `bundle` spends from and pays to the transparent
+    /// addresses of wallet accounts.
+    fn transparent_values(
+        &self,
+        conn: &mut SqliteConnection,
+        bundle: &TransparentBundle<TransparentAuthorized>,
+        changes: &mut BTreeMap<(usize, [u8; 32]), i64>,
+    ) {
+        let zec = AssetBase::zatoshi().to_bytes();
+        if !bundle.is_coinbase() {
+            for input in &bundle.vin {
+                if let Some(utxo) = transparent::find(conn, input.prevout()) {
+                    if let Owner::Account(account) = utxo.owner() {
+                        *changes.entry((account, zec)).or_default() -= utxo.amount;
+                    }
+                }
+            }
+        }
+        for output in &bundle.vout {
+            let account = output
+                .recipient_address()
+                .and_then(|address| self.account_for_transparent_address(&address));
+            if let Some(account) = account {
+                *changes.entry((account, zec)).or_default() += u64::from(output.value()) as i64;
+            }
+        }
+    }
+
+    /// Accumulate the value `bundle` spends from and pays to the Sapling
+    /// addresses of wallet accounts.
+    fn sapling_values(
+        &self,
+        conn: &mut SqliteConnection,
+        bundle: &SaplingBundle<sapling_crypto::bundle::Authorized, ZatBalance>,
+        decrypted: &[SaplingDecryptedOutput],
+        changes: &mut BTreeMap<(usize, [u8; 32]), i64>,
+    ) {
+        let zec = AssetBase::zatoshi().to_bytes();
+        for spend in bundle.shielded_spends() {
+            if let Some(note) = sapling::find_by_nullifier(conn, &spend.nullifier().0) {
+                *changes.entry((note.account as usize, zec)).or_default() -= note.amount;
+            }
+        }
+        for (_, account, note, _) in decrypted {
+            *changes.entry((*account, zec)).or_default() += note.value().inner() as i64;
+        }
+    }
+
+    /// Append the Sapling note commitments of `transactions` to the Sapling
+    /// tree and the witnesses of the unspent notes, store the notes decrypted
+    /// during the scan and mark the notes the transactions spend.
+    fn add_sapling_notes_from_block(
         &mut self,
         conn: &mut SqliteConnection,
+        transactions: &[ScannedTransaction],
+        height: u32,
+    ) -> Result<(), SyncError> {
+        if transactions
+            .iter()
+            .all(|stx| stx.tx.sapling_bundle().is_none())
+        {
+            return Ok(());
+        }
+        let mut tracked = sapling::unspent(conn)
+            .into_iter()
+            .map(|note| note.witness().map(|witness| (note, witness)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let full_tree = || "Sapling note commitment tree is full".to_string();
+
+        for stx in transactions {
+            let Some(bundle) = stx.tx.sapling_bundle() else {
+                continue;
+            };
+            let txid = stx.tx.txid();
+            for spend in bundle.shielded_spends() {
+                if sapling::mark_spent(conn, &spend.nullifier().0, txid.as_ref(), height) {
+                    info!("Sapling note spent");
+                }
+            }
+            for (index, output) in bundle.shielded_outputs().iter().enumerate() {
+                let node = SaplingNode::from_cmu(output.cmu());
+                self.sapling_tree.append(node).map_err(|_| full_tree())?;
+                for (_, witness) in &mut tracked {
+                    witness.append(node).map_err(|_| full_tree())?;
+                }
+                let Some((_, account, note, memo)) =
+                    stx.sapling_decrypted.iter().find(|(i, ..)| *i == index)
+                else {
+                    continue;
+                };
+                let witness = IncrementalWitness::from_tree(self.sapling_tree.clone())
+                    .ok_or_else(full_tree)?;
+                let nk = self.sapling_accounts[account].to_nk(zip32::Scope::External);
+                let nf = note.nf(&nk, u64::from(witness.witnessed_position()));
+                let data = SaplingNoteData::new(
+                    *txid.as_ref(),
+                    index,
+                    *account,
+                    note,
+                    nf.0,
+                    *memo,
+                    &witness,
+                    height,
+                );
+                match data {
+                    Some(data) => {
+                        info!("Store Sapling note {}:{}", txid, index);
+                        sapling::insert(conn, &data);
+                        tracked.push((data, witness));
+                    }
+                    None => info!("Skipping pre-ZIP-212 Sapling note {}:{}", txid, index),
+                }
+            }
+        }
+        for (note, witness) in &tracked {
+            sapling::update_witness(conn, note, witness);
+        }
+        Ok(())
+    }
+
+    /// Store the outputs of `bundle` paying wallet accounts as UTXOs and mark
+    /// the wallet UTXOs it spends.
+    fn add_utxos_from_bundle(
+        &self,
+        conn: &mut SqliteConnection,
         txid: &TxId,
-        bundle: &Bundle<Authorized, ZatBalance, O>,
+        bundle: &TransparentBundle<TransparentAuthorized>,
+        height: u32,
     ) {
-        let keys = self
-            .key_store
-            .viewing_keys
-            .keys()
-            .cloned()
-            .collect::<Vec<_>>();
+        if !bundle.is_coinbase() {
+            for input in &bundle.vin {
+                if transparent::mark_spent(conn, input.prevout(), txid.as_ref(), height) {
+                    info!("Transparent output {:?} spent", input.prevout());
+                }
+            }
+        }
+        for (index, output) in bundle.vout.iter().enumerate() {
+            let owner = output
+                .recipient_address()
+                .and_then(|address| self.transparent_owner(&address));
+            if let Some(owner) = owner {
+                info!("Store transparent output {}:{}", txid, index);
+                let utxo = Utxo::new(
+                    *txid.as_ref(),
+                    index as u32,
+                    owner,
+                    u64::from(output.value()),
+                    bundle.is_coinbase(),
+                    height,
+                );
+                transparent::insert(conn, &utxo);
+            }
+        }
+    }
 
-        for (action_idx, ivk, note, _recipient, memo) in bundle.decrypt_outputs_with_keys(&keys) {
+    fn add_notes_from_decrypted_outputs(
+        &mut self,
+        conn: &mut SqliteConnection,
+        txid: &TxId,
+        decrypted: &[DecryptedOutput],
+        height: u32,
+    ) {
+        for (action_idx, ivk, note, _recipient, memo) in decrypted {
             info!("Store note");
-            self.store_note(conn, txid, action_idx, ivk.clone(), note, memo)
+            self.store_note(conn, txid, *action_idx, ivk.clone(), *note, *memo, height)
                 .unwrap();
         }
     }
 
+    /// Store the outputs of `stx` recovered with the outgoing viewing key of a
+    /// wallet account, so payments made by the wallet remain visible after
+    /// restoring it from seed.
+    fn add_sent_notes(
+        &self,
+        conn: &mut SqliteConnection,
+        txid: &TxId,
+        stx: &ScannedTransaction,
+        height: u32,
+    ) {
+        for output in &stx.recovered {
+            info!("Recovered outgoing note of account {}", output.account);
+            let sent_note = SentNoteData {
+                id: 0,
+                tx_id: txid.as_ref().to_vec(),
+                action_index: output.action_index as i32,
+                sender_account: output.account as i32,
+                recipient_address: output.recipient.to_raw_address_bytes().to_vec(),
+                recipient_account: self
+                    .account_for_address(&output.recipient)
+                    .map(|a| a as i32),
+                amount: output.note.value().inner() as i64,
+                asset: output.note.asset().to_bytes().to_vec(),
+                memo: output.memo.to_vec(),
+                mined_height: height as i32,
+            };
+            notes_db::insert_sent_note(conn, sent_note);
+        }
+    }
+
     fn add_notes_from_issue_bundle(
         &mut self,
         conn: &mut SqliteConnection,
         txid: &TxId,
         bundle: &IssueBundle<Signed>,
         note_index_offset: usize,
+        height: u32,
     ) {
         for (note_index, note) in bundle.actions().iter().flat_map(|a| a.notes()).enumerate() {
             if let Some(ivk) = self.key_store.ivk_for_address(&note.recipient()) {
                 let note_index = note_index + note_index_offset;
-                self.store_note(conn, txid, note_index, ivk.clone(), *note, [0; 512])
+                self.store_note(conn, txid, note_index, ivk.clone(), *note, [0; 512], height)
                     .unwrap();
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn store_note(
         &mut self,
         conn: &mut SqliteConnection,
@@ -442,6 +1368,7 @@ impl Wallet {
         ivk: IncomingViewingKey,
         note: Note,
         memo_bytes: [u8; 512],
+        mined_height: u32,
     ) -> Result<(), BundleError> {
         if let Some(fvk) = self.key_store.viewing_keys.get(&ivk) {
             info!("Adding decrypted note to the user");
@@ -464,6 +1391,10 @@ impl Wallet {
                 recipient_address: recipient.to_raw_address_bytes().to_vec(),
                 spend_tx_id: None,
                 spend_action_index: -1,
+                mined_height: Some(mined_height as i32),
+                spend_height: None,
+                locked_by_tx_id: None,
+                lock_expiry_height: None,
             };
             notes_db::insert_note(conn, note_data);
 
@@ -480,11 +1411,18 @@ impl Wallet {
         conn: &mut SqliteConnection,
         txid: &TxId,
         orchard_bundle: &Bundle<Authorized, ZatBalance, O>,
+        height: u32,
     ) {
         for (action_index, action) in orchard_bundle.actions().iter().enumerate() {
             if let Some(note) = notes_db::find_by_nullifier(conn, action.nullifier()) {
                 info!("Adding spend of nullifier {:?}", action.nullifier());
-                notes_db::mark_as_potentially_spent(conn, note.id, txid, action_index as i32);
+                notes_db::mark_as_potentially_spent(
+                    conn,
+                    note.id,
+                    txid,
+                    action_index as i32,
+                    height,
+                );
             }
         }
     }
@@ -552,3 +1490,67 @@ impl Wallet {
         Ok(())
     }
 }
+
+fn note_recipient(note_data: &NoteData) -> Address {
+    Address::from_raw_address_bytes(note_data.recipient_address.as_slice().try_into().unwrap())
+        .unwrap()
+}
+
+/// The fee paid by `tx`, if it can be derived from the transaction alone:
+/// transactions with transparent inputs would need the spent outputs.
+fn tx_fee(tx: &Transaction) -> Option<i64> {
+    if tx.transparent_bundle().is_some_and(|b| !b.vin.is_empty()) {
+        return None;
+    }
+    Some(fee_with_transparent_inputs(tx, 0))
+}
+
+/// The fee paid by `tx` given the total value of its transparent inputs. For
+/// a coinbase transaction this is the negated value of its outputs.
+pub(crate) fn fee_with_transparent_inputs(tx: &Transaction, transparent_in: i64) -> i64 {
+    let transparent_out: i64 = tx
+        .transparent_bundle()
+        .map(|b| b.vout.iter().map(|o| u64::from(o.value()) as i64).sum())
+        .unwrap_or(0);
+    let orchard = match tx.orchard_bundle() {
+        Some(OrchardBundle::OrchardVanilla(b)) => i64::from(*b.value_balance()),
+        Some(OrchardBundle::OrchardZSA(b)) => i64::from(*b.value_balance()),
+        None => 0,
+    };
+    let sapling = tx
+        .sapling_bundle()
+        .map(|b| i64::from(*b.value_balance()))
+        .unwrap_or(0);
+    transparent_in + orchard + sapling - transparent_out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::db;
+    use tempfile::NamedTempFile;
+
+    const SEED_PHRASE: &str = "fabric dilemma shift time border road fork license among \
+                               uniform early laundry caution deer stamp";
+
+    #[test]
+    fn test_load_tree_state_without_sapling_tree() {
+        let db_file = NamedTempFile::new().unwrap();
+        let mut conn = db::establish_connection(&db_file.path().to_string_lossy());
+
+        // The state of a wallet synced before the Sapling tree was stored.
+        let mut tree: BridgeTree<MerkleHashOrchard, u32, NOTE_COMMITMENT_TREE_DEPTH> =
+            BridgeTree::new(MAX_CHECKPOINTS);
+        tree.append(MerkleHashOrchard::from_bytes(&[1; 32]).unwrap());
+        tree.mark();
+        tree.checkpoint(7);
+        tree_state::save_tree_state(&mut conn, &tree, 7, &hex::encode([2u8; 32])).unwrap();
+        assert!(sapling::load_tree(&mut conn).unwrap().is_none());
+
+        let wallet = Wallet::new(&mut conn, SEED_PHRASE);
+        assert_eq!(wallet.commitment_tree, BridgeTree::new(MAX_CHECKPOINTS));
+        assert_eq!(wallet.last_block_height, None);
+        assert_eq!(wallet.last_block_hash, None);
+        assert!(tree_state::load_tree_state(&mut conn).unwrap().is_none());
+    }
+}
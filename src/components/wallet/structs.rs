@@ -1,5 +1,7 @@
+use orchard::note::AssetBase;
 use orchard::Address;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
 /// Internal newtype wrapper that allows us to use addresses as
 /// BTreeMap keys.
@@ -34,3 +36,29 @@ impl Ord for OrderedAddress {
             .cmp(&other.to_raw_address_bytes())
     }
 }
+
+/// Unspent note totals per asset, keyed by the asset's canonical encoding so
+/// iteration order is stable across runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AssetBalances(BTreeMap<[u8; 32], u64>);
+
+impl AssetBalances {
+    pub(crate) fn credit(&mut self, asset: [u8; 32], amount: u64) {
+        *self.0.entry(asset).or_insert(0) += amount;
+    }
+
+    /// Total held of `asset`, or 0 if the wallet holds none.
+    pub fn get(&self, asset: AssetBase) -> u64 {
+        self.0.get(&asset.to_bytes()).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (AssetBase, u64)> + '_ {
+        self.0
+            .iter()
+            .map(|(asset, amount)| (AssetBase::from_bytes(asset).unwrap(), *amount))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
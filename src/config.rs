@@ -4,6 +4,8 @@
 //! application's configuration file and/or command-line options
 //! for specifying it.
 
+use crate::components::block_data::RetentionPolicy;
+use crate::components::equihash;
 use serde::{Deserialize, Serialize};
 use std::env;
 
@@ -37,6 +39,95 @@ pub struct WalletSection {
     pub seed_phrase: String,
     /// Miner seed phrase as defined in BIP-39
     pub miner_seed_phrase: String,
+    /// Height of the first block that can contain wallet transactions. When
+    /// set, a cold sync seeds the commitment tree from the node's tree state
+    /// just below it instead of scanning from the activation height.
+    pub birthday_height: Option<u32>,
+    /// Compare the wallet's Orchard commitment tree root with the node's
+    /// (`z_gettreestate`) after every synced block and stop at the first
+    /// height where they differ.
+    pub verify_orchard_root: bool,
+    /// Number of most recent block hashes to keep in the local db after each
+    /// sync. `None` keeps every synced block.
+    pub retain_blocks: Option<u32>,
+    /// When pruning, also keep the hashes of older blocks at heights that are
+    /// a multiple of this (0 keeps none of them).
+    pub retain_checkpoint_interval: u32,
+    /// Coinbase of the blocks the tool mines: the node's own, or one built
+    /// locally paying the miner key or an Orchard address of the wallet.
+    pub coinbase: CoinbaseMode,
+    /// Wallet account whose external Orchard address receives the coinbase
+    /// in `orchard` mode.
+    pub coinbase_account: u32,
+    /// Chain history root mined blocks commit to: the node's, the one of the
+    /// wallet's local history tree, or the node's after checking it against
+    /// the local one.
+    pub chain_history_root: HistoryRootMode,
+    /// Proof of work of mined blocks: none (a dummy solution, for nodes that
+    /// do not check it), or an Equihash solution meeting the target.
+    pub proof_of_work: ProofOfWork,
+}
+
+/// Where the coinbase of mined blocks comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoinbaseMode {
+    /// Use the coinbase of the node's block template.
+    #[default]
+    Node,
+    /// Build the coinbase, paying the transparent address of the miner key.
+    Transparent,
+    /// Build the coinbase, paying an Orchard address of the wallet.
+    Orchard,
+}
+
+/// Where the chain history root of mined blocks comes from. The local history
+/// tree is only used when it covers every block below the mined one; the
+/// node's root is used otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryRootMode {
+    /// Use `chainhistoryroot` from the node's block template.
+    #[default]
+    Node,
+    /// Use the root of the local history tree.
+    Local,
+    /// Use the node's root, failing if it differs from the local one.
+    Verify,
+}
+
+/// How mined blocks are given a proof of work.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofOfWork {
+    /// A dummy nonce and an all-zero solution.
+    #[default]
+    None,
+    /// Equihash (48, 5), as in zcashd's regtest.
+    Regtest,
+    /// Equihash (200, 9), as on mainnet and testnet.
+    Standard,
+}
+
+impl ProofOfWork {
+    /// The Equihash parameters to solve, if any.
+    pub fn equihash_params(self) -> Option<equihash::Params> {
+        match self {
+            ProofOfWork::None => None,
+            ProofOfWork::Regtest => Some(equihash::Params::REGTEST),
+            ProofOfWork::Standard => Some(equihash::Params::STANDARD),
+        }
+    }
+}
+
+impl WalletSection {
+    /// The block data retention policy applied after sync, if any.
+    pub fn block_data_retention(&self) -> Option<RetentionPolicy> {
+        self.retain_blocks.map(|keep_last| RetentionPolicy {
+            keep_last,
+            checkpoint_interval: self.retain_checkpoint_interval,
+        })
+    }
 }
 
 impl Default for WalletSection {
@@ -44,6 +135,14 @@ impl Default for WalletSection {
         Self {
             seed_phrase: "fabric dilemma shift time border road fork license among uniform early laundry caution deer stamp".to_string(), // tmLTZegcJN5zaufWQBARHkvqC62mTumm3jR
             miner_seed_phrase: "fabric dilemma shift time border road fork license among uniform early laundry caution deer stamp".to_string(), // tmLTZegcJN5zaufWQBARHkvqC62mTumm3jR
+            birthday_height: None,
+            verify_orchard_root: true,
+            retain_blocks: None,
+            retain_checkpoint_interval: 1000,
+            coinbase: CoinbaseMode::Node,
+            coinbase_account: 0,
+            chain_history_root: HistoryRootMode::Node,
+            proof_of_work: ProofOfWork::None,
         }
     }
 }
@@ -15,14 +15,15 @@ pub mod components;
 pub mod config;
 pub mod error;
 mod model;
+pub mod output;
 pub mod prelude;
+pub mod report;
 mod schema;
 
-/// Print an informational message to stdout for human-readable commands.
-/// Suppressed entirely for machine-readable subcommands (e.g. `get-block-data`)
-/// that require clean, noise-free stdout.
+/// Print an informational message to stdout in text output mode.
+/// Suppressed entirely in JSON mode, where stdout only holds results.
 pub fn print_info(msg: &str) {
-    if !std::env::args().any(|a| a == "get-block-data") {
+    if !output::is_json() {
         println!("{}", msg);
     }
 }
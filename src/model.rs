@@ -1,6 +1,6 @@
-use zcash_primitives::block::BlockHash;
+use zcash_primitives::block::{BlockHash, BlockHeader};
 use zcash_protocol::consensus::BlockHeight;
-use zcash_primitives::transaction::TxId;
+use zcash_primitives::transaction::{Transaction, TxId};
 
 #[derive(Debug, Clone)]
 pub struct Block {
@@ -10,3 +10,27 @@ pub struct Block {
     pub tx_ids: Vec<TxId>,
     pub previous_block_hash: BlockHash,
 }
+
+/// A block with its header and fully parsed transactions.
+#[derive(Debug)]
+pub struct FullBlock {
+    /// Block hash, in the same byte order as [`Block::hash`].
+    pub hash: BlockHash,
+    pub height: BlockHeight,
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+}
+
+/// The note commitment tree state of the node after a given block.
+#[derive(Debug, Clone)]
+pub struct TreeState {
+    /// Block hash, in the same byte order as [`Block::hash`].
+    pub hash: BlockHash,
+    pub height: BlockHeight,
+    /// The Orchard note commitment tree in the legacy `CommitmentTree`
+    /// encoding, `None` if no Orchard commitment exists yet.
+    pub orchard_tree: Option<Vec<u8>>,
+    /// The Sapling note commitment tree in the same encoding, `None` if no
+    /// Sapling commitment exists yet.
+    pub sapling_tree: Option<Vec<u8>>,
+}
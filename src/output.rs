@@ -0,0 +1,69 @@
+//! Machine-readable output.
+//!
+//! With `--output json` every command reports its results as JSON lines on
+//! stdout: one object per event, with the event kind under `"event"`, e.g.
+//!
+//! ```text
+//! {"event":"block","height":12,"txids":["..."]}
+//! {"event":"check","name":"balances","passed":true,"expected":[5,3],"actual":[5,3]}
+//! {"event":"result","status":"passed"}
+//! ```
+//!
+//! Log lines and informational messages are not printed in that mode, so
+//! stdout only holds events. In text mode [`emit`] prints nothing and results
+//! are only logged.
+
+use std::sync::OnceLock;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// How results are reported.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Human-readable logs.
+    #[default]
+    Text,
+    /// JSON lines on stdout.
+    Json,
+}
+
+static MODE: OnceLock<OutputMode> = OnceLock::new();
+
+/// Set the output mode of the process. Only the first call has an effect.
+pub fn set_mode(mode: OutputMode) {
+    let _ = MODE.set(mode);
+}
+
+/// The output mode of the process, [`OutputMode::Text`] until set.
+pub fn mode() -> OutputMode {
+    MODE.get().copied().unwrap_or_default()
+}
+
+pub fn is_json() -> bool {
+    mode() == OutputMode::Json
+}
+
+/// Print the event `kind` with the fields of `data`, which must serialize to
+/// a JSON object, in JSON mode.
+pub fn emit<T: Serialize>(kind: &str, data: &T) {
+    if !is_json() {
+        return;
+    }
+    let mut event = Map::new();
+    event.insert("event".to_string(), Value::from(kind));
+    match serde_json::to_value(data).unwrap() {
+        Value::Object(fields) => event.extend(fields),
+        other => panic!("{kind} event data is not an object: {other}"),
+    }
+    println!("{}", Value::Object(event));
+}
+
+/// The message of a panic payload, as passed to `panic!`.
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
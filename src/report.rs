@@ -0,0 +1,336 @@
+//! Scenario reports.
+//!
+//! With `--report-dir` the steps of a command are recorded as it runs: every
+//! transaction built, block mined, sync and checked expectation, with its
+//! duration and what it produced. When the command ends the report is written
+//! to the directory as `<command>.json` and as JUnit XML in `<command>.xml`,
+//! one test case per step, also when the command fails.
+//!
+//! Recording is a no-op unless a report was started, so the steps can be
+//! recorded unconditionally where they happen.
+
+use std::fmt::{Debug, Write as _};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::output;
+
+/// What a step did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    Build,
+    Mine,
+    Sync,
+    Check,
+}
+
+/// Outcome of a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Passed,
+    Failed,
+    /// A block the node did not accept. Not a failure by itself, as some
+    /// scenarios expect it; an unexpected rejection fails the command.
+    Rejected,
+}
+
+/// One recorded step.
+#[derive(Clone, Debug, Serialize)]
+pub struct Step {
+    pub kind: StepKind,
+    pub name: String,
+    pub status: StepStatus,
+    pub duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub txids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Step {
+    pub fn new(kind: StepKind, name: impl Into<String>, duration: Duration) -> Self {
+        Step {
+            kind,
+            name: name.into(),
+            status: StepStatus::Passed,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            height: None,
+            txids: vec![],
+            expected: None,
+            actual: None,
+            error: None,
+        }
+    }
+}
+
+/// The report of one command.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub name: String,
+    pub status: StepStatus,
+    pub duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+struct Recording {
+    name: String,
+    started: Instant,
+    steps: Vec<Step>,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+/// Start recording the steps of the command `name`.
+pub fn start(name: &str) {
+    *RECORDING.lock().unwrap() = Some(Recording {
+        name: name.to_string(),
+        started: Instant::now(),
+        steps: vec![],
+    });
+}
+
+/// Record `step` if a report was started.
+pub fn record(step: Step) {
+    if let Some(recording) = RECORDING.lock().unwrap().as_mut() {
+        recording.steps.push(step);
+    }
+}
+
+/// Stop recording. `error` is the panic message of a failed command.
+/// Returns `None` if no report was started.
+pub fn finish(error: Option<String>) -> Option<Report> {
+    let recording = RECORDING.lock().unwrap().take()?;
+    let failed = error.is_some()
+        || recording
+            .steps
+            .iter()
+            .any(|step| step.status == StepStatus::Failed);
+    Some(Report {
+        name: recording.name,
+        status: if failed {
+            StepStatus::Failed
+        } else {
+            StepStatus::Passed
+        },
+        duration_ms: recording.started.elapsed().as_secs_f64() * 1000.0,
+        error,
+        steps: recording.steps,
+    })
+}
+
+/// Compare `actual` with `expected`, computed since `started`: report the
+/// outcome as a `check` event and step and return whether they are equal.
+/// Callers still assert on the result.
+pub fn check<T: Serialize + PartialEq + Debug>(
+    name: &str,
+    started: Instant,
+    expected: &T,
+    actual: &T,
+) -> bool {
+    let passed = expected == actual;
+    let expected = serde_json::to_value(expected).unwrap();
+    let actual = serde_json::to_value(actual).unwrap();
+    output::emit(
+        "check",
+        &json!({
+            "name": name,
+            "passed": passed,
+            "expected": expected,
+            "actual": actual,
+        }),
+    );
+    let mut step = Step::new(StepKind::Check, name, started.elapsed());
+    if !passed {
+        step.status = StepStatus::Failed;
+        step.error = Some(format!("expected {expected}, got {actual}"));
+    }
+    step.expected = Some(expected);
+    step.actual = Some(actual);
+    record(step);
+    passed
+}
+
+impl Report {
+    /// Write the report to `dir` as `<name>.json` and `<name>.xml`.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(
+            dir.join(format!("{}.json", self.name)),
+            serde_json::to_string_pretty(self).unwrap(),
+        )?;
+        fs::write(dir.join(format!("{}.xml", self.name)), self.to_junit())
+    }
+
+    /// The report as a JUnit XML test suite with one test case per step, and
+    /// a final `outcome` test case carrying the error of a failed command.
+    pub fn to_junit(&self) -> String {
+        let failures = self
+            .steps
+            .iter()
+            .filter(|step| step.status == StepStatus::Failed)
+            .count()
+            + usize::from(self.error.is_some());
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            xml,
+            "<testsuites><testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+            escape(&self.name),
+            self.steps.len() + 1,
+            failures,
+            self.duration_ms / 1000.0
+        )
+        .unwrap();
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let kind = serde_json::to_value(step.kind).unwrap();
+            let mut name = format!("{:03} {} {}", index + 1, kind.as_str().unwrap(), step.name);
+            if let Some(height) = step.height {
+                write!(name, " at height {height}").unwrap();
+            }
+            write!(
+                xml,
+                "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">",
+                escape(&self.name),
+                escape(&name),
+                step.duration_ms / 1000.0
+            )
+            .unwrap();
+            let error = step.error.as_deref().map(escape).unwrap_or_default();
+            let mut out = vec![];
+            match step.status {
+                StepStatus::Passed => {}
+                StepStatus::Failed => {
+                    write!(xml, "<failure message=\"{error}\">{error}</failure>").unwrap()
+                }
+                StepStatus::Rejected => out.push(format!("rejected: {error}")),
+            }
+            if !step.txids.is_empty() {
+                out.push(format!("txids: {}", step.txids.join(" ")));
+            }
+            if !out.is_empty() {
+                write!(xml, "<system-out>{}</system-out>", out.join("&#10;")).unwrap();
+            }
+            xml.push_str("</testcase>\n");
+        }
+
+        write!(
+            xml,
+            "  <testcase classname=\"{}\" name=\"outcome\" time=\"{:.3}\">",
+            escape(&self.name),
+            self.duration_ms / 1000.0
+        )
+        .unwrap();
+        if let Some(error) = &self.error {
+            let error = escape(error);
+            write!(xml, "<failure message=\"{error}\">{error}</failure>").unwrap();
+        }
+        xml.push_str("</testcase>\n</testsuite></testsuites>\n");
+        xml
+    }
+}
+
+/// Escape `text` for XML attribute values and character data.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(steps: Vec<Step>, error: Option<String>) -> Report {
+        Report {
+            name: "test-scenario".to_string(),
+            status: StepStatus::Passed,
+            duration_ms: 1500.0,
+            error,
+            steps,
+        }
+    }
+
+    #[test]
+    fn test_junit_has_a_test_case_per_step_and_the_outcome() {
+        let mut mine = Step::new(StepKind::Mine, "block", Duration::from_millis(250));
+        mine.height = Some(12);
+        mine.txids = vec!["aa".to_string(), "bb".to_string()];
+        let xml = report(vec![mine], None).to_junit();
+
+        assert!(xml.contains("tests=\"2\" failures=\"0\" time=\"1.500\""));
+        assert!(xml.contains(
+            "name=\"001 mine block at height 12\" time=\"0.250\"><system-out>txids: aa bb</system-out>"
+        ));
+        assert!(xml.contains("name=\"outcome\""));
+        assert!(!xml.contains("<failure"));
+    }
+
+    #[test]
+    fn test_junit_reports_failures() {
+        let mut check = Step::new(StepKind::Check, "balances", Duration::ZERO);
+        check.status = StepStatus::Failed;
+        check.error = Some("expected [1], got [2]".to_string());
+        let mut rejected = Step::new(StepKind::Mine, "block", Duration::ZERO);
+        rejected.status = StepStatus::Rejected;
+        rejected.error = Some("bad-txns".to_string());
+        let xml = report(
+            vec![check, rejected],
+            Some("assertion `left == right` failed".to_string()),
+        )
+        .to_junit();
+
+        assert!(xml.contains("failures=\"2\""));
+        assert!(xml.contains("<failure message=\"expected [1], got [2]\">"));
+        assert!(xml.contains("<system-out>rejected: bad-txns</system-out>"));
+        assert!(xml.contains("<failure message=\"assertion `left == right` failed\">"));
+    }
+
+    #[test]
+    fn test_escapes_xml() {
+        assert_eq!(
+            escape("<a href=\"x\">'&'</a>\n"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;&#10;"
+        );
+    }
+
+    #[test]
+    fn test_steps_are_only_recorded_while_started() {
+        record(Step::new(StepKind::Sync, "sync", Duration::ZERO));
+        assert!(finish(None).is_none());
+
+        start("test-scenario");
+        record(Step::new(StepKind::Sync, "sync", Duration::ZERO));
+        assert!(!check("balances", Instant::now(), &[1u64, 2], &[1, 3]));
+        let report = finish(None).unwrap();
+        assert_eq!(report.steps.len(), 2);
+        assert_eq!(report.status, StepStatus::Failed);
+        assert_eq!(report.steps[1].actual, Some(json!([1, 3])));
+    }
+}
@@ -15,6 +15,26 @@ diesel::table! {
         recipient_address -> Binary,
         spend_tx_id -> Nullable<Binary>,
         spend_action_index -> Integer,
+        mined_height -> Nullable<Integer>,
+        spend_height -> Nullable<Integer>,
+        locked_by_tx_id -> Nullable<Binary>,
+        lock_expiry_height -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    /// Outputs of wallet transactions recovered with an outgoing viewing key.
+    sent_notes (id) {
+        id -> Integer,
+        tx_id -> Binary,
+        action_index -> Integer,
+        sender_account -> Integer,
+        recipient_address -> Binary,
+        recipient_account -> Nullable<Integer>,
+        amount -> BigInt,
+        asset -> Binary,
+        memo -> Binary,
+        mined_height -> Integer,
     }
 }
 
@@ -23,15 +43,127 @@ diesel::table! {
     block_data (height) {
         height -> Integer,
         hash -> Text,
+        prev_hash -> Nullable<Text>,
+        merkle_root -> Nullable<Text>,
+        block_commitments -> Nullable<Text>,
+        time -> Nullable<BigInt>,
+        bits -> Nullable<BigInt>,
     }
 }
 
 diesel::table! {
-    /// Persisted wallet state: commitment tree, last synced block height/hash.
+    /// Persisted wallet state: current bridge of the commitment tree, last
+    /// synced block height/hash.
     wallet_state (id) {
         id -> Integer,
-        commitment_tree_json -> Text,
+        format_version -> Integer,
+        commitment_tree_json -> Nullable<Text>,
+        current_bridge -> Nullable<Binary>,
+        max_checkpoints -> Integer,
         last_block_height -> Integer,
         last_block_hash -> Text,
     }
 }
+
+diesel::table! {
+    /// Prior bridges of the persisted commitment tree, in order.
+    tree_bridges (idx) {
+        idx -> Integer,
+        digest -> Binary,
+        data -> Binary,
+    }
+}
+
+diesel::table! {
+    /// Checkpoints of the persisted commitment tree, keyed by checkpoint id.
+    tree_checkpoints (id) {
+        id -> Integer,
+        data -> Binary,
+    }
+}
+
+diesel::table! {
+    /// Marked positions of the persisted commitment tree and their bridge.
+    tree_marks (position) {
+        position -> BigInt,
+        bridge_idx -> Integer,
+    }
+}
+
+diesel::table! {
+    /// Transactions that touched the wallet, recorded at build time and when mined.
+    transactions (txid) {
+        txid -> Binary,
+        mined_height -> Nullable<Integer>,
+        block_hash -> Nullable<Text>,
+        expiry_height -> Integer,
+        fee -> Nullable<BigInt>,
+        memo_refs -> Text,
+        issuance -> Text,
+        burns -> Text,
+    }
+}
+
+diesel::table! {
+    /// Net value change of a recorded transaction per account and asset.
+    transaction_values (txid, account, asset) {
+        txid -> Binary,
+        account -> Integer,
+        asset -> Binary,
+        value_change -> BigInt,
+    }
+}
+
+diesel::table! {
+    /// Peaks of the local chain history tree, left to right.
+    history_tree_peaks (idx) {
+        idx -> Integer,
+        branch_id -> BigInt,
+        data -> Binary,
+    }
+}
+
+diesel::table! {
+    /// Transparent outputs paying the wallet's transparent addresses.
+    transparent_utxos (txid, output_index) {
+        txid -> Binary,
+        output_index -> Integer,
+        account -> Nullable<Integer>,
+        is_miner -> Bool,
+        amount -> BigInt,
+        is_coinbase -> Bool,
+        mined_height -> Integer,
+        spend_tx_id -> Nullable<Binary>,
+        spend_height -> Nullable<Integer>,
+        locked_by_tx_id -> Nullable<Binary>,
+        lock_expiry_height -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    /// Sapling notes received by wallet accounts.
+    sapling_notes (txid, output_index) {
+        txid -> Binary,
+        output_index -> Integer,
+        account -> Integer,
+        amount -> BigInt,
+        recipient -> Binary,
+        rseed -> Binary,
+        nullifier -> Binary,
+        memo -> Binary,
+        witness -> Binary,
+        mined_height -> Integer,
+        spend_tx_id -> Nullable<Binary>,
+        spend_height -> Nullable<Integer>,
+        locked_by_tx_id -> Nullable<Binary>,
+        lock_expiry_height -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    /// Frontier of the Sapling note commitment tree at the sync position.
+    sapling_tree_state (id) {
+        id -> Integer,
+        tree -> Binary,
+    }
+}